@@ -33,7 +33,7 @@ CREATE TABLE osm_rels (
     tags jsonb not null
 );
 
--- rel_members.write_record(&["rel_id", "ordinal", "role", "node_id", "way_id", "rel_id"])?;
+-- rel_members.write_record(&["rel_id", "ordinal", "role", "member_node_id", "member_way_id", "member_rel_id"])?;
 CREATE TABLE osm_rel_members (
     rel_id bigint not null,
     ordinal bigint not null,
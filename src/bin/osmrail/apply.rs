@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use osmrail::{
+    network::{Feature, Map},
+    osc::{Action, OscReader},
+    output::osm_ref,
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Builds the rail network from the input, then applies each of the
+/// OsmChange files in turn, reporting the stations, sections and routes that
+/// changed.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    changes: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct Change<'a> {
+    file: std::borrow::Cow<'a, str>,
+    action: Action,
+    feature: Feature,
+    id: String,
+    name: Option<&'a str>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+
+    let mut map = Map::from_reader(input.par_objs()?)?;
+
+    let mut out = opts.output();
+    for path in args.changes.iter() {
+        let changes = OscReader::open(path)?;
+        for it in changes {
+            let (action, obj) = it.with_context(|| format!("read {:?}", path))?;
+            if let Some(changed) = map.apply(action, obj) {
+                out.write(&Change {
+                    file: path.to_string_lossy(),
+                    action: changed.action,
+                    feature: changed.feature,
+                    id: osm_ref(changed.id),
+                    name: changed.name.as_deref(),
+                })?;
+            }
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use anyhow::Result;
+use im::Vector;
+use log::{debug, info, trace};
+use osmpbfreader::OsmId;
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    output::osm_ref,
+};
+use serde::Serialize;
+use smartstring::alias::String;
+use structopt::StructOpt;
+
+use crate::Options;
+
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Log how much memory the network takes up.
+    #[structopt(long)]
+    stats: bool,
+}
+
+/// Where the catchments of two stations meet, and the path between them.
+#[derive(Debug, Serialize)]
+struct Boundary<'a> {
+    from: &'a str,
+    to: &'a str,
+    path: Vec<std::string::String>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    if args.stats {
+        info!("Memory used by the network:\n{}", map.stats());
+    }
+
+    let crses = map.crses().map(String::from).collect::<BTreeSet<_>>();
+
+    let mut stack = VecDeque::<(String, Vector<OsmId>, Vertex)>::new();
+    // We might not need to keep the path here. Given that we only care the
+    // paths for vertexes on the "fringe", we _could_ maybe get away with only
+    // storing it in the fringe.
+    let mut seen = HashMap::<Vertex, (String, Vector<OsmId>)>::new();
+    let mut boundaries = BTreeMap::<(String, String), Vector<OsmId>>::new();
+
+    for crs in crses.iter().cloned() {
+        let idx = map.vertex_by_crs(&crs).expect(&crs);
+        let path = Vector::unit(map.node_id(idx).into());
+        seen.insert(idx, (crs.clone(), path.clone()));
+        stack.push_back((crs, path, idx));
+    }
+
+    while let Some((crs, path, idx)) = stack.pop_front() {
+        trace!(
+            "Visit:\t{:?}: {:?}; {:?}, {:?}; {:?}",
+            crs,
+            idx,
+            path,
+            map.node_id(idx),
+            map.obj_by_vertex(idx)
+        );
+        for (succ, via) in map.edges(idx) {
+            let succ_osm_id = map.node_id(succ);
+            match seen.get(&succ) {
+                Some((succ_crs, succ_path)) if crs != *succ_crs => {
+                    debug!("Boundary:\t{}[{:?}]--{}[{:?}]", crs, idx, succ_crs, succ);
+                    let key;
+                    let full_path;
+                    if &crs <= succ_crs {
+                        key = (crs.clone(), succ_crs.clone());
+                        full_path = path
+                            .iter()
+                            .cloned()
+                            .chain(succ_path.iter().rev().cloned())
+                            .collect();
+                    } else {
+                        key = (succ_crs.clone(), crs.clone());
+                        full_path = succ_path
+                            .iter()
+                            .cloned()
+                            .chain(path.iter().rev().cloned())
+                            .collect();
+                    };
+                    boundaries.entry(key).or_insert(full_path);
+                }
+                // Seen, but uninteresting.
+                Some((succ_crs, _)) => trace!("Seen:\t{}[{:?}]", succ_crs, succ),
+                None => {
+                    trace!("New:\t{}[{:?}]", crs, succ);
+                    let mut path = path.clone();
+
+                    path.push_back(via);
+                    path.push_back(succ_osm_id.into());
+
+                    seen.insert(succ, (crs.clone(), path.clone()));
+                    stack.push_back((crs.clone(), path, succ));
+                }
+            }
+        }
+    }
+    let mut out = opts.output();
+    for ((a, b), path) in boundaries.iter() {
+        out.write(&Boundary {
+            from: a,
+            to: b,
+            path: path.iter().cloned().map(osm_ref).collect(),
+        })?;
+    }
+    out.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    contraction::Hierarchy,
+};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{route, Options};
+
+/// Looks up distances along the track between pairs of stations, using a
+/// contraction hierarchy. Building the hierarchy takes a while, but then
+/// each lookup only takes microseconds, so use `--cache` to keep it for
+/// next time.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The station to go from: a CRS code (`HYS`) or node id (`n123`).
+    #[structopt(requires = "to", required_unless = "pairs")]
+    from: Option<String>,
+    /// The station to go to.
+    to: Option<String>,
+    /// A CSV file of pairs of stations, with `from` and `to` columns.
+    #[structopt(long, conflicts_with = "from")]
+    pairs: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pair {
+    from: String,
+    to: String,
+}
+
+/// How far it is (in metres) along the track from `from` to `to`, as they
+/// were given. There's no `distance` when there's no way there.
+#[derive(Debug, Serialize)]
+struct Distance<'a> {
+    from: &'a str,
+    to: &'a str,
+    distance: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let pairs = match (&args.pairs, args.from, args.to) {
+        (Some(path), _, _) => {
+            let mut rdr =
+                csv::Reader::from_path(path).with_context(|| format!("open {:?}", path))?;
+            rdr.deserialize()
+                .collect::<Result<Vec<Pair>, _>>()
+                .with_context(|| format!("read {:?}", path))?
+        }
+        (None, Some(from), Some(to)) => vec![Pair { from, to }],
+        _ => bail!("Need stations to go from and to, or --pairs"),
+    };
+
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    let hierarchy = opts
+        .cache()
+        .get_or_build("hierarchy", &input, &(), || Ok(Hierarchy::new(&map)))?;
+
+    // The same stations tend to come up over and over again.
+    let mut stations = HashMap::<&str, Vertex>::new();
+    let mut station = |s| -> Result<Vertex> {
+        if let Some(&v) = stations.get(s) {
+            return Ok(v);
+        }
+        let v = route::stop(&map, s)?;
+        if !hierarchy.contains(v) {
+            bail!("{} isn't a station or junction", s);
+        }
+        stations.insert(s, v);
+        Ok(v)
+    };
+
+    let mut out = opts.output();
+    let start = Instant::now();
+    for pair in &pairs {
+        let (from, to) = (station(&pair.from)?, station(&pair.to)?);
+        out.write(&Distance {
+            from: &pair.from,
+            to: &pair.to,
+            distance: hierarchy.distance(from, to).map(f64::round),
+        })?;
+    }
+    out.finish()?;
+    info!(
+        "Looked up {} distances in {:?}",
+        pairs.len(),
+        start.elapsed()
+    );
+
+    Ok(())
+}
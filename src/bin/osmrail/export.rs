@@ -0,0 +1,628 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use arrow_array::{
+    builder::{ArrayBuilder, Float64Builder, Int64Builder, MapBuilder, StringBuilder},
+    Array, ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use osmpbfreader::{Node, OsmId, OsmObj, Relation, Tags, Way};
+use osmrail::filter::TagFilter;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Exports everything, or just the objects matching `--filter` if given.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    dst_dir: PathBuf,
+    /// Table format, one of `csv` or `parquet`.
+    #[structopt(long, default_value = "csv")]
+    table_format: Format,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+/// Where the extracted rows end up. Each implementation writes the same five
+/// tables (nodes, ways, way nodes, relations, relation members).
+trait Tables {
+    fn add_node(&mut self, node: Node) -> Result<()>;
+    fn add_way(&mut self, way: Way) -> Result<()>;
+    fn add_rel(&mut self, rel: Relation) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct ExtractTransform {
+    tables: Box<dyn Tables>,
+}
+
+#[derive(Debug)]
+struct CsvTables {
+    nodes: csv::Writer<File>,
+    ways: csv::Writer<File>,
+    way_nodes: csv::Writer<File>,
+    rels: csv::Writer<File>,
+    rel_members: csv::Writer<File>,
+}
+
+// Rows are buffered in the builders until we have this many, and then
+// written out as a single record batch.
+const BATCH_ROWS: usize = 64 * 1024;
+
+type TagsBuilder = MapBuilder<StringBuilder, StringBuilder>;
+
+struct ParquetTables {
+    nodes: ParquetTable<NodeRows>,
+    ways: ParquetTable<TaggedRows>,
+    way_nodes: ParquetTable<WayNodeRows>,
+    rels: ParquetTable<TaggedRows>,
+    rel_members: ParquetTable<RelMemberRows>,
+}
+
+struct ParquetTable<R> {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    rows: R,
+}
+
+trait Rows {
+    fn len(&self) -> usize;
+    fn finish(&mut self) -> Vec<ArrayRef>;
+}
+
+struct NodeRows {
+    ids: Int64Builder,
+    lats: Float64Builder,
+    lons: Float64Builder,
+    tags: TagsBuilder,
+}
+
+struct TaggedRows {
+    ids: Int64Builder,
+    tags: TagsBuilder,
+}
+
+#[derive(Default)]
+struct WayNodeRows {
+    way_ids: Int64Builder,
+    ordinals: Int64Builder,
+    node_ids: Int64Builder,
+}
+
+#[derive(Default)]
+struct RelMemberRows {
+    rel_ids: Int64Builder,
+    ordinals: Int64Builder,
+    roles: StringBuilder,
+    member_node_ids: Int64Builder,
+    member_way_ids: Int64Builder,
+    member_rel_ids: Int64Builder,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+
+    let mut writer =
+        ExtractTransform::new(&args.dst_dir, args.table_format).context("create extractor")?;
+
+    let objs = input.par_objs()?.filter(|it| match it {
+        Ok(obj) => opts.filters.is_empty() || TagFilter::any_match(&opts.filters, obj.tags()),
+        Err(_) => true,
+    });
+    writer.extract(objs).context("run extract")?;
+
+    writer.finish()?;
+
+    Ok(())
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            other => bail!("Unknown table format: {:?}", other),
+        }
+    }
+}
+
+impl ExtractTransform {
+    fn new(dir: &Path, format: Format) -> Result<Self> {
+        let tables: Box<dyn Tables> = match format {
+            Format::Csv => Box::new(CsvTables::new(dir)?),
+            Format::Parquet => Box::new(ParquetTables::new(dir)?),
+        };
+        Ok(Self { tables })
+    }
+
+    fn extract(&mut self, objs: impl Iterator<Item = Result<OsmObj>>) -> Result<()> {
+        for it in objs {
+            let it = it.context("Read item")?;
+            match it {
+                OsmObj::Node(n) => self.tables.add_node(n)?,
+                OsmObj::Way(w) => self.tables.add_way(w)?,
+                OsmObj::Relation(r) => self.tables.add_rel(r)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        self.tables.finish()
+    }
+}
+
+impl CsvTables {
+    fn new(dir: &Path) -> Result<Self> {
+        let mut nodes = csv::Writer::from_path(dir.join("nodes.csv"))?;
+        nodes.write_record(["node_id", "lat", "lon", "tags"])?;
+
+        let mut ways = csv::Writer::from_path(dir.join("ways.csv"))?;
+        ways.write_record(["way_id", "tags"])?;
+
+        let mut way_nodes = csv::Writer::from_path(dir.join("way-nodes.csv"))?;
+        way_nodes.write_record(["way_id", "ordinal", "node_id"])?;
+
+        let mut rels = csv::Writer::from_path(dir.join("relations.csv"))?;
+        rels.write_record(["rel_id", "tags"])?;
+
+        let mut rel_members = csv::Writer::from_path(dir.join("relation-members.csv"))?;
+        rel_members.write_record([
+            "rel_id",
+            "ordinal",
+            "role",
+            "member_node_id",
+            "member_way_id",
+            "member_rel_id",
+        ])?;
+
+        let me = Self {
+            nodes,
+            ways,
+            way_nodes,
+            rels,
+            rel_members,
+        };
+        Ok(me)
+    }
+}
+
+impl Tables for CsvTables {
+    fn add_node(&mut self, node: Node) -> Result<()> {
+        self.nodes.write_record([
+            &format!("{}", node.id.0) as &dyn AsRef<[u8]>,
+            &format!("{}", node.lat()),
+            &format!("{}", node.lon()),
+            &serde_json::to_vec(&node.tags)?,
+        ])?;
+        Ok(())
+    }
+
+    fn add_way(&mut self, way: Way) -> Result<()> {
+        self.ways.write_record([
+            &format!("{}", way.id.0) as &dyn AsRef<[u8]>,
+            &serde_json::to_vec(&way.tags)?,
+        ])?;
+
+        for (i, node) in way.nodes.iter().enumerate() {
+            self.way_nodes.write_record([
+                &format!("{}", way.id.0),
+                &format!("{}", i),
+                &format!("{}", node.0),
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    fn add_rel(&mut self, rel: Relation) -> Result<()> {
+        self.rels
+            .write_record([&format!("{}", rel.id.0), &serde_json::to_string(&rel.tags)?])?;
+
+        for (i, member) in rel.refs.iter().enumerate() {
+            match member.member {
+                OsmId::Node(node_id) => {
+                    self.rel_members.write_record([
+                        &format!("{}", rel.id.0) as &dyn AsRef<[u8]>,
+                        &format!("{}", i),
+                        &member.role,
+                        &format!("{}", node_id.0),
+                        &"",
+                        &"",
+                    ])?;
+                }
+                OsmId::Way(way_id) => {
+                    self.rel_members.write_record([
+                        &format!("{}", rel.id.0) as &dyn AsRef<[u8]>,
+                        &format!("{}", i),
+                        &member.role,
+                        &"",
+                        &format!("{}", way_id.0),
+                        &"",
+                    ])?;
+                }
+                OsmId::Relation(rel_id) => {
+                    self.rel_members.write_record([
+                        &format!("{}", rel.id.0) as &dyn AsRef<[u8]>,
+                        &format!("{}", i),
+                        &member.role,
+                        &"",
+                        &"",
+                        &format!("{}", rel_id.0),
+                    ])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let Self {
+            mut nodes,
+            mut ways,
+            mut way_nodes,
+            mut rels,
+            mut rel_members,
+        } = *self;
+        nodes.flush()?;
+        ways.flush()?;
+        way_nodes.flush()?;
+        rels.flush()?;
+        rel_members.flush()?;
+
+        Ok(())
+    }
+}
+
+impl ParquetTables {
+    // The column names match those used by `import-csvs.sql`, so the same
+    // queries work against either export.
+    fn new(dir: &Path) -> Result<Self> {
+        let id = |name| Field::new(name, DataType::Int64, false);
+        let member_id = |name| Field::new(name, DataType::Int64, true);
+        let tags = || Field::new("tags", tags_type(), false);
+
+        let nodes = ParquetTable::create(
+            &dir.join("nodes.parquet"),
+            vec![
+                id("node_id"),
+                Field::new("lat", DataType::Float64, false),
+                Field::new("lon", DataType::Float64, false),
+                tags(),
+            ],
+        )?;
+        let ways = ParquetTable::create(&dir.join("ways.parquet"), vec![id("way_id"), tags()])?;
+        let way_nodes = ParquetTable::create(
+            &dir.join("way-nodes.parquet"),
+            vec![id("way_id"), id("ordinal"), id("node_id")],
+        )?;
+        let rels =
+            ParquetTable::create(&dir.join("relations.parquet"), vec![id("rel_id"), tags()])?;
+        let rel_members = ParquetTable::create(
+            &dir.join("relation-members.parquet"),
+            vec![
+                id("rel_id"),
+                id("ordinal"),
+                Field::new("role", DataType::Utf8, false),
+                member_id("member_node_id"),
+                member_id("member_way_id"),
+                member_id("member_rel_id"),
+            ],
+        )?;
+
+        Ok(Self {
+            nodes,
+            ways,
+            way_nodes,
+            rels,
+            rel_members,
+        })
+    }
+}
+
+impl Tables for ParquetTables {
+    fn add_node(&mut self, node: Node) -> Result<()> {
+        let rows = &mut self.nodes.rows;
+        rows.ids.append_value(node.id.0);
+        rows.lats.append_value(node.lat());
+        rows.lons.append_value(node.lon());
+        append_tags(&mut rows.tags, &node.tags)?;
+        self.nodes.row_added()
+    }
+
+    fn add_way(&mut self, way: Way) -> Result<()> {
+        let rows = &mut self.ways.rows;
+        rows.ids.append_value(way.id.0);
+        append_tags(&mut rows.tags, &way.tags)?;
+        self.ways.row_added()?;
+
+        for (i, node) in way.nodes.iter().enumerate() {
+            let rows = &mut self.way_nodes.rows;
+            rows.way_ids.append_value(way.id.0);
+            rows.ordinals.append_value(i as i64);
+            rows.node_ids.append_value(node.0);
+            self.way_nodes.row_added()?;
+        }
+
+        Ok(())
+    }
+
+    fn add_rel(&mut self, rel: Relation) -> Result<()> {
+        let rows = &mut self.rels.rows;
+        rows.ids.append_value(rel.id.0);
+        append_tags(&mut rows.tags, &rel.tags)?;
+        self.rels.row_added()?;
+
+        for (i, member) in rel.refs.iter().enumerate() {
+            let rows = &mut self.rel_members.rows;
+            rows.rel_ids.append_value(rel.id.0);
+            rows.ordinals.append_value(i as i64);
+            rows.roles.append_value(&member.role);
+            rows.member_node_ids
+                .append_option(member.member.node().map(|id| id.0));
+            rows.member_way_ids
+                .append_option(member.member.way().map(|id| id.0));
+            rows.member_rel_ids
+                .append_option(member.member.relation().map(|id| id.0));
+            self.rel_members.row_added()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let Self {
+            nodes,
+            ways,
+            way_nodes,
+            rels,
+            rel_members,
+        } = *self;
+        nodes.close().context("nodes")?;
+        ways.close().context("ways")?;
+        way_nodes.close().context("way nodes")?;
+        rels.close().context("relations")?;
+        rel_members.close().context("relation members")?;
+
+        Ok(())
+    }
+}
+
+impl<R: Rows + Default> ParquetTable<R> {
+    fn create(path: &Path, fields: Vec<Field>) -> Result<Self> {
+        let schema = Arc::new(Schema::new(fields));
+        let file = File::create(path).with_context(|| format!("create {:?}", path))?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+        Ok(Self {
+            writer,
+            schema,
+            rows: R::default(),
+        })
+    }
+
+    fn row_added(&mut self) -> Result<()> {
+        if self.rows.len() >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.rows.len() == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), self.rows.finish())?;
+        self.writer.write(&batch)?;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<()> {
+        self.write_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+impl Default for NodeRows {
+    fn default() -> Self {
+        Self {
+            ids: Int64Builder::new(),
+            lats: Float64Builder::new(),
+            lons: Float64Builder::new(),
+            tags: tags_builder(),
+        }
+    }
+}
+
+impl Rows for NodeRows {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.ids.finish()),
+            Arc::new(self.lats.finish()),
+            Arc::new(self.lons.finish()),
+            Arc::new(self.tags.finish()),
+        ]
+    }
+}
+
+impl Default for TaggedRows {
+    fn default() -> Self {
+        Self {
+            ids: Int64Builder::new(),
+            tags: tags_builder(),
+        }
+    }
+}
+
+impl Rows for TaggedRows {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![Arc::new(self.ids.finish()), Arc::new(self.tags.finish())]
+    }
+}
+
+impl Rows for WayNodeRows {
+    fn len(&self) -> usize {
+        self.way_ids.len()
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.way_ids.finish()),
+            Arc::new(self.ordinals.finish()),
+            Arc::new(self.node_ids.finish()),
+        ]
+    }
+}
+
+impl Rows for RelMemberRows {
+    fn len(&self) -> usize {
+        self.rel_ids.len()
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.rel_ids.finish()),
+            Arc::new(self.ordinals.finish()),
+            Arc::new(self.roles.finish()),
+            Arc::new(self.member_node_ids.finish()),
+            Arc::new(self.member_way_ids.finish()),
+            Arc::new(self.member_rel_ids.finish()),
+        ]
+    }
+}
+
+// We let the builder tell us what a map of strings looks like, so that the
+// schema always agrees with the arrays we hand to the writer.
+fn tags_type() -> DataType {
+    tags_builder().finish().data_type().clone()
+}
+
+fn tags_builder() -> TagsBuilder {
+    MapBuilder::new(None, StringBuilder::new(), StringBuilder::new())
+}
+
+fn append_tags(builder: &mut TagsBuilder, tags: &Tags) -> Result<()> {
+    for (k, v) in tags.iter() {
+        builder.keys().append_value(k);
+        builder.values().append_value(v);
+    }
+    builder.append(true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type},
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::fixtures;
+
+    fn read(path: &Path) -> RecordBatch {
+        let file = File::open(path).unwrap();
+        let mut batches = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = batches.next().unwrap().unwrap();
+        assert!(batches.next().is_none());
+        batch
+    }
+
+    #[test]
+    fn writes_parquet_tables() {
+        let dir = fixtures::temp_path("export-parquet");
+        fs::create_dir_all(&dir).unwrap();
+        let mut writer = ExtractTransform::new(&dir, Format::Parquet).unwrap();
+        writer
+            .extract(fixtures::objs().into_iter().map(Result::Ok))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let nodes = read(&dir.join("nodes.parquet"));
+        assert_eq!(nodes.num_rows(), 5);
+        let schema = nodes.schema();
+        assert_eq!(schema.field(0).name(), "node_id");
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Float64);
+        assert!(matches!(schema.field(3).data_type(), DataType::Map(..)));
+        assert_eq!(nodes.column(0).as_primitive::<Int64Type>().value(0), 1);
+        let lat = nodes.column(1).as_primitive::<Float64Type>().value(0);
+        assert!((lat - 51.5007292).abs() < 1e-9);
+        let tags = nodes.column(3).as_map();
+        let keys = tags.value(0);
+        let keys = keys.column(0).as_string::<i32>();
+        let values = tags.value(0);
+        let values = values.column(1).as_string::<i32>();
+        let crs = (0..keys.len())
+            .find(|&i| keys.value(i) == "ref:crs")
+            .unwrap();
+        assert_eq!(values.value(crs), "FAC");
+        assert!(tags.value(1).is_empty());
+
+        let way_nodes = read(&dir.join("way-nodes.parquet"));
+        let ordinals = way_nodes.column(1).as_primitive::<Int64Type>();
+        assert_eq!(ordinals.values().to_vec(), vec![0, 1, 2, 3]);
+
+        let members = read(&dir.join("relation-members.parquet"));
+        let schema = members.schema();
+        let names = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "rel_id",
+                "ordinal",
+                "role",
+                "member_node_id",
+                "member_way_id",
+                "member_rel_id"
+            ]
+        );
+        let column = |i: usize| {
+            let ids = members.column(i).as_primitive::<Int64Type>();
+            (0..ids.len())
+                .map(|r| Some(ids.value(r)).filter(|_| ids.is_valid(r)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(column(3), vec![Some(1), Some(5), None, None]);
+        assert_eq!(column(4), vec![None, None, Some(10), None]);
+        assert_eq!(column(5), vec![None, None, None, Some(21)]);
+        assert_eq!(members.column(2).as_string::<i32>().value(3), "sub area");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::{OsmId, OsmObj};
+use osmrail::{filter::TagFilter, input::Format, pbf::PbfWriter, xml::XmlWriter};
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Writes the objects matching `--filter` (by default, the railway related
+/// ones), along with everything they depend on, to a new `.osm.pbf` or
+/// `.osm` file.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Output file; the format is picked from the extension.
+    dst: PathBuf,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    // Before we read anything, or touch the output file.
+    let format = format(&args.dst)?;
+    let input = opts.input()?;
+
+    let objs = input
+        .get_objs_and_deps(|obj| TagFilter::any_match(&opts.filters, obj.tags()))
+        .context("select objects")?;
+    log::info!("Selected {} objects", objs.len());
+
+    write(&args.dst, format, objs).context("write extract")?;
+
+    Ok(())
+}
+
+fn format(dst: &Path) -> Result<Format> {
+    let name = dst.to_string_lossy();
+    if name.ends_with(".pbf") {
+        Ok(Format::Pbf)
+    } else if name.ends_with(".osm") {
+        Ok(Format::Xml)
+    } else {
+        bail!("Don't know what format to write {:?} in", dst);
+    }
+}
+
+fn write(dst: &Path, format: Format, objs: BTreeMap<OsmId, OsmObj>) -> Result<()> {
+    let out = BufWriter::new(File::create(dst).with_context(|| format!("create {:?}", dst))?);
+    match format {
+        Format::Pbf => {
+            let mut w = PbfWriter::new(out)?;
+            for obj in objs.into_values() {
+                w.write(obj)?;
+            }
+            w.finish()?;
+        }
+        Format::Xml => {
+            let mut w = XmlWriter::new(out)?;
+            for obj in objs.values() {
+                w.write(obj)?;
+            }
+            w.finish()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_format_from_the_name() {
+        assert_eq!(format(Path::new("out.osm.pbf")).unwrap(), Format::Pbf);
+        assert_eq!(format(Path::new("out.osm")).unwrap(), Format::Xml);
+        assert!(format(Path::new("out.xml")).is_err());
+        assert!(format(Path::new("out.osm.gz")).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use osmpbfreader::OsmId;
+use osmrail::{
+    compact::CompactMap,
+    output::osm_ref,
+    routing::{covered, length, ShortestPaths, Speeds},
+};
+use serde::Serialize;
+use serde_json::json;
+use structopt::StructOpt;
+
+use crate::{route, Options};
+
+/// Finds the stations within a distance or time along the track of any of
+/// some origins.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The stations to start from: CRS codes (`HYS`) or node ids (`n123`).
+    #[structopt(required = true)]
+    origins: Vec<String>,
+    /// How far to go along the track, in kilometres.
+    #[structopt(long, required_unless = "minutes", conflicts_with = "minutes")]
+    km: Option<f64>,
+    /// How long to go along the track for, at the line speed.
+    #[structopt(long)]
+    minutes: Option<f64>,
+    /// The speed (in km/h) to assume on track without a `maxspeed`.
+    #[structopt(long, default_value = "80")]
+    default_speed: f64,
+    /// Write the track we can get to as GeoJSON to this file, one
+    /// `MultiLineString` for each way.
+    #[structopt(long)]
+    track: Option<PathBuf>,
+}
+
+/// A station we can get to from `origin` (the nearest one). `distance` is
+/// in metres, and `minutes` is how long it takes at the line speed, both
+/// along the best route (the shortest, or the quickest with `--minutes`).
+#[derive(Debug, Serialize)]
+struct Station<'a> {
+    station: String,
+    crs: Option<String>,
+    name: Option<String>,
+    origin: &'a str,
+    distance: f64,
+    minutes: f64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    let origins = args
+        .origins
+        .iter()
+        .map(|s| route::stop(&map, s))
+        .collect::<Result<Vec<_>>>()?;
+    let speeds = Speeds::new(&map, args.default_speed);
+
+    let (limit, by_time) = match (args.km, args.minutes) {
+        (Some(km), None) => (km * 1000.0, false),
+        (None, Some(minutes)) => (minutes * 60.0, true),
+        _ => bail!("Need one of --km or --minutes"),
+    };
+    let mut paths = ShortestPaths::new(&map, origins.iter().map(|&v| (v, 0.0)));
+    if by_time {
+        paths = paths.by_time(&speeds);
+    }
+    let mut reached = Vec::new();
+    for (v, cost) in &mut paths {
+        if cost > limit {
+            break;
+        }
+        reached.push((v, cost));
+    }
+    info!("Reached {} vertices", reached.len());
+
+    let mut out = opts.output();
+    for &(v, _) in &reached {
+        if !map.is_station(v) {
+            continue;
+        }
+        let steps = paths.path_to(v).expect("reached");
+        let origin = origins
+            .iter()
+            .position(|&o| o == steps[0].0)
+            .expect("origin");
+        let distance = steps
+            .windows(2)
+            .fold(0.0, |total, w| total + length(&map, w[0].0, w[1].0));
+        let seconds = steps
+            .windows(2)
+            .filter_map(|w| Some(speeds.seconds(&map, w[0].0, w[1].0, w[1].1?)))
+            .fold(0.0, |total, s| total + s);
+        let obj = map.obj_by_vertex(v).expect("station");
+        let coord = map.coord(v);
+        out.write(&Station {
+            station: osm_ref(obj.id()),
+            crs: obj.tags().get("ref:crs").map(|s| s.to_string()),
+            name: obj.tags().get("name").map(|s| s.to_string()),
+            origin: &args.origins[origin],
+            distance: distance.round(),
+            minutes: (seconds / 6.0).round() / 10.0,
+            lat: coord.map(|c| c.lat()),
+            lon: coord.map(|c| c.lon()),
+        })?;
+    }
+    out.finish()?;
+
+    if let Some(path) = &args.track {
+        let speeds = if by_time { Some(&speeds) } else { None };
+        let track = covered(&map, speeds, &reached, limit);
+        write_track(&map, path, &track)?;
+    }
+
+    Ok(())
+}
+
+fn write_track(
+    map: &CompactMap,
+    path: &Path,
+    track: &BTreeMap<OsmId, Vec<Vec<[f64; 2]>>>,
+) -> Result<()> {
+    let features = track
+        .iter()
+        .map(|(&via, lines)| {
+            let name = map
+                .obj(via)
+                .and_then(|obj| obj.tags().get("name").map(|s| s.to_string()));
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "MultiLineString", "coordinates": lines},
+                "properties": {"id": osm_ref(via), "name": name},
+            })
+        })
+        .collect::<Vec<_>>();
+    let f = File::create(path).with_context(|| format!("create {:?}", path))?;
+    serde_json::to_writer(
+        BufWriter::new(f),
+        &json!({"type": "FeatureCollection", "features": features}),
+    )
+    .with_context(|| format!("write {:?}", path))?;
+    info!("Wrote {} ways of track to {:?}", track.len(), path);
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Result;
+use geo::{HaversineDistance, Point};
+use osmpbfreader::Tags;
+use osmrail::{
+    filter::TagFilter,
+    geometry::{split, way_lines, Regions, WayCoords},
+    routing::parse_speed,
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Adds up the length of the ways matching `--filter` (or every way with a
+/// `railway` tag), broken down by tag.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Keys to break the totals down by. `maxspeed` is grouped into bands
+    /// (see `--maxspeed-band`).
+    #[structopt(
+        long = "by",
+        number_of_values = 1,
+        use_delimiter = true,
+        default_value = "railway,usage,electrified,gauge,maxspeed,operator"
+    )]
+    keys: Vec<String>,
+    /// How wide each `maxspeed` band is, in km/h.
+    #[structopt(long, default_value = "40")]
+    maxspeed_band: u32,
+    /// Also break the totals down by the regions in this GeoJSON file. Each
+    /// bit of track counts towards the first region it's in.
+    #[structopt(long)]
+    regions: Option<PathBuf>,
+    /// The property of each region to name it by.
+    #[structopt(long, default_value = "name")]
+    region_name: String,
+}
+
+/// How much track there is with `key=value` (in `region`, if we were given
+/// any). `value` is empty for track without the tag.
+#[derive(Debug, Serialize)]
+struct Length<'a> {
+    region: Option<&'a str>,
+    key: &'a str,
+    value: Option<&'a str>,
+    km: f64,
+    ways: u64,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let regions = match &args.regions {
+        Some(path) => Regions::from_geojson(path, Some(&args.region_name))?,
+        None => Regions::default(),
+    };
+
+    let lines = way_lines(&input, |way| {
+        if opts.filters.is_empty() {
+            way.tags.contains_key("railway")
+        } else {
+            TagFilter::any_match(&opts.filters, &way.tags)
+        }
+    })?;
+
+    let totals = totals(&lines, &args, &regions);
+
+    let mut out = opts.output();
+    for ((region, i, value), (metres, ways)) in &totals {
+        out.write(&Length {
+            region: *region,
+            key: &args.keys[*i],
+            value: value.as_deref(),
+            km: (metres / 10.0).round() / 100.0,
+            ways: *ways,
+        })?;
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// The length in metres, and the number of ways, by region, key (as an
+// index into `args.keys`) and value.
+type Totals<'a> = BTreeMap<(Option<&'a str>, usize, Option<String>), (f64, u64)>;
+
+fn totals<'a>(lines: &[WayCoords], args: &Args, regions: &'a Regions) -> Totals<'a> {
+    let mut totals = Totals::new();
+    for (way, coords) in lines {
+        let mut by_region = BTreeMap::<Option<&str>, f64>::new();
+        for seg in split(coords).0.iter().flat_map(|line| line.lines()) {
+            let (a, b) = (Point::from(seg.start), Point::from(seg.end));
+            let region = regions.find(Point::new((a.x() + b.x()) / 2.0, (a.y() + b.y()) / 2.0));
+            *by_region.entry(region).or_default() += a.haversine_distance(&b);
+        }
+        for (region, metres) in by_region {
+            for (i, key) in args.keys.iter().enumerate() {
+                let value = value(&way.tags, key, args.maxspeed_band);
+                let total = totals.entry((region, i, value)).or_default();
+                total.0 += metres;
+                total.1 += 1;
+            }
+        }
+    }
+    totals
+}
+
+// Speeds are grouped into bands, eg: `80-120` for `maxspeed=100`. Anything
+// we can't read as a speed (eg: `signals`) is left as it is.
+fn value(tags: &Tags, key: &str, band: u32) -> Option<String> {
+    let value = tags.get(key)?;
+    if key != "maxspeed" || band == 0 {
+        return Some(value.to_string());
+    }
+    match parse_speed(value) {
+        Some(kmh) => {
+            let lower = (kmh / f64::from(band)).floor() as u32 * band;
+            Some(format!("{}-{}", lower, lower + band))
+        }
+        None => Some(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use geo::Coord;
+    use osmpbfreader::{NodeId, Way, WayId};
+
+    use super::*;
+    use crate::fixtures;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    #[test]
+    fn bands_maxspeeds() {
+        let band = |maxspeed: &str, band| value(&tags(&[("maxspeed", maxspeed)]), "maxspeed", band);
+        assert_eq!(band("100", 40).as_deref(), Some("80-120"));
+        assert_eq!(band("120", 40).as_deref(), Some("120-160"));
+        assert_eq!(band("60 mph", 40).as_deref(), Some("80-120"));
+        assert_eq!(band("50 km/h", 20).as_deref(), Some("40-60"));
+        assert_eq!(band("signals", 40).as_deref(), Some("signals"));
+        assert_eq!(band("100", 0).as_deref(), Some("100"));
+
+        let tags = tags(&[("gauge", "1435")]);
+        assert_eq!(value(&tags, "gauge", 40).as_deref(), Some("1435"));
+        assert_eq!(value(&tags, "maxspeed", 40), None);
+    }
+
+    #[test]
+    fn breaks_the_totals_down_by_region() {
+        // Two squares either side of the meridian.
+        let square = |name: &str, west: f64| {
+            serde_json::json!({
+                "type": "Feature",
+                "properties": {"name": name},
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [west, 0.0], [west + 1.0, 0.0], [west + 1.0, 1.0],
+                        [west, 1.0], [west, 0.0],
+                    ]],
+                },
+            })
+        };
+        let path = fixtures::temp_path("length-regions.geojson");
+        let geojson = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [square("west", -1.0), square("east", 0.0)],
+        });
+        fs::write(&path, geojson.to_string()).unwrap();
+        let regions = Regions::from_geojson(&path, Some("name")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let way = |id, nodes: usize, t: &[(&str, &str)]| Way {
+            id: WayId(id),
+            tags: tags(t),
+            nodes: (0..nodes as i64).map(NodeId).collect(),
+        };
+        let at = |lon: f64| Some(Coord { x: lon, y: 0.5 });
+        let lines = vec![
+            // Half in each region.
+            (
+                way(1, 3, &[("railway", "rail"), ("maxspeed", "100")]),
+                vec![at(-0.2), at(0.0), at(0.2)],
+            ),
+            // All in the east, with the gap in the middle left out.
+            (
+                way(2, 4, &[("railway", "rail")]),
+                vec![at(0.2), at(0.4), None, at(0.8)],
+            ),
+            // Outside of both.
+            (way(3, 2, &[("railway", "rail")]), vec![at(1.2), at(1.4)]),
+        ];
+        let args = Args::from_iter(&["length", "--by", "railway,maxspeed"]);
+
+        let km = |lon: f64| {
+            let metres = Point::new(0.0, 0.5).haversine_distance(&Point::new(lon, 0.5));
+            (metres / 10.0).round() / 100.0
+        };
+        let totals = totals(&lines, &args, &regions)
+            .into_iter()
+            .map(|((region, i, value), (metres, ways))| {
+                let key = args.keys[i].as_str();
+                (
+                    (region, key, value),
+                    ((metres / 10.0).round() / 100.0, ways),
+                )
+            })
+            .collect::<Vec<_>>();
+        let rail = Some("rail".to_string());
+        let band = Some("80-120".to_string());
+        assert_eq!(
+            totals,
+            vec![
+                ((None, "railway", rail.clone()), (km(0.2), 1)),
+                ((None, "maxspeed", None), (km(0.2), 1)),
+                ((Some("east"), "railway", rail.clone()), (km(0.4), 2)),
+                ((Some("east"), "maxspeed", None), (km(0.2), 1)),
+                ((Some("east"), "maxspeed", band.clone()), (km(0.2), 1)),
+                ((Some("west"), "railway", rail), (km(0.2), 1)),
+                ((Some("west"), "maxspeed", band), (km(0.2), 1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use osmrail::{
+    filter::{BBox, TagFilter},
+    lint::{locate, Linter, Rule, GREAT_BRITAIN},
+    output::osm_ref,
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Checks the railway objects (or those matching `--filter`) for common
+/// tagging mistakes.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Only run these rules: `station-without-crs`, `duplicate-crs`,
+    /// `stop-area-without-station`, `single-node-way`, `dead-end`,
+    /// `electrified-voltage` or `unknown-railway`. Runs all of them by
+    /// default.
+    #[structopt(long = "rule", number_of_values = 1, use_delimiter = true)]
+    rules: Vec<Rule>,
+    /// Where stations should have CRS codes, as
+    /// `min_lon,min_lat,max_lon,max_lat`. Defaults to Great Britain.
+    #[structopt(long)]
+    crs_area: Option<BBox>,
+}
+
+/// Something that needs fixing, and where it is.
+#[derive(Debug, Serialize)]
+struct Problem {
+    rule: Rule,
+    id: String,
+    message: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let rules = if args.rules.is_empty() {
+        &Rule::ALL[..]
+    } else {
+        &args.rules[..]
+    };
+
+    let mut linter = Linter::new(rules, args.crs_area.unwrap_or(GREAT_BRITAIN));
+    for it in input.par_objs()? {
+        let it = it.context("Read item")?;
+        if TagFilter::any_match(&opts.filters, it.tags()) {
+            linter.check(&it);
+        } else {
+            linter.note(&it);
+        }
+    }
+    let findings = linter.finish();
+    let coords = locate(&findings, input.par_objs()?)?;
+
+    let mut out = opts.output();
+    for finding in findings {
+        let coord = finding.at.and_then(|n| coords.get(&n));
+        out.write(&Problem {
+            rule: finding.rule,
+            id: osm_ref(finding.id),
+            message: finding.message,
+            lat: coord.map(|c| c.0),
+            lon: coord.map(|c| c.1),
+        })?;
+    }
+    out.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::{
+    io::{BufWriter, Stdout},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use osmrail::{
+    cache::Cache,
+    filter::{BBox, TagFilter},
+    input::Input,
+    output::{self, Output},
+};
+use structopt::StructOpt;
+
+mod apply;
+mod catchments;
+mod distance;
+mod export;
+mod extract;
+// The library's test fixtures; not everything in there is used here.
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../../fixtures.rs"]
+mod fixtures;
+mod isochrone;
+mod length;
+mod lint;
+mod map_match;
+mod matrix;
+mod mileage;
+mod nearest;
+mod reconcile;
+mod route;
+mod show;
+mod stats;
+mod sync;
+
+/// Tools for looking at the railway network in OpenStreetMap data.
+#[derive(Debug, StructOpt)]
+struct Args {
+    #[structopt(flatten)]
+    opts: Options,
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+// Options shared by all of the subcommands, which can be given either before
+// or after the subcommand name. (A doc comment here would replace the one on
+// `Args` in the help.)
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    /// The OSM data to read: `.osm.pbf`, or (optionally compressed) OSM XML.
+    #[structopt(short, long, global = true)]
+    input: Option<PathBuf>,
+    /// Only look at objects with this tag (`key` or `key=value`). May be
+    /// given more than once. Used by `export`, `stats`, `length`, `lint` and
+    /// `extract`.
+    #[structopt(short = "f", long = "filter", number_of_values = 1, global = true)]
+    filters: Vec<TagFilter>,
+    /// Only read the data inside `min_lon,min_lat,max_lon,max_lat`.
+    #[structopt(long, global = true)]
+    bbox: Option<BBox>,
+    /// Output format: `text`, `json`, `jsonl`, `csv` or `geojson`.
+    #[structopt(long, default_value = "text", global = true)]
+    format: output::Format,
+    /// Keep whatever we build from the input in this directory, and re-use
+    /// it on later runs.
+    #[structopt(long, global = true)]
+    cache: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Finds where the catchment areas of neighbouring stations meet.
+    Catchments(catchments::Args),
+    /// Finds the shortest route between stations, through or avoiding others.
+    Route(route::Args),
+    /// Writes everything out as tables, for loading into other tools.
+    Export(export::Args),
+    /// Counts tag values, pairs of tags and how complete the tagging is.
+    Stats(stats::Args),
+    /// Adds up how many kilometres of track there are, by tag.
+    Length(length::Args),
+    /// Checks for common mistakes in railway tagging.
+    Lint(lint::Args),
+    /// Compares the stations against a reference list.
+    Reconcile(reconcile::Args),
+    /// Finds the stations nearest to some points.
+    Nearest(nearest::Args),
+    /// Finds the stations within a distance or time along the track.
+    Isochrone(isochrone::Args),
+    /// Works out the distance along the track between every pair of stations.
+    Matrix(matrix::Args),
+    /// Looks up distances between stations, quickly, for lots of pairs.
+    Distance(distance::Args),
+    /// Works out where a GPS trace went along the track.
+    Match(map_match::Args),
+    /// Converts between positions and mileages along each ELR.
+    Mileage(mileage::Args),
+    /// Shows an object and everything it refers to.
+    Show(show::Args),
+    /// Writes the matching objects (and what they depend on) to a new file.
+    Extract(extract::Args),
+    /// Applies OsmChange files, and reports what changed.
+    Apply(apply::Args),
+    /// Keeps a saved network up to date from a replication feed.
+    Sync(sync::Args),
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let Args { opts, cmd } = Args::from_args();
+
+    match cmd {
+        Command::Catchments(args) => catchments::run(&opts, args),
+        Command::Route(args) => route::run(&opts, args),
+        Command::Export(args) => export::run(&opts, args),
+        Command::Stats(args) => stats::run(&opts, args),
+        Command::Length(args) => length::run(&opts, args),
+        Command::Lint(args) => lint::run(&opts, args),
+        Command::Reconcile(args) => reconcile::run(&opts, args),
+        Command::Nearest(args) => nearest::run(&opts, args),
+        Command::Isochrone(args) => isochrone::run(&opts, args),
+        Command::Matrix(args) => matrix::run(&opts, args),
+        Command::Distance(args) => distance::run(&opts, args),
+        Command::Match(args) => map_match::run(&opts, args),
+        Command::Mileage(args) => mileage::run(&opts, args),
+        Command::Show(args) => show::run(&opts, args),
+        Command::Extract(args) => extract::run(&opts, args),
+        Command::Apply(args) => apply::run(&opts, args),
+        Command::Sync(args) => sync::run(&opts, args),
+    }
+}
+
+impl Options {
+    pub fn input(&self) -> Result<Input> {
+        let path = self.input.as_ref().context("Need an --input file")?;
+        Ok(Input::open(path)
+            .with_context(|| format!("open input {:?}", path))?
+            .clip(self.bbox))
+    }
+
+    pub fn cache(&self) -> Cache {
+        Cache::new(self.cache.as_deref())
+    }
+
+    pub fn output(&self) -> Output<BufWriter<Stdout>> {
+        Output::stdout(self.format)
+    }
+}
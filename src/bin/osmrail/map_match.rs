@@ -0,0 +1,168 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use osmpbfreader::OsmId;
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    index::SegmentIndex,
+    matching::{Matcher, Params},
+    output::osm_ref,
+    trace,
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Works out where a GPS trace went along the track.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The trace: a `.gpx` file, or a CSV file with `lat`, `lon` and
+    /// optionally `time` columns.
+    trace: PathBuf,
+    /// How far (in metres) from each point to look for track.
+    #[structopt(long, default_value = "50")]
+    radius: f64,
+    /// How many of the nearest bits of track to consider for each point.
+    #[structopt(long, default_value = "8")]
+    candidates: usize,
+    /// The standard deviation of the GPS error, in metres.
+    #[structopt(long, default_value = "10")]
+    sigma: f64,
+    /// How much longer (in metres) than a straight line we expect the track
+    /// between two points to be.
+    #[structopt(long, default_value = "50")]
+    beta: f64,
+    /// List the ways and stations passed, rather than where each point
+    /// ended up.
+    #[structopt(long)]
+    passed: bool,
+}
+
+/// Where the `point`th point of the trace ended up on the track, if
+/// anywhere. `distance` is how far it moved, in metres.
+#[derive(Debug, Serialize)]
+struct Matched<'a> {
+    point: usize,
+    time: Option<&'a str>,
+    way: Option<String>,
+    distance: Option<f64>,
+    gps_lat: f64,
+    gps_lon: f64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+/// A way or station the trace went along or through, in order. `point` is
+/// the first point we matched after passing it.
+#[derive(Debug, Serialize)]
+struct Passed {
+    seq: usize,
+    kind: &'static str,
+    id: String,
+    name: Option<String>,
+    point: usize,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let fixes = trace::read(&args.trace)?;
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    let segments = SegmentIndex::new(&map, |via| matches!(via, OsmId::Way(_)));
+    let params = Params {
+        radius: args.radius,
+        candidates: args.candidates,
+        sigma: args.sigma,
+        beta: args.beta,
+    };
+    let steps = Matcher::new(&map, &segments, params).run(&fixes);
+
+    let mut out = opts.output();
+    if args.passed {
+        let mut passed = Vec::<Passed>::new();
+        for step in &steps {
+            let snap = match step.snap {
+                Some(snap) => snap,
+                None => continue,
+            };
+            for &(v, via) in &step.route {
+                push(&mut passed, &map, "way", via, step.fix, None);
+                for station in stations_at(&map, v) {
+                    let id = map.node_id(station).into();
+                    push(&mut passed, &map, "station", id, step.fix, Some(station));
+                }
+            }
+            push(&mut passed, &map, "way", snap.via, step.fix, None);
+        }
+        for p in passed {
+            out.write(&p)?;
+        }
+    } else {
+        for (step, fix) in steps.iter().zip(&fixes) {
+            out.write(&Matched {
+                point: step.fix,
+                time: fix.time.as_deref(),
+                way: step.snap.map(|s| osm_ref(s.via)),
+                distance: step.snap.map(|s| s.distance.round()),
+                gps_lat: fix.lat,
+                gps_lon: fix.lon,
+                lat: step.snap.map(|s| s.lat),
+                lon: step.snap.map(|s| s.lon),
+            })?;
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// Only adds `id` if it's not the same as the last thing of its kind, as we
+// see the same way for many points in a row.
+fn push(
+    passed: &mut Vec<Passed>,
+    map: &CompactMap,
+    kind: &'static str,
+    id: OsmId,
+    point: usize,
+    at: Option<Vertex>,
+) {
+    let name = map
+        .obj(id)
+        .and_then(|obj| obj.tags().get("name").map(|s| s.to_string()));
+    let id = osm_ref(id);
+    if passed.iter().rev().find(|p| p.kind == kind).map(|p| &p.id) == Some(&id) {
+        return;
+    }
+    let coord = at.and_then(|v| map.coord(v));
+    passed.push(Passed {
+        seq: passed.len() + 1,
+        kind,
+        id,
+        name,
+        point,
+        lat: coord.map(|c| c.lat()),
+        lon: coord.map(|c| c.lon()),
+    });
+}
+
+// Stations at `v`: either `v` itself, or one in a stop area with it (eg:
+// when the track goes through a platform, and the station is off to the
+// side).
+fn stations_at(map: &CompactMap, v: Vertex) -> Vec<Vertex> {
+    let mut stations = Vec::new();
+    if map.is_station(v) {
+        stations.push(v);
+    }
+    for (w, via) in map.edges(v) {
+        if matches!(via, OsmId::Relation(_)) && map.is_station(w) {
+            stations.push(w);
+        }
+    }
+    stations
+}
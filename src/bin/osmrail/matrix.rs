@@ -0,0 +1,137 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    contraction::Hierarchy,
+    filter::BBox,
+    output::osm_ref,
+    routing::one_to_many,
+};
+use par_map::ParMap;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Works out the distance along the track between every pair of some
+/// stations, searching from each station in parallel.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The stations, by CRS code. May be given more than once, or as a
+    /// comma-separated list.
+    #[structopt(long, use_delimiter = true, number_of_values = 1)]
+    crs: Vec<String>,
+    /// Every station inside `min_lon,min_lat,max_lon,max_lat`. Unlike
+    /// `--bbox`, routes can still go outside it.
+    #[structopt(long)]
+    within: Option<BBox>,
+    /// Write one row per station with a column for each of the others,
+    /// rather than a row per pair.
+    #[structopt(long)]
+    dense: bool,
+    /// Look up each distance in a contraction hierarchy (see `distance`),
+    /// rather than searching the network from each station. Building the
+    /// hierarchy takes a while, so this is only quicker with `--cache`.
+    #[structopt(long)]
+    hierarchy: bool,
+}
+
+/// How far it is (in metres) along the track between two stations, named
+/// by their CRS code (or their node id, if they don't have one). There's
+/// no `distance` when there's no way there.
+#[derive(Debug, Serialize)]
+struct Distance<'a> {
+    from: &'a str,
+    to: &'a str,
+    distance: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    let stations = stations(&map, &args);
+    if stations.is_empty() {
+        bail!("No stations to work out distances between: see --crs and --within");
+    }
+    info!("Working out distances between {} stations", stations.len());
+
+    let names = stations.iter().map(|&v| name(&map, v)).collect::<Vec<_>>();
+    let targets = Arc::new(stations.clone());
+    let rows: Box<dyn Iterator<Item = Vec<Option<f64>>>> = if args.hierarchy {
+        let hierarchy = Arc::new(
+            opts.cache()
+                .get_or_build("hierarchy", &input, &(), || Ok(Hierarchy::new(&map)))?,
+        );
+        Box::new(
+            stations
+                .into_iter()
+                .par_map(move |v| targets.iter().map(|&t| hierarchy.distance(v, t)).collect()),
+        )
+    } else {
+        let map = Arc::new(map);
+        Box::new(
+            stations
+                .into_iter()
+                .par_map(move |v| one_to_many(&map, v, &targets)),
+        )
+    };
+
+    let mut out = opts.output();
+    for (from, row) in names.iter().zip(rows) {
+        let distances = row.into_iter().map(|d| d.map(f64::round));
+        if args.dense {
+            let mut record = Map::new();
+            record.insert("from".into(), Value::from(from.as_str()));
+            for (to, d) in names.iter().zip(distances) {
+                record.insert(to.clone(), d.map(Value::from).unwrap_or(Value::Null));
+            }
+            out.write(&record)?;
+        } else {
+            for (to, distance) in names.iter().zip(distances) {
+                out.write(&Distance { from, to, distance })?;
+            }
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// The stations in `--crs` order, followed by any others `--within` the box,
+// in id order.
+fn stations(map: &CompactMap, args: &Args) -> Vec<Vertex> {
+    let mut stations = Vec::new();
+    let mut seen = HashSet::new();
+    for crs in &args.crs {
+        match map.vertex_by_crs(&crs.to_uppercase()) {
+            Some(v) if seen.insert(v) => stations.push(v),
+            Some(_) => (),
+            None => warn!("No station with CRS code {:?}", crs),
+        }
+    }
+    if let Some(bbox) = args.within {
+        for v in 0..map.vertex_count() as Vertex {
+            let inside = map
+                .coord(v)
+                .map(|c| bbox.contains(c.lat(), c.lon()))
+                .unwrap_or(false);
+            if inside && map.is_station(v) && seen.insert(v) {
+                stations.push(v);
+            }
+        }
+    }
+    stations
+}
+
+fn name(map: &CompactMap, v: Vertex) -> String {
+    map.obj_by_vertex(v)
+        .and_then(|obj| obj.tags().get("ref:crs").map(|s| s.to_string()))
+        .unwrap_or_else(|| osm_ref(map.node_id(v).into()))
+}
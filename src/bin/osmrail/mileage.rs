@@ -0,0 +1,127 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+use osmrail::{
+    geometry::LatLon,
+    linref::{format_mileage, parse_mileage, LinearRef, Position},
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Converts between positions and mileages along each ELR (Engineer's Line
+/// Reference), using the mileposts to calibrate.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// A point to find the mileage of, as `lat,lon`. May be given more than
+    /// once.
+    #[structopt(long = "at", number_of_values = 1)]
+    points: Vec<LatLon>,
+    /// A mileage to find, as `ELR:miles`, where the miles are either decimal
+    /// (`HAY:12.5`) or miles and chains (`HAY:12m 40ch`). May be given more
+    /// than once.
+    #[structopt(long = "find", number_of_values = 1)]
+    mileages: Vec<ElrMileage>,
+    /// How far (in metres) from a point to look for track with an ELR.
+    #[structopt(long, default_value = "100")]
+    radius: f64,
+}
+
+#[derive(Debug)]
+struct ElrMileage {
+    elr: String,
+    miles: f64,
+}
+
+impl FromStr for ElrMileage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some((elr, miles)) if !elr.trim().is_empty() => Ok(ElrMileage {
+                elr: elr.trim().to_string(),
+                miles: parse_mileage(miles)?,
+            }),
+            _ => bail!("Expected ELR:miles, eg: HAY:12m 40ch, not {:?}", s),
+        }
+    }
+}
+
+/// What we found for the `query`th `--at` or `--find`. Points with no track
+/// nearby, and mileages off the end of the line, have no `elr`. `distance`
+/// is how far (in metres) the point was from the track. Mileages are only
+/// `calibrated` if there are at least two mileposts on that stretch of
+/// line; otherwise they're just the distance from one end (or from the one
+/// milepost, going whichever way the line happens to run).
+#[derive(Debug, Serialize)]
+struct Mileage {
+    query: String,
+    elr: Option<String>,
+    miles: Option<f64>,
+    mileage: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    distance: Option<f64>,
+    calibrated: Option<bool>,
+}
+
+impl Mileage {
+    fn new(query: String, position: Option<Position>) -> Self {
+        match position {
+            Some(p) => Mileage {
+                query,
+                mileage: Some(format_mileage(p.miles)),
+                miles: Some(round(p.miles, 1e4)),
+                elr: Some(p.elr),
+                lat: Some(round(p.lat, 1e7)),
+                lon: Some(round(p.lon, 1e7)),
+                distance: Some(p.distance.round()),
+                calibrated: Some(p.calibrated),
+            },
+            None => Mileage {
+                query,
+                elr: None,
+                miles: None,
+                mileage: None,
+                lat: None,
+                lon: None,
+                distance: None,
+                calibrated: None,
+            },
+        }
+    }
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    if args.points.is_empty() && args.mileages.is_empty() {
+        bail!("Need something to look up: --at or --find");
+    }
+
+    let input = opts.input()?;
+    let linref = LinearRef::from_input(&input).context("Build linear referencing")?;
+
+    let mut out = opts.output();
+    for p in &args.points {
+        let query = format!("{},{}", p.lat, p.lon);
+        let position = linref.locate(p.lat, p.lon, args.radius);
+        out.write(&Mileage::new(query, position))?;
+    }
+    for m in &args.mileages {
+        let query = format!("{}:{}", m.elr, format_mileage(m.miles));
+        let positions = linref.position(&m.elr, m.miles);
+        if positions.is_empty() {
+            out.write(&Mileage::new(query.clone(), None))?;
+        }
+        for position in positions {
+            out.write(&Mileage::new(query.clone(), Some(position)))?;
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+fn round(x: f64, scale: f64) -> f64 {
+    (x * scale).round() / scale
+}
@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use geo::{HaversineDistance, Point};
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    geometry::LatLon,
+    index::SpatialIndex,
+    output::osm_ref,
+    routing::ShortestPaths,
+};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Finds the stations nearest to some points.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// A point to look near, as `lat,lon`. May be given more than once.
+    #[structopt(long = "at", number_of_values = 1)]
+    points: Vec<LatLon>,
+    /// A CSV file of points to look near, with `lat` and `lon` columns, and
+    /// optionally an `id`.
+    #[structopt(long)]
+    points_file: Option<PathBuf>,
+    /// How many stations to find for each point.
+    #[structopt(short = "n", long, default_value = "5")]
+    count: usize,
+    /// Find the nearest stations along the track, rather than as the crow
+    /// flies. We join the network at the nearest vertex to each point.
+    #[structopt(long)]
+    network: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(alias = "latitude")]
+    lat: f64,
+    #[serde(alias = "longitude", alias = "lng")]
+    lon: f64,
+}
+
+/// The `rank`th nearest station to `point` (which is the query's `id`, or
+/// its position in the list). `distance` is in a straight line, and
+/// `network_distance` along the track (including getting onto it), both in
+/// metres.
+#[derive(Debug, Serialize)]
+struct Nearest {
+    point: String,
+    rank: usize,
+    station: String,
+    crs: Option<String>,
+    name: Option<String>,
+    distance: f64,
+    network_distance: Option<f64>,
+    lat: f64,
+    lon: f64,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let mut queries = args
+        .points
+        .iter()
+        .map(|p| Query {
+            id: None,
+            lat: p.lat,
+            lon: p.lon,
+        })
+        .collect::<Vec<_>>();
+    if let Some(path) = &args.points_file {
+        let mut rdr = csv::Reader::from_path(path).with_context(|| format!("open {:?}", path))?;
+        for q in rdr.deserialize() {
+            queries.push(q.with_context(|| format!("read {:?}", path))?);
+        }
+    }
+    if queries.is_empty() {
+        bail!("Need some points to look near: --at or --points-file");
+    }
+
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+    let stations = SpatialIndex::new(&map, |v| map.is_station(v));
+    let track = if args.network {
+        Some(SpatialIndex::new(&map, |v| map.edges(v).next().is_some()))
+    } else {
+        None
+    };
+
+    let mut out = opts.output();
+    for (i, q) in queries.iter().enumerate() {
+        let point = q.id.clone().unwrap_or_else(|| i.to_string());
+        let found = match &track {
+            Some(track) => by_network(&map, track, q, args.count),
+            None => stations
+                .nearest(&map, q.lat, q.lon)
+                .take(args.count)
+                .map(|(v, _)| (v, None))
+                .collect(),
+        };
+        for (rank, (v, network_distance)) in found.into_iter().enumerate() {
+            let obj = map.obj_by_vertex(v).expect("station");
+            let coord = map.coord(v).expect("indexed station has a position");
+            let straight =
+                Point::new(q.lon, q.lat).haversine_distance(&Point::new(coord.lon(), coord.lat()));
+            out.write(&Nearest {
+                point: point.clone(),
+                rank: rank + 1,
+                station: osm_ref(obj.id()),
+                crs: obj.tags().get("ref:crs").map(|s| s.to_string()),
+                name: obj.tags().get("name").map(|s| s.to_string()),
+                distance: straight.round(),
+                network_distance: network_distance.map(f64::round),
+                lat: coord.lat(),
+                lon: coord.lon(),
+            })?;
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// Joins the network at the nearest vertex, and then searches outwards
+// along the track until we've found enough stations.
+fn by_network(
+    map: &CompactMap,
+    track: &SpatialIndex,
+    q: &Query,
+    count: usize,
+) -> Vec<(Vertex, Option<f64>)> {
+    let start = match track.nearest(map, q.lat, q.lon).next() {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+    ShortestPaths::new(map, Some(start))
+        .filter(|&(v, _)| map.is_station(v))
+        .take(count)
+        .map(|(v, d)| (v, Some(d)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn finds_the_nearest_stations_along_the_track() {
+        // Station 1 is nearest as the crow flies, but the track goes the
+        // long way round to it; station 4 is further off, but on a direct
+        // line.
+        let map = fixtures::network(
+            &[
+                (0.0, 0.0),
+                (0.001, 0.0),
+                (0.0, 0.01),
+                (0.001, 0.01),
+                (0.0, -0.002),
+            ],
+            &[&[0, 2, 3, 1], &[0, 4]],
+            &[],
+            &[1, 4],
+        );
+        let q = Query {
+            id: None,
+            lat: 0.0,
+            lon: 0.0001,
+        };
+        let stations = SpatialIndex::new(&map, |v| map.is_station(v));
+        let track = SpatialIndex::new(&map, |v| map.edges(v).next().is_some());
+
+        let straight = stations
+            .nearest(&map, q.lat, q.lon)
+            .map(|(v, _)| map.node_id(v).0)
+            .collect::<Vec<_>>();
+        assert_eq!(straight, vec![2, 5]);
+
+        let along = by_network(&map, &track, &q, 5)
+            .into_iter()
+            .map(|(v, d)| (map.node_id(v).0, d.map(f64::round)))
+            .collect::<Vec<_>>();
+        // Including the 11m to get onto the track at node 1.
+        assert_eq!(along, vec![(5, Some(234.0)), (2, Some(2346.0))]);
+        assert_eq!(by_network(&map, &track, &q, 1).len(), 1);
+    }
+}
@@ -0,0 +1,318 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::{Context, Result};
+use geo::{HaversineDistance, Point};
+use osmpbfreader::Node;
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    output::osm_ref,
+};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Compares the stations in the input against a reference list.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// A CSV file of stations, with `crs`, `tiploc`, `name`, `lat` and `lon`
+    /// columns.
+    reference: PathBuf,
+    /// Report stations that are further than this (in metres) from where
+    /// the reference says they are.
+    #[structopt(long, default_value = "200")]
+    misplaced: f64,
+    /// How far (in metres) to look for a station with the same name that's
+    /// missing its code, or has the wrong one.
+    #[structopt(long, default_value = "1000")]
+    search: f64,
+    /// Report the stations that match too.
+    #[structopt(long)]
+    all: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Reference {
+    #[serde(alias = "CRS")]
+    crs: String,
+    #[serde(alias = "TIPLOC", default)]
+    tiploc: Option<String>,
+    #[serde(alias = "Name", alias = "NAME")]
+    name: String,
+    #[serde(alias = "latitude", alias = "Latitude", alias = "LAT")]
+    lat: f64,
+    #[serde(alias = "longitude", alias = "Longitude", alias = "LON", alias = "lng")]
+    lon: f64,
+}
+
+/// How a reference station compares with what's in OSM:
+///
+/// * `ok`: found by its CRS code, about where we expected.
+/// * `misplaced`: found by its CRS code, but too far away.
+/// * `miscoded`: found nearby by name, but without the right CRS code.
+/// * `missing`: not found at all.
+/// * `extra`: a CRS code in OSM that isn't in the reference.
+#[derive(Debug, Serialize)]
+struct Discrepancy<'a> {
+    status: &'static str,
+    crs: Option<&'a str>,
+    tiploc: Option<&'a str>,
+    name: Option<&'a str>,
+    osm_id: Option<String>,
+    osm_crs: Option<&'a str>,
+    osm_name: Option<&'a str>,
+    distance: Option<f64>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+
+    let mut rdr = csv::Reader::from_path(&args.reference)
+        .with_context(|| format!("open {:?}", args.reference))?;
+    let reference = rdr
+        .deserialize()
+        .collect::<Result<Vec<Reference>, _>>()
+        .with_context(|| format!("read {:?}", args.reference))?;
+
+    let stations = stations(&map);
+
+    let known = reference
+        .iter()
+        .map(|r| r.crs.as_str())
+        .collect::<HashSet<_>>();
+    let mut out = opts.output();
+    for r in &reference {
+        let here = Point::new(r.lon, r.lat);
+        let by_crs = map.vertex_by_crs(&r.crs).and_then(|v| node(&map, v));
+
+        let (status, node) = compare(by_crs.as_ref(), &stations, r, &args);
+        if status == "ok" && !args.all {
+            continue;
+        }
+        out.write(&Discrepancy {
+            status,
+            crs: Some(&r.crs),
+            tiploc: r.tiploc.as_deref(),
+            name: Some(&r.name),
+            osm_id: node.map(|n| osm_ref(n.id.into())),
+            osm_crs: node.and_then(|n| n.tags.get("ref:crs")).map(|s| s.as_str()),
+            osm_name: node.and_then(|n| n.tags.get("name")).map(|s| s.as_str()),
+            distance: node.map(|n| distance(here, n).round()),
+            lat: Some(r.lat),
+            lon: Some(r.lon),
+        })?;
+    }
+
+    for crs in extra(&map, &known) {
+        let v = map.vertex_by_crs(crs).expect("known code");
+        let node = node(&map, v);
+        let node = node.as_ref();
+        out.write(&Discrepancy {
+            status: "extra",
+            crs: None,
+            tiploc: None,
+            name: None,
+            osm_id: Some(osm_ref(map.node_id(v).into())),
+            osm_crs: Some(crs),
+            osm_name: node.and_then(|n| n.tags.get("name")).map(|s| s.as_str()),
+            distance: None,
+            lat: node.map(Node::lat),
+            lon: node.map(Node::lon),
+        })?;
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// Where `r` is in OSM (if anywhere), and how that compares with the
+// reference. `by_crs` is the station with its code, if there is one.
+fn compare<'a>(
+    by_crs: Option<&'a Node>,
+    stations: &'a [Node],
+    r: &Reference,
+    args: &Args,
+) -> (&'static str, Option<&'a Node>) {
+    let here = Point::new(r.lon, r.lat);
+    match by_crs {
+        Some(node) if distance(here, node) > args.misplaced => ("misplaced", Some(node)),
+        Some(node) => ("ok", Some(node)),
+        None => match find(stations, r, args.search) {
+            Some(node) => ("miscoded", Some(node)),
+            None => ("missing", None),
+        },
+    }
+}
+
+// The CRS codes in OSM that aren't in the reference.
+fn extra<'a>(map: &'a CompactMap, known: &'a HashSet<&str>) -> impl Iterator<Item = &'a str> {
+    map.crses().filter(move |crs| !known.contains(crs))
+}
+
+fn stations(map: &CompactMap) -> Vec<Node> {
+    (0..map.vertex_count() as Vertex)
+        .filter(|&v| map.is_station(v))
+        .filter_map(|v| node(map, v))
+        .collect()
+}
+
+// The nearest station within `radius` with the same name. Other stations
+// nearby may well be different ones entirely, so we don't guess.
+fn find<'a>(stations: &'a [Node], r: &Reference, radius: f64) -> Option<&'a Node> {
+    let here = Point::new(r.lon, r.lat);
+    let name = normalise(&r.name);
+    stations
+        .iter()
+        .filter(|n| {
+            n.tags
+                .get("name")
+                .map(|s| normalise(s) == name)
+                .unwrap_or(false)
+        })
+        .map(|n| (distance(here, n), n))
+        .filter(|(d, _)| *d <= radius)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, n)| n)
+}
+
+fn node(map: &CompactMap, v: Vertex) -> Option<Node> {
+    map.obj_by_vertex(v).and_then(|obj| obj.node().cloned())
+}
+
+fn distance(here: Point<f64>, node: &Node) -> f64 {
+    let there = Point::new(node.lon(), node.lat());
+    here.haversine_distance(&there)
+}
+
+// So that eg: "St. Albans City Rail Station" matches "St Albans City", and
+// "Saint" and "&" match "St" and "and".
+fn normalise(name: &str) -> String {
+    let name = name
+        .replace('&', " and ")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    let mut words = name
+        .split_whitespace()
+        .map(|word| if word == "saint" { "st" } else { word })
+        .collect::<Vec<_>>();
+    for suffix in &["station", "rail"] {
+        if words.len() > 1 && words.last() == Some(suffix) {
+            words.pop();
+        }
+    }
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, OsmObj, Tags};
+
+    use super::*;
+
+    fn station(id: i64, lat: f64, name: &str, crs: Option<&str>) -> OsmObj {
+        let mut tags = Tags::new();
+        tags.insert("railway".into(), "station".into());
+        tags.insert("name".into(), name.into());
+        if let Some(crs) = crs {
+            tags.insert("ref:crs".into(), crs.into());
+        }
+        Node {
+            id: NodeId(id),
+            tags,
+            decimicro_lat: (lat * 1e7) as i32,
+            decimicro_lon: 0,
+        }
+        .into()
+    }
+
+    fn reference(crs: &str, name: &str, lat: f64) -> Reference {
+        Reference {
+            crs: crs.into(),
+            tiploc: None,
+            name: name.into(),
+            lat,
+            lon: 0.0,
+        }
+    }
+
+    #[test]
+    fn normalises_names() {
+        for (a, b) in &[
+            ("St. Albans City", "St Albans City"),
+            ("Saint Albans City", "St Albans City"),
+            ("Elephant & Castle", "Elephant and Castle"),
+            ("Elephant&Castle", "elephant and castle"),
+            ("KING'S CROSS", "Kings Cross"),
+            ("  Kings   Cross ", "Kings Cross"),
+            ("Kings Cross Rail Station", "Kings Cross"),
+            ("Kings Cross Station", "Kings Cross"),
+            ("Stainton", "Stainton"),
+        ] {
+            assert_eq!(normalise(a), normalise(b), "{:?} vs {:?}", a, b);
+        }
+        assert_eq!(normalise("Station"), "station");
+        assert_ne!(normalise("Kings Cross"), normalise("Kings Lynn"));
+        assert_ne!(normalise("St Pancras"), normalise("Stpancras"));
+    }
+
+    #[test]
+    fn compares_with_the_reference() {
+        // 0.001 degrees of latitude is about 111m.
+        let objs = vec![
+            station(1, 51.000, "Alpha", Some("AAA")),
+            station(2, 51.010, "Bravo", Some("BBB")),
+            station(3, 51.020, "St. Charlie", None),
+            station(4, 51.030, "Delta", Some("XXX")),
+            station(5, 51.040, "Echo", Some("EEE")),
+            station(6, 51.060, "Foxtrot", None),
+        ];
+        let map = CompactMap::from_reader(objs.into_iter().map(Ok)).unwrap();
+        let stations = stations(&map);
+        let args = Args::from_iter(&["reconcile", "ref.csv", "--search", "1000"]);
+
+        let reference = [
+            reference("AAA", "Alpha", 51.001),
+            // 1.1km from where it is.
+            reference("BBB", "Bravo", 51.020),
+            // By name, without a code, or with the wrong one.
+            reference("CCC", "Saint Charlie", 51.021),
+            reference("DDD", "Delta Rail Station", 51.035),
+            // There's a Foxtrot, but too far away to be sure it's this one.
+            reference("FFF", "Foxtrot", 51.070),
+            reference("GGG", "Golf", 51.070),
+        ];
+        let compared = reference
+            .iter()
+            .map(|r| {
+                let by_crs = map.vertex_by_crs(&r.crs).and_then(|v| node(&map, v));
+                let (status, node) = compare(by_crs.as_ref(), &stations, r, &args);
+                (r.crs.as_str(), status, node.map(|n| n.id.0))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            compared,
+            vec![
+                ("AAA", "ok", Some(1)),
+                ("BBB", "misplaced", Some(2)),
+                ("CCC", "miscoded", Some(3)),
+                ("DDD", "miscoded", Some(4)),
+                ("FFF", "missing", None),
+                ("GGG", "missing", None),
+            ]
+        );
+
+        let known = reference.iter().map(|r| r.crs.as_str()).collect();
+        let mut extra = extra(&map, &known).collect::<Vec<_>>();
+        extra.sort_unstable();
+        assert_eq!(extra, vec!["EEE", "XXX"]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use osmpbfreader::{OsmId, OsmObj, RelationId};
+use osmrail::{
+    compact::{CompactMap, Vertex},
+    geometry::Regions,
+    input::Input,
+    output::{osm_ref, parse_osm_ref},
+    routing::{k_shortest_paths, length, shortest_path, Avoid, Speeds},
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Finds the shortest route along the track between stations, calling at
+/// each of them in turn.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Where to go from, through and to, in order: CRS codes (`HYS`) or
+    /// node ids (`n123`).
+    #[structopt(min_values = 2, required = true)]
+    stops: Vec<String>,
+    /// Keep out of this station (a CRS code or node id), way (`w45`) or
+    /// relation (`r6`). Avoiding a station avoids its whole stop area, and
+    /// avoiding a relation avoids all of its members. May be given more
+    /// than once.
+    #[structopt(long, number_of_values = 1)]
+    avoid: Vec<String>,
+    /// Keep out of the polygons in this GeoJSON file. May be given more
+    /// than once.
+    #[structopt(long, number_of_values = 1)]
+    avoid_area: Vec<PathBuf>,
+    /// List up to this many different routes between two stops, shortest
+    /// first, rather than the steps along the best one.
+    #[structopt(long)]
+    alternatives: Option<usize>,
+    /// How much (from 0 to 1) of each alternative has to be on different
+    /// track from all of the shorter ones.
+    #[structopt(long, default_value = "0.2")]
+    min_dissimilarity: f64,
+    /// How many routes to look at, at most, when looking for alternatives.
+    /// Complicated junctions can make for a lot of routes which are almost
+    /// the same, so this may need raising to find very different ones.
+    #[structopt(long, default_value = "200")]
+    max_candidates: usize,
+    /// The speed (in km/h) to assume on track without a `maxspeed`, for
+    /// working out how long each alternative takes.
+    #[structopt(long, default_value = "80")]
+    default_speed: f64,
+}
+
+/// The `seq`th vertex along the `leg`th leg of the route, which goes from
+/// stop `from` to stop `to`. `via` is the way (or stop area) we took to get
+/// here, and `distance` is how far we've come since the start of the route,
+/// in metres.
+#[derive(Debug, Serialize)]
+struct Step<'a> {
+    leg: usize,
+    from: &'a str,
+    to: &'a str,
+    seq: usize,
+    node: String,
+    via: Option<String>,
+    name: Option<String>,
+    crs: Option<String>,
+    distance: f64,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+/// The `rank`th shortest route from `from` to `to`. `distance` is in
+/// metres, and `minutes` is how long it takes at the line speed. `shared`
+/// is the percentage of it that's also on the best route, and `ways` are
+/// the ones it uses that none of the other alternatives do.
+#[derive(Debug, Serialize)]
+struct Alternative<'a> {
+    rank: usize,
+    from: &'a str,
+    to: &'a str,
+    distance: f64,
+    minutes: f64,
+    shared: f64,
+    ways: String,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+    let map = opts
+        .cache()
+        .get_or_build("compact-network", &input, &(), || {
+            CompactMap::from_input(&input)
+        })?;
+
+    let stops = args
+        .stops
+        .iter()
+        .map(|s| stop(&map, s))
+        .collect::<Result<Vec<_>>>()?;
+    let avoid = avoid(&map, &input, &args)?;
+    for (s, &v) in args.stops.iter().zip(&stops) {
+        if avoid.avoids(v) {
+            bail!("Can't both call at and avoid {}", s);
+        }
+    }
+
+    if let Some(k) = args.alternatives {
+        return alternatives(opts, &args, &map, &stops, &avoid, k);
+    }
+
+    let mut out = opts.output();
+    let mut total = 0.0;
+    for (leg, pair) in stops.windows(2).enumerate() {
+        let (from, to) = (&args.stops[leg], &args.stops[leg + 1]);
+        let path = match shortest_path(&map, pair[0], pair[1], &avoid) {
+            Some(path) => path,
+            None => bail!("No route from {} to {}", from, to),
+        };
+        info!("{} → {}: {:.0}m", from, to, path.distance);
+
+        let mut distance = total;
+        let mut last = pair[0];
+        for (seq, &(v, via)) in path.steps.iter().enumerate() {
+            distance += length(&map, last, v);
+            last = v;
+            let obj = map.obj(map.node_id(v).into());
+            let tag = |key| {
+                obj.as_ref()
+                    .and_then(|obj| obj.tags().get(key).map(|s| s.to_string()))
+            };
+            let coord = map.coord(v);
+            out.write(&Step {
+                leg: leg + 1,
+                from,
+                to,
+                seq,
+                node: osm_ref(map.node_id(v).into()),
+                via: via.map(osm_ref),
+                name: tag("name"),
+                crs: tag("ref:crs"),
+                distance: distance.round(),
+                lat: coord.map(|c| c.lat()),
+                lon: coord.map(|c| c.lon()),
+            })?;
+        }
+        total += path.distance;
+    }
+    out.finish()?;
+    info!("Total: {:.0}m", total);
+
+    Ok(())
+}
+
+fn alternatives(
+    opts: &Options,
+    args: &Args,
+    map: &CompactMap,
+    stops: &[Vertex],
+    avoid: &Avoid,
+    k: usize,
+) -> Result<()> {
+    if stops.len() != 2 {
+        bail!("--alternatives only works between two stops");
+    }
+    let (from, to) = (&args.stops[0], &args.stops[1]);
+    let paths = k_shortest_paths(
+        map,
+        stops[0],
+        stops[1],
+        avoid,
+        k,
+        args.min_dissimilarity,
+        args.max_candidates,
+    );
+    if paths.is_empty() {
+        bail!("No route from {} to {}", from, to);
+    }
+    if paths.len() < k {
+        warn!(
+            "Only found {} different enough routes from {} to {} (see --max-candidates)",
+            paths.len(),
+            from,
+            to
+        );
+    }
+
+    let vias = paths
+        .iter()
+        .map(|p| p.vias().into_iter().collect::<HashSet<_>>())
+        .collect::<Vec<_>>();
+    let speeds = Speeds::new(map, args.default_speed);
+    let mut out = opts.output();
+    for (i, path) in paths.iter().enumerate() {
+        let ways = path
+            .vias()
+            .into_iter()
+            .filter(|via| matches!(via, OsmId::Way(_)))
+            .filter(|via| (0..paths.len()).all(|j| j == i || !vias[j].contains(via)))
+            .map(osm_ref)
+            .collect::<Vec<_>>();
+        out.write(&Alternative {
+            rank: i + 1,
+            from,
+            to,
+            distance: path.distance.round(),
+            minutes: (speeds.path_seconds(map, path) / 6.0).round() / 10.0,
+            shared: (path.shared(map, &paths[0]) * 1000.0).round() / 10.0,
+            ways: ways.join(" "),
+        })?;
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// A station to call at, by CRS code or node id.
+pub(crate) fn stop(map: &CompactMap, s: &str) -> Result<Vertex> {
+    if let Some(v) = map.vertex_by_crs(&s.to_uppercase()) {
+        return Ok(v);
+    }
+    match parse_osm_ref(s) {
+        Ok(OsmId::Node(id)) => map
+            .vertex(id)
+            .with_context(|| format!("{} isn't in the network", s)),
+        Ok(_) => bail!("Stops need to be nodes, not {}", s),
+        Err(_) => bail!("No station with CRS code {:?}", s),
+    }
+}
+
+fn avoid(map: &CompactMap, input: &Input, args: &Args) -> Result<Avoid> {
+    let mut avoid = Avoid::default();
+    let mut rels = BTreeSet::new();
+    for s in &args.avoid {
+        if let Some(v) = map.vertex_by_crs(&s.to_uppercase()) {
+            avoid.station(map, v);
+            continue;
+        }
+        match parse_osm_ref(s).with_context(|| format!("No station with CRS code {:?}", s))? {
+            OsmId::Node(id) => match map.vertex(id) {
+                Some(v) if map.is_station(v) => avoid.station(map, v),
+                Some(v) => avoid.vertex(v),
+                None => warn!("{} isn't in the network, so there's nothing to avoid", s),
+            },
+            OsmId::Way(id) => avoid.via(id.into()),
+            OsmId::Relation(id) => {
+                avoid.via(id.into());
+                rels.insert(id);
+            }
+        }
+    }
+    if !rels.is_empty() {
+        avoid_members(map, input, rels, &mut avoid)?;
+    }
+
+    for path in &args.avoid_area {
+        let regions = Regions::from_geojson(path, None)?;
+        let n = avoid.area(map, |p| regions.find(p).is_some());
+        info!("Avoiding {} vertices inside {:?}", n, path);
+    }
+    Ok(avoid)
+}
+
+// We only keep stop areas in the network, so we read the other relations
+// (eg: routes) from the input to find their members. Members which are
+// relations themselves (eg: the routes in a route master) take another
+// pass each level down.
+fn avoid_members(
+    map: &CompactMap,
+    input: &Input,
+    mut wanted: BTreeSet<RelationId>,
+    avoid: &mut Avoid,
+) -> Result<()> {
+    let mut seen = HashSet::new();
+    while !wanted.is_empty() {
+        seen.extend(wanted.iter().cloned());
+        let mut next = BTreeSet::new();
+        for it in input.par_objs()? {
+            let rel = match it.context("Read item")? {
+                OsmObj::Relation(rel) if wanted.remove(&rel.id) => rel,
+                _ => continue,
+            };
+            for r in &rel.refs {
+                match r.member {
+                    OsmId::Node(id) => {
+                        if let Some(v) = map.vertex(id) {
+                            avoid.vertex(v);
+                        }
+                    }
+                    OsmId::Way(id) => avoid.via(id.into()),
+                    OsmId::Relation(id) => {
+                        avoid.via(id.into());
+                        if !seen.contains(&id) {
+                            next.insert(id);
+                        }
+                    }
+                }
+            }
+        }
+        for id in wanted {
+            warn!("r{} isn't in the input, so there's nothing to avoid", id.0);
+        }
+        wanted = next;
+    }
+    Ok(())
+}
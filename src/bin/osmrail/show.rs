@@ -0,0 +1,588 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::Write,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::{Node, OsmId, OsmObj, Tags};
+use osmrail::{
+    filter::TagFilter,
+    input::Input,
+    output::{osm_ref, parse_osm_ref, Output},
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Shows the given objects, or those matching `--filter`, along with
+/// everything they refer to.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Objects to show, eg: `n123`, `w45` or `r6`.
+    #[structopt(parse(try_from_str = parse_osm_ref))]
+    ids: Vec<OsmId>,
+    /// How many levels of members to show. Without this, we go all the way
+    /// down to the nodes.
+    #[structopt(long)]
+    depth: Option<usize>,
+    /// Write each object as a single nested record, rather than one record
+    /// per member. Best used with `--format json`.
+    #[structopt(long)]
+    tree: bool,
+    /// Show what's changed between the input and this other snapshot,
+    /// rather than the objects themselves.
+    #[structopt(long)]
+    diff: Option<PathBuf>,
+}
+
+/// An object in the tree under one we're showing, in depth-first order.
+/// `cycle` is set on relations that turn up inside themselves, which we
+/// don't descend into a second time.
+#[derive(Debug, Serialize)]
+struct Member<'a> {
+    root: String,
+    depth: usize,
+    role: Option<&'a str>,
+    id: String,
+    tags: Option<&'a Tags>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    cycle: bool,
+}
+
+/// An object, and everything under it. `tags` is missing for objects that
+/// aren't in the input (eg: outside of an extract).
+#[derive(Debug, Serialize)]
+struct Element<'a> {
+    #[serde(skip)]
+    osm_id: OsmId,
+    id: String,
+    role: Option<&'a str>,
+    tags: Option<&'a Tags>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    cycle: bool,
+    children: Vec<Element<'a>>,
+}
+
+/// One thing that's different between two snapshots of an object. `path`
+/// is the chain of ids from the root down to whatever changed.
+#[derive(Debug, Serialize)]
+struct Difference {
+    root: String,
+    path: String,
+    change: &'static str,
+    key: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+struct Builder<'a> {
+    data: &'a BTreeMap<OsmId, OsmObj>,
+    max_depth: Option<usize>,
+    // The relations between the root and where we are now.
+    ancestors: HashSet<OsmId>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    if args.ids.is_empty() && opts.filters.is_empty() {
+        bail!("Need some ids to show, or a --filter to find them with");
+    }
+    let input = opts.input()?;
+    let (mut roots, data) = select(opts, &args, &input)?;
+
+    let mut out = opts.output();
+    match &args.diff {
+        None => {
+            for &root in &roots {
+                if !data.contains_key(&root) {
+                    log::warn!("{} isn't in {:?}", osm_ref(root), input.path());
+                    continue;
+                }
+                let tree = Builder::new(&data, args.depth).build(0, None, root);
+                if args.tree {
+                    out.write(&tree)?;
+                } else {
+                    flatten(&mut out, &tree, &tree.id, 0)?;
+                }
+            }
+        }
+        Some(other) => {
+            let other = Input::open(other)
+                .with_context(|| format!("open {:?}", other))?
+                .clip(opts.bbox);
+            let (other_roots, other_data) = select(opts, &args, &other)?;
+            for root in other_roots {
+                if !roots.contains(&root) {
+                    roots.push(root);
+                }
+            }
+            for &root in &roots {
+                let before = Builder::new(&data, args.depth).build(0, None, root);
+                let after = Builder::new(&other_data, args.depth).build(0, None, root);
+                let mut diffs = Vec::new();
+                diff(&before, &after, &mut vec![before.id.clone()], &mut diffs);
+                for d in diffs {
+                    out.write(&d)?;
+                }
+            }
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
+
+// Finds the objects we were asked for, and everything under them.
+fn select(
+    opts: &Options,
+    args: &Args,
+    input: &Input,
+) -> Result<(Vec<OsmId>, BTreeMap<OsmId, OsmObj>)> {
+    let wanted = args.ids.iter().cloned().collect::<HashSet<_>>();
+    let mut roots = args.ids.clone();
+    let data = input.get_objs_and_deps(|obj| {
+        let found = wanted.contains(&obj.id())
+            || (!opts.filters.is_empty() && TagFilter::any_match(&opts.filters, obj.tags()));
+        if found && !wanted.contains(&obj.id()) {
+            roots.push(obj.id());
+        }
+        found
+    })?;
+    Ok((roots, data))
+}
+
+impl<'a> Builder<'a> {
+    fn new(data: &'a BTreeMap<OsmId, OsmObj>, max_depth: Option<usize>) -> Self {
+        Builder {
+            data,
+            max_depth,
+            ancestors: HashSet::new(),
+        }
+    }
+
+    fn build(&mut self, depth: usize, role: Option<&'a str>, id: OsmId) -> Element<'a> {
+        let obj = self.data.get(&id);
+        let node = obj.and_then(OsmObj::node);
+        let cycle = self.ancestors.contains(&id);
+        let mut el = Element {
+            osm_id: id,
+            id: osm_ref(id),
+            role,
+            tags: obj.map(OsmObj::tags),
+            lat: node.map(Node::lat),
+            lon: node.map(Node::lon),
+            cycle,
+            children: Vec::new(),
+        };
+
+        if cycle || self.max_depth.map(|max| depth >= max).unwrap_or(false) {
+            return el;
+        }
+        match obj {
+            Some(OsmObj::Relation(rel)) => {
+                self.ancestors.insert(id);
+                el.children = rel
+                    .refs
+                    .iter()
+                    .map(|child| self.build(depth + 1, Some(&child.role), child.member))
+                    .collect();
+                self.ancestors.remove(&id);
+            }
+            Some(OsmObj::Way(way)) => {
+                el.children = way
+                    .nodes
+                    .iter()
+                    .map(|child| self.build(depth + 1, None, (*child).into()))
+                    .collect();
+            }
+            Some(OsmObj::Node(_)) | None => (),
+        }
+        el
+    }
+}
+
+fn flatten<W: Write>(out: &mut Output<W>, el: &Element, root: &str, depth: usize) -> Result<()> {
+    out.write(&Member {
+        root: root.to_string(),
+        depth,
+        role: el.role,
+        id: el.id.clone(),
+        tags: el.tags,
+        lat: el.lat,
+        lon: el.lon,
+        cycle: el.cycle,
+    })?;
+    for child in &el.children {
+        flatten(out, child, root, depth + 1)?;
+    }
+    Ok(())
+}
+
+// Members are matched up by id, keeping them in order (so a member that's
+// moved shows up as removed from one place and added in another).
+fn diff(before: &Element, after: &Element, path: &mut Vec<String>, diffs: &mut Vec<Difference>) {
+    match (before.tags, after.tags) {
+        (Some(_), None) => diffs.push(Difference::at(path, "deleted", None, None, None)),
+        (None, Some(_)) => diffs.push(Difference::at(path, "created", None, None, None)),
+        (None, None) => (),
+        (Some(old), Some(new)) => {
+            for key in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+                let (a, b) = (old.get(key), new.get(key));
+                if a != b {
+                    diffs.push(Difference::at(
+                        path,
+                        "tag",
+                        Some(key.to_string()),
+                        a.map(|v| v.to_string()),
+                        b.map(|v| v.to_string()),
+                    ));
+                }
+            }
+            if (before.lat, before.lon) != (after.lat, after.lon) {
+                let pos = |el: &Element| {
+                    el.lat
+                        .zip(el.lon)
+                        .map(|(lat, lon)| format!("{},{}", lat, lon))
+                };
+                diffs.push(Difference::at(
+                    path,
+                    "position",
+                    None,
+                    pos(before),
+                    pos(after),
+                ));
+            }
+        }
+    }
+
+    let common = lcs(&before.children, &after.children);
+    let (mut i, mut j) = (0, 0);
+    for (ci, cj) in common
+        .into_iter()
+        .chain(Some((before.children.len(), after.children.len())))
+    {
+        for child in &before.children[i..ci] {
+            let member = Some(child.member());
+            diffs.push(Difference::at(path, "member removed", None, member, None));
+        }
+        for child in &after.children[j..cj] {
+            let member = Some(child.member());
+            diffs.push(Difference::at(path, "member added", None, None, member));
+        }
+        if ci < before.children.len() {
+            let (a, b) = (&before.children[ci], &after.children[cj]);
+            path.push(a.id.clone());
+            if a.role != b.role {
+                diffs.push(Difference::at(
+                    path,
+                    "role",
+                    None,
+                    a.role.map(str::to_string),
+                    b.role.map(str::to_string),
+                ));
+            }
+            diff(a, b, path, diffs);
+            path.pop();
+        }
+        i = ci + 1;
+        j = cj + 1;
+    }
+}
+
+impl Element<'_> {
+    // How a member appears in its parent, eg: `w45 platform`.
+    fn member(&self) -> String {
+        match self.role {
+            Some(role) if !role.is_empty() => format!("{} {}", self.id, role),
+            _ => self.id.clone(),
+        }
+    }
+}
+
+impl Difference {
+    fn at(
+        path: &[String],
+        change: &'static str,
+        key: Option<String>,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Self {
+        Difference {
+            root: path[0].clone(),
+            path: path.join("/"),
+            change,
+            key,
+            before,
+            after,
+        }
+    }
+}
+
+// Past this many cells (64 MB), the table for the longest common
+// subsequence takes too much memory, so we match members up as we go.
+const MAX_LCS_CELLS: usize = 1 << 24;
+
+// The longest common subsequence of the two lists of members, by id, as
+// pairs of indexes. Usually most of a relation hasn't changed, so we only
+// need to work it out for the part between the common start and end.
+fn lcs(a: &[Element], b: &[Element]) -> Vec<(usize, usize)> {
+    let shortest = a.len().min(b.len());
+    let prefix = (0..shortest)
+        .take_while(|&i| a[i].osm_id == b[i].osm_id)
+        .count();
+    let suffix = (0..shortest - prefix)
+        .take_while(|&k| a[a.len() - 1 - k].osm_id == b[b.len() - 1 - k].osm_id)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut pairs = (0..prefix).map(|i| (i, i)).collect::<Vec<_>>();
+    let middle = if (a_mid.len() + 1) * (b_mid.len() + 1) <= MAX_LCS_CELLS {
+        lcs_table(a_mid, b_mid)
+    } else {
+        in_order(a_mid, b_mid)
+    };
+    pairs.extend(middle.into_iter().map(|(i, j)| (i + prefix, j + prefix)));
+    pairs.extend((0..suffix).map(|k| (a.len() - suffix + k, b.len() - suffix + k)));
+    pairs
+}
+
+fn lcs_table(a: &[Element], b: &[Element]) -> Vec<(usize, usize)> {
+    let mut len = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            len[i][j] = if a[i].osm_id == b[j].osm_id {
+                len[i + 1][j + 1] + 1
+            } else {
+                len[i + 1][j].max(len[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].osm_id == b[j].osm_id {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if len[i + 1][j] >= len[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+// Matches each member of `a` with the next one with the same id in `b`, if
+// there is one. Not always the longest match, but it only takes as much
+// memory as the lists themselves.
+fn in_order(a: &[Element], b: &[Element]) -> Vec<(usize, usize)> {
+    let mut positions = BTreeMap::<OsmId, Vec<usize>>::new();
+    for (j, el) in b.iter().enumerate() {
+        positions.entry(el.osm_id).or_default().push(j);
+    }
+    let mut pairs = Vec::new();
+    let mut next = 0;
+    for (i, el) in a.iter().enumerate() {
+        let js = match positions.get(&el.osm_id) {
+            Some(js) => js,
+            None => continue,
+        };
+        if let Some(&j) = js.get(js.partition_point(|&j| j < next)) {
+            pairs.push((i, j));
+            next = j + 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, Ref, Relation, RelationId, Way, WayId};
+
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    fn data(members: &[(OsmId, &str)], way: &str, lat: f64) -> BTreeMap<OsmId, OsmObj> {
+        let objs: Vec<OsmObj> = vec![
+            Relation {
+                id: RelationId(1),
+                tags: tags(&[("type", "route")]),
+                refs: members
+                    .iter()
+                    .map(|&(member, role)| Ref {
+                        member,
+                        role: role.into(),
+                    })
+                    .collect(),
+            }
+            .into(),
+            Way {
+                id: WayId(2),
+                tags: tags(&[("railway", way)]),
+                nodes: vec![NodeId(3)],
+            }
+            .into(),
+            Node {
+                id: NodeId(3),
+                tags: Tags::new(),
+                decimicro_lat: (lat * 1e7_f64).round() as i32,
+                decimicro_lon: 0,
+            }
+            .into(),
+            Node {
+                id: NodeId(4),
+                tags: tags(&[("railway", "stop")]),
+                decimicro_lat: 0,
+                decimicro_lon: 0,
+            }
+            .into(),
+        ];
+        objs.into_iter().map(|o| (o.id(), o)).collect()
+    }
+
+    // Where, what, and the key, before and after.
+    type Row = (
+        String,
+        &'static str,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    );
+
+    fn diffs(before: &BTreeMap<OsmId, OsmObj>, after: &BTreeMap<OsmId, OsmObj>) -> Vec<Row> {
+        let root = RelationId(1).into();
+        let before = Builder::new(before, None).build(0, None, root);
+        let after = Builder::new(after, None).build(0, None, root);
+        let mut diffs = Vec::new();
+        diff(&before, &after, &mut vec![before.id.clone()], &mut diffs);
+        diffs
+            .into_iter()
+            .map(|d| {
+                assert_eq!(d.root, "r1");
+                (d.path, d.change, d.key, d.before, d.after)
+            })
+            .collect()
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let data = data(
+            &[(WayId(2).into(), ""), (NodeId(4).into(), "stop")],
+            "rail",
+            1.0,
+        );
+        assert!(diffs(&data, &data).is_empty());
+    }
+
+    #[test]
+    fn finds_what_changed() {
+        let before = data(
+            &[
+                (NodeId(4).into(), "stop"),
+                (WayId(2).into(), ""),
+                (NodeId(3).into(), "a"),
+            ],
+            "rail",
+            1.0,
+        );
+        // The stop moved to the end, the node's role changed, a
+        // relation was added, and the way and its node were changed.
+        let after = data(
+            &[
+                (WayId(2).into(), ""),
+                (NodeId(3).into(), "b"),
+                (RelationId(5).into(), ""),
+                (NodeId(4).into(), "stop"),
+            ],
+            "disused",
+            1.5,
+        );
+        assert_eq!(
+            diffs(&before, &after),
+            vec![
+                ("r1".into(), "member removed", None, some("n4 stop"), None),
+                (
+                    "r1/w2".into(),
+                    "tag",
+                    some("railway"),
+                    some("rail"),
+                    some("disused")
+                ),
+                (
+                    "r1/w2/n3".into(),
+                    "position",
+                    None,
+                    some("1,0"),
+                    some("1.5,0")
+                ),
+                ("r1/n3".into(), "role", None, some("a"), some("b")),
+                ("r1/n3".into(), "position", None, some("1,0"), some("1.5,0")),
+                ("r1".into(), "member added", None, None, some("r5")),
+                ("r1".into(), "member added", None, None, some("n4 stop")),
+            ]
+        );
+
+        // A member that wasn't there before (even one we don't have).
+        let before = data(&[(WayId(2).into(), "")], "rail", 1.0);
+        let after = data(&[(WayId(2).into(), ""), (WayId(9).into(), "")], "rail", 1.0);
+        assert_eq!(
+            diffs(&before, &after),
+            vec![("r1".into(), "member added", None, None, some("w9"))]
+        );
+        assert_eq!(
+            diffs(&after, &before),
+            vec![("r1".into(), "member removed", None, some("w9"), None)]
+        );
+    }
+
+    fn members(ids: impl Iterator<Item = i64>) -> Vec<Element<'static>> {
+        ids.map(|id| Element {
+            osm_id: NodeId(id).into(),
+            id: format!("n{}", id),
+            role: None,
+            tags: None,
+            lat: None,
+            lon: None,
+            cycle: false,
+            children: Vec::new(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn matches_up_long_lists() {
+        // One inserted in the middle: only the bit in between needs a table.
+        let a = members(0..10_000);
+        let b = members((0..5_000).chain(Some(-1)).chain(5_000..10_000));
+        let pairs = lcs(&a, &b);
+        assert_eq!(pairs.len(), 10_000);
+        assert_eq!(pairs[4_999], (4_999, 4_999));
+        assert_eq!(pairs[5_000], (5_000, 5_001));
+
+        // Too much has changed to work out the best match, but we still
+        // find one.
+        let a = members(0..10_000);
+        let b = members((0..10_000).map(|i| if i % 2 == 0 { i } else { -i }).rev());
+        let pairs = lcs(&a, &b);
+        assert!(!pairs.is_empty());
+        for w in pairs.windows(2) {
+            assert!(w[0].0 < w[1].0 && w[0].1 < w[1].1);
+        }
+        for &(i, j) in &pairs {
+            assert_eq!(a[i].osm_id, b[j].osm_id);
+        }
+        assert_eq!(lcs(&a, &a).len(), 10_000);
+        assert!(lcs(&a, &[]).is_empty());
+    }
+}
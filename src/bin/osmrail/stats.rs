@@ -0,0 +1,324 @@
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::{Context, Result};
+use osmpbfreader::OsmObj;
+use osmrail::{filter::TagFilter, output::Output};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Counts tags on the objects matching `--filter` (or everything, with no
+/// filters), separately for nodes, ways and relations.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// Count the values of these keys. May be given more than once, or as a
+    /// comma separated list.
+    #[structopt(
+        long = "key",
+        number_of_values = 1,
+        use_delimiter = true,
+        default_value = "railway"
+    )]
+    keys: Vec<String>,
+    /// Also count how often each pair of `--key`s turn up on the same object.
+    #[structopt(long)]
+    pairs: bool,
+    /// Report the share of objects that have these keys, eg: `-f railway=rail
+    /// --completeness gauge,electrified,maxspeed`.
+    #[structopt(long, number_of_values = 1, use_delimiter = true)]
+    completeness: Vec<String>,
+}
+
+/// One line of the results. `stat` says which sort it is:
+///
+/// * `value`: how many objects have `key=value`.
+/// * `pair`: how many objects have both `key` and `with`.
+/// * `completeness`: how many of the `total` objects looked at have `key`.
+#[derive(Debug, Serialize)]
+struct Stat<'a> {
+    stat: &'static str,
+    kind: &'static str,
+    key: &'a str,
+    value: Option<&'a str>,
+    with: Option<&'a str>,
+    count: u64,
+    total: Option<u64>,
+    share: Option<f64>,
+}
+
+// The counts for one kind of object.
+#[derive(Debug, Default)]
+struct Counts {
+    total: u64,
+    values: BTreeMap<(usize, String), u64>,
+    pairs: BTreeMap<(usize, usize), u64>,
+    present: BTreeMap<usize, u64>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let input = opts.input()?;
+
+    let counts = count(input.par_objs()?, &opts.filters, &args)?;
+    write(&args, &counts, opts.output())
+}
+
+// With no filters, we count everything.
+fn count(
+    objs: impl Iterator<Item = Result<OsmObj>>,
+    filters: &[TagFilter],
+    args: &Args,
+) -> Result<[(&'static str, Counts); 3]> {
+    let mut counts = [
+        ("node", Counts::default()),
+        ("way", Counts::default()),
+        ("relation", Counts::default()),
+    ];
+
+    for it in objs {
+        let it = it.context("Read item")?;
+        let tags = it.tags();
+        if !filters.is_empty() && !TagFilter::any_match(filters, tags) {
+            continue;
+        }
+        let (_, counts) = match it {
+            OsmObj::Node(_) => &mut counts[0],
+            OsmObj::Way(_) => &mut counts[1],
+            OsmObj::Relation(_) => &mut counts[2],
+        };
+        counts.total += 1;
+
+        for (i, key) in args.keys.iter().enumerate() {
+            if let Some(val) = tags.get(key.as_str()) {
+                *counts.values.entry((i, val.to_string())).or_default() += 1;
+            }
+        }
+        if args.pairs {
+            for (i, a) in args.keys.iter().enumerate() {
+                for (j, b) in args.keys.iter().enumerate().skip(i + 1) {
+                    if tags.contains_key(a.as_str()) && tags.contains_key(b.as_str()) {
+                        *counts.pairs.entry((i, j)).or_default() += 1;
+                    }
+                }
+            }
+        }
+        for (i, key) in args.completeness.iter().enumerate() {
+            if tags.contains_key(key.as_str()) {
+                *counts.present.entry(i).or_default() += 1;
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+fn write<W: Write>(
+    args: &Args,
+    counts: &[(&'static str, Counts)],
+    mut out: Output<W>,
+) -> Result<()> {
+    let stat = |stat, kind, key| Stat {
+        stat,
+        kind,
+        key,
+        value: None,
+        with: None,
+        count: 0,
+        total: None,
+        share: None,
+    };
+
+    for (kind, counts) in counts {
+        for ((i, value), &count) in &counts.values {
+            out.write(&Stat {
+                value: Some(value),
+                count,
+                ..stat("value", kind, &args.keys[*i])
+            })?;
+        }
+        for (&(i, j), &count) in &counts.pairs {
+            out.write(&Stat {
+                with: Some(&args.keys[j]),
+                count,
+                ..stat("pair", kind, &args.keys[i])
+            })?;
+        }
+        // We don't bother with kinds we haven't seen any of.
+        if counts.total == 0 {
+            continue;
+        }
+        for (i, key) in args.completeness.iter().enumerate() {
+            let count = counts.present.get(&i).cloned().unwrap_or(0);
+            out.write(&Stat {
+                count,
+                total: Some(counts.total),
+                share: Some(count as f64 / counts.total as f64),
+                ..stat("completeness", kind, key)
+            })?;
+        }
+    }
+
+    out.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{Node, NodeId, Relation, RelationId, Tags, Way, WayId};
+    use osmrail::output::Format;
+
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    fn objs() -> Vec<OsmObj> {
+        let node = |id, t: &[(&str, &str)]| {
+            Node {
+                id: NodeId(id),
+                tags: tags(t),
+                decimicro_lat: 0,
+                decimicro_lon: 0,
+            }
+            .into()
+        };
+        let way = |id, t: &[(&str, &str)]| {
+            Way {
+                id: WayId(id),
+                tags: tags(t),
+                nodes: Vec::new(),
+            }
+            .into()
+        };
+        vec![
+            node(1, &[("railway", "station"), ("name", "A")]),
+            node(2, &[("railway", "signal")]),
+            node(3, &[("highway", "crossing")]),
+            way(
+                10,
+                &[("railway", "rail"), ("gauge", "1435"), ("maxspeed", "60")],
+            ),
+            way(11, &[("railway", "rail"), ("gauge", "1435")]),
+            way(12, &[("railway", "disused")]),
+            way(13, &[("highway", "primary"), ("maxspeed", "30")]),
+            Relation {
+                id: RelationId(20),
+                tags: tags(&[("railway", "rail")]),
+                refs: Vec::new(),
+            }
+            .into(),
+        ]
+    }
+
+    // A row we write, as `(stat, kind, key, value or with, count)`.
+    type Row = (String, String, String, String, u64);
+
+    fn stats(args: &[&str], filters: &[&str]) -> Vec<Row> {
+        let args = Args::from_iter(Some("stats").into_iter().chain(args.iter().cloned()));
+        let filters = filters
+            .iter()
+            .map(|f| f.parse().unwrap())
+            .collect::<Vec<TagFilter>>();
+        let counts = count(objs().into_iter().map(Ok), &filters, &args).unwrap();
+        let mut buf = Vec::new();
+        write(&args, &counts, Output::new(Format::Jsonl, &mut buf)).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        text.lines()
+            .map(|line| {
+                let row = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                let s = |k: &str| row[k].as_str().unwrap_or_default().to_string();
+                let detail = match s("stat").as_str() {
+                    "value" => s("value"),
+                    "pair" => s("with"),
+                    _ => format!("{}/{}", row["count"], row["total"]),
+                };
+                (
+                    s("stat"),
+                    s("kind"),
+                    s("key"),
+                    detail,
+                    row["count"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn row(stat: &str, kind: &str, key: &str, detail: &str, count: u64) -> Row {
+        (stat.into(), kind.into(), key.into(), detail.into(), count)
+    }
+
+    #[test]
+    fn counts_values() {
+        assert_eq!(
+            stats(&[], &[]),
+            vec![
+                row("value", "node", "railway", "signal", 1),
+                row("value", "node", "railway", "station", 1),
+                row("value", "way", "railway", "disused", 1),
+                row("value", "way", "railway", "rail", 2),
+                row("value", "relation", "railway", "rail", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_pairs() {
+        let rows = stats(&["--key", "railway,gauge,maxspeed", "--pairs"], &[]);
+        let pairs = rows
+            .into_iter()
+            .filter(|r| r.0 == "pair")
+            .collect::<Vec<_>>();
+        // Never a key with itself, and each pair only one way round.
+        assert_eq!(
+            pairs,
+            vec![
+                row("pair", "way", "railway", "gauge", 2),
+                row("pair", "way", "railway", "maxspeed", 1),
+                row("pair", "way", "gauge", "maxspeed", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_completeness() {
+        let rows = stats(&["--completeness", "gauge,maxspeed,name"], &[]);
+        let complete = rows
+            .into_iter()
+            .filter(|r| r.0 == "completeness")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            complete,
+            vec![
+                row("completeness", "node", "gauge", "0/3", 0),
+                row("completeness", "node", "maxspeed", "0/3", 0),
+                row("completeness", "node", "name", "1/3", 1),
+                row("completeness", "way", "gauge", "2/4", 2),
+                row("completeness", "way", "maxspeed", "2/4", 2),
+                row("completeness", "way", "name", "0/4", 0),
+                row("completeness", "relation", "gauge", "0/1", 0),
+                row("completeness", "relation", "maxspeed", "0/1", 0),
+                row("completeness", "relation", "name", "0/1", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_counts_what_matches_the_filters() {
+        let rows = stats(
+            &["--key", "railway,maxspeed", "--completeness", "gauge"],
+            &["railway=rail"],
+        );
+        assert_eq!(
+            rows,
+            vec![
+                row("value", "way", "railway", "rail", 2),
+                row("value", "way", "maxspeed", "60", 1),
+                row("completeness", "way", "gauge", "2/2", 2),
+                row("value", "relation", "railway", "rail", 1),
+                row("completeness", "relation", "gauge", "0/1", 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::{path::PathBuf, thread, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use osmrail::{
+    checkpoint::Checkpoint,
+    network::{Feature, Map},
+    osc::Action,
+    output::osm_ref,
+    replication::Replication,
+};
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::Options;
+
+/// Keeps a saved rail network up to date from an OSM replication feed,
+/// reporting the stations, sections and routes that change. If there's no
+/// checkpoint yet, the network is built from the input.
+///
+/// Each batch of changes is reported once the checkpoint has been saved, so
+/// if we're stopped partway, a batch may go unreported but never shows up
+/// twice.
+#[derive(Debug, StructOpt)]
+pub struct Args {
+    /// The replication feed; either a local directory or an http:// or
+    /// https:// URL.
+    replication: String,
+    /// Where to keep the network and the last applied sequence number.
+    checkpoint: PathBuf,
+    /// The replication sequence number that the input is up to date with.
+    /// Defaults to the current state of the feed. Only used when there's no
+    /// checkpoint yet.
+    #[structopt(long)]
+    sequence: Option<u64>,
+    /// Keep checking for new changes every this many seconds, rather than
+    /// stopping once we've caught up.
+    #[structopt(long)]
+    poll: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Change<'a> {
+    sequence: u64,
+    action: Action,
+    feature: Feature,
+    id: String,
+    name: Option<&'a str>,
+}
+
+pub fn run(opts: &Options, args: Args) -> Result<()> {
+    let replication = Replication::new(&args.replication);
+
+    let mut checkpoint = match Checkpoint::load(&args.checkpoint)? {
+        Some(checkpoint) => {
+            if let Some(sequence) = args.sequence {
+                bail!(
+                    "The checkpoint {:?} is already at sequence {}, so can't start from {}",
+                    args.checkpoint,
+                    checkpoint.sequence,
+                    sequence
+                );
+            }
+            checkpoint
+        }
+        None => {
+            if opts.input.is_none() {
+                bail!("There's no checkpoint yet, so we need an --input file");
+            }
+            let sequence = match args.sequence {
+                Some(sequence) => sequence,
+                None => replication.state()?.sequence,
+            };
+            let input = opts.input()?;
+            let map = Map::from_reader(input.par_objs()?)?;
+            let checkpoint = Checkpoint { sequence, map };
+            checkpoint.save(&args.checkpoint)?;
+            checkpoint
+        }
+    };
+
+    let mut out = opts.output();
+    loop {
+        let state = replication.state()?;
+        info!(
+            "At sequence {}; feed is at {} ({})",
+            checkpoint.sequence,
+            state.sequence,
+            state.timestamp.as_deref().unwrap_or("?")
+        );
+
+        while checkpoint.sequence < state.sequence {
+            let sequence = checkpoint.sequence + 1;
+            let mut changes = Vec::new();
+            for it in replication.changes(sequence)? {
+                let (action, obj) = it.with_context(|| format!("read changes {}", sequence))?;
+                changes.extend(checkpoint.map.apply(action, obj));
+            }
+            checkpoint.sequence = sequence;
+            checkpoint.save(&args.checkpoint)?;
+
+            for changed in &changes {
+                out.write(&Change {
+                    sequence,
+                    action: changed.action,
+                    feature: changed.feature,
+                    id: osm_ref(changed.id),
+                    name: changed.name.as_deref(),
+                })?;
+            }
+            out.flush()?;
+        }
+
+        match args.poll {
+            Some(secs) => thread::sleep(Duration::from_secs(secs)),
+            None => break,
+        }
+    }
+    out.finish()?;
+
+    Ok(())
+}
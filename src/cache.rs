@@ -0,0 +1,339 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::input::Input;
+
+// Bump this whenever the layout of anything we cache changes, so that old
+// caches get rebuilt rather than mis-read.
+const VERSION: u32 = 5;
+const MAGIC: &[u8; 8] = b"osmrail\0";
+
+/// Saves things we've built from an input file (eg: the rail network), so
+/// later runs can skip re-reading the whole file. A cache entry is only
+/// used if it was built from the same version of this code, with the same
+/// settings, from a file with the same contents.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Header {
+    version: u32,
+    kind: String,
+    settings: Vec<u8>,
+    source: Source,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Source {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: u128,
+}
+
+impl Cache {
+    /// With no directory, nothing is cached, and `get_or_build` always
+    /// builds.
+    pub fn new(dir: Option<&Path>) -> Self {
+        Cache {
+            dir: dir.map(Path::to_owned),
+        }
+    }
+
+    /// Loads the `kind` of thing we built from `input` with `settings`, or
+    /// calls `build` to make it (and then saves it for next time).
+    pub fn get_or_build<T, S, F>(
+        &self,
+        kind: &str,
+        input: &Input,
+        settings: &S,
+        build: F,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        S: Serialize,
+        F: FnOnce() -> Result<T>,
+    {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return build(),
+        };
+        let path = dir.join(format!(
+            "{}.{}.cache",
+            input
+                .path()
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default(),
+            kind
+        ));
+        let settings = bincode::serialize(&(input.bbox(), settings))?;
+
+        // A cache we can't read (eg: it was cut short, or the layout
+        // changed without a version bump) is no worse than a stale one.
+        match self.load(&path, kind, &settings, input.path()) {
+            Ok(Some(val)) => {
+                info!("Loaded {} from {:?}", kind, path);
+                return Ok(val);
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Couldn't load cache {:?}, so rebuilding it: {:#}", path, e),
+        }
+
+        // Before building, so that if the input changes while we're at it,
+        // we don't save what we built from the old one as the new one.
+        let source = Source::of(input.path())?;
+        let val = build()?;
+        let header = Header {
+            version: VERSION,
+            kind: kind.to_string(),
+            settings,
+            source,
+        };
+        fs::create_dir_all(dir).with_context(|| format!("create {:?}", dir))?;
+        save_atomically(&path, |w| {
+            w.write_all(MAGIC)?;
+            bincode::serialize_into(&mut *w, &header)?;
+            bincode::serialize_into(&mut *w, &val)?;
+            Ok(())
+        })
+        .with_context(|| format!("save cache {:?}", path))?;
+        info!("Saved {} to {:?}", kind, path);
+
+        Ok(val)
+    }
+
+    fn load<T: DeserializeOwned>(
+        &self,
+        path: &Path,
+        kind: &str,
+        settings: &[u8],
+        src: &Path,
+    ) -> Result<Option<T>> {
+        let mut r = match File::open(path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            debug!("{:?} isn't a cache file", path);
+            return Ok(None);
+        }
+        let header: Header = bincode::deserialize_from(&mut r)?;
+        if header.version != VERSION || header.kind != kind || header.settings != settings {
+            debug!("{:?} was built with different settings", path);
+            return Ok(None);
+        }
+        if !header.source.matches(src)? {
+            debug!("{:?} was built from a different input", path);
+            return Ok(None);
+        }
+
+        Ok(Some(bincode::deserialize_from(&mut r)?))
+    }
+}
+
+impl Source {
+    fn of(path: &Path) -> Result<Self> {
+        let meta = fs::metadata(path).with_context(|| format!("stat {:?}", path))?;
+        Ok(Source {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            hash: hash_file(path)?,
+        })
+    }
+
+    // If the size and modification time are the same, we trust that the
+    // contents are too, and skip hashing the file.
+    fn matches(&self, path: &Path) -> Result<bool> {
+        let meta = fs::metadata(path).with_context(|| format!("stat {:?}", path))?;
+        if meta.len() != self.len {
+            return Ok(false);
+        }
+        if self.modified.is_some() && meta.modified().ok() == self.modified {
+            return Ok(true);
+        }
+        Ok(hash_file(path)? == self.hash)
+    }
+}
+
+fn hash_file(path: &Path) -> Result<u128> {
+    let mut hasher = Xxh3::new();
+    let mut f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        match f.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.digest128())
+}
+
+/// Writes to a temporary file next to `path`, and then renames it into
+/// place. So if we crash part way through, we're left with whatever was
+/// there before, rather than half of a new file.
+pub(crate) fn save_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp).with_context(|| format!("create {:?}", tmp))?);
+    write(&mut w)?;
+    w.into_inner()?.sync_all()?;
+
+    fs::rename(&tmp, path).with_context(|| format!("rename {:?} to {:?}", tmp, path))?;
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        fs::OpenOptions,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn rebuilds_what_it_cannot_load() {
+        let src = fixtures::temp_path("cache-input.osm");
+        let dir = fixtures::temp_path("cache");
+        fs::write(&src, "<osm/>").unwrap();
+        let input = Input::open(&src).unwrap();
+        let cache = Cache::new(Some(&dir));
+        let path = dir.join(format!(
+            "{}.thing.cache",
+            src.file_name().unwrap().to_string_lossy()
+        ));
+
+        let builds = Cell::new(0);
+        let get = |val: &str| -> Vec<String> {
+            cache
+                .get_or_build("thing", &input, &(), || {
+                    builds.set(builds.get() + 1);
+                    Ok(vec![val.to_string(); 100])
+                })
+                .unwrap()
+        };
+        assert_eq!(get("a")[0], "a");
+        assert_eq!(get("b")[0], "a");
+        assert_eq!(builds.get(), 1);
+
+        // Cut short part way through the contents.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len / 2)
+            .unwrap();
+        assert_eq!(get("c")[0], "c");
+        assert_eq!(builds.get(), 2);
+
+        // Cut short in the middle of the magic number.
+        fs::write(&path, &MAGIC[..4]).unwrap();
+        assert_eq!(get("d")[0], "d");
+        assert_eq!(builds.get(), 3);
+
+        // Something else entirely after the header.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        bytes.extend([0xff; 64]);
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(get("e")[0], "e");
+        assert_eq!(get("f")[0], "e");
+        assert_eq!(builds.get(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&src).unwrap();
+    }
+
+    // Builds (or loads) a thing from `input`, counting the builds.
+    fn get(cache: &Cache, input: &Input, settings: u32, builds: &Cell<u32>) -> u32 {
+        cache
+            .get_or_build("thing", input, &settings, || {
+                builds.set(builds.get() + 1);
+                Ok(builds.get())
+            })
+            .unwrap()
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn rebuilds_when_the_input_changes() {
+        let src = fixtures::temp_path("cache-changes.osm");
+        let dir = fixtures::temp_path("cache-changes");
+        let cache = Cache::new(Some(&dir));
+        let builds = Cell::new(0);
+        fs::write(&src, "<osm>a</osm>").unwrap();
+        touch(&src, 1_000_000);
+        let input = Input::open(&src).unwrap();
+
+        assert_eq!(get(&cache, &input, 1, &builds), 1);
+        assert_eq!(get(&cache, &input, 1, &builds), 1);
+
+        // The same size, but different contents.
+        fs::write(&src, "<osm>b</osm>").unwrap();
+        touch(&src, 2_000_000);
+        assert_eq!(get(&cache, &input, 1, &builds), 2);
+        assert_eq!(get(&cache, &input, 1, &builds), 2);
+
+        // Only the time changed, so the contents hash the same.
+        touch(&src, 3_000_000);
+        assert_eq!(get(&cache, &input, 1, &builds), 2);
+
+        // Different settings, or only part of the input.
+        assert_eq!(get(&cache, &input, 2, &builds), 3);
+        let clipped = Input::open(&src)
+            .unwrap()
+            .clip(Some("-1,50,1,52".parse().unwrap()));
+        assert_eq!(get(&cache, &clipped, 2, &builds), 4);
+        assert_eq!(get(&cache, &clipped, 2, &builds), 4);
+        assert_eq!(get(&cache, &input, 2, &builds), 5);
+
+        // Changed while we were building, so what we built is already out
+        // of date.
+        let built = cache
+            .get_or_build("thing", &input, &3, || {
+                fs::write(&src, "<osm>c</osm>").unwrap();
+                touch(&src, 4_000_000);
+                Ok(0)
+            })
+            .unwrap();
+        assert_eq!(built, 0);
+        assert_eq!(get(&cache, &input, 3, &builds), 6);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&src).unwrap();
+    }
+}
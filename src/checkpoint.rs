@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{cache::save_atomically, network::Map};
+
+/// The rail network, along with the last replication sequence number that
+/// has been applied to it. These are saved together so that one can never
+/// get ahead of the other.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub sequence: u64,
+    pub map: Map,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("open {:?}", path)),
+        };
+        let checkpoint = bincode::deserialize_from(BufReader::new(f))
+            .with_context(|| format!("read checkpoint {:?}", path))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Saves atomically, so if we crash part way through, we're left with
+    /// the previous checkpoint rather than half of a new one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_atomically(path, |w| {
+            bincode::serialize_into(w, self).context("write checkpoint")?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn saves_and_loads() {
+        let path = fixtures::temp_path("checkpoint");
+        assert!(Checkpoint::load(&path).unwrap().is_none());
+
+        let map = Map::from_reader(fixtures::objs().into_iter().map(Ok)).unwrap();
+        let checkpoint = Checkpoint { sequence: 42, map };
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.sequence, 42);
+        assert_eq!(loaded.map.objs, checkpoint.map.objs);
+        assert_eq!(loaded.map.vertex_by_osm_id, checkpoint.map.vertex_by_osm_id);
+        assert_eq!(loaded.map.node_id_by_crs, checkpoint.map.node_id_by_crs);
+
+        // Saving again replaces it, and leaves nothing else behind.
+        Checkpoint {
+            sequence: 43,
+            map: loaded.map,
+        }
+        .save(&path)
+        .unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap().unwrap().sequence, 43);
+        assert!(!path.with_extension("tmp").exists());
+
+        // Something that isn't a checkpoint is an error, rather than a
+        // reason to start again from scratch.
+        fs::write(&path, b"not a checkpoint").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,716 @@
+use std::{collections::HashMap, fmt, mem::size_of_val};
+
+use anyhow::{Context, Result};
+use log::warn;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, Tags, Way};
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String;
+
+use crate::{
+    filter::is_network,
+    input::{resolve_nodes, Input},
+    network::is_station,
+};
+
+/// A vertex of a `CompactMap`. Vertices are numbered densely from zero, in
+/// order of their OSM node id.
+pub type Vertex = u32;
+
+/// The same railway network as `network::Map`, but laid out to use as
+/// little memory as we can get away with, so that it's practical to load
+/// a whole continent. It can't be changed once it's built.
+///
+/// Rather than keeping whole `OsmObj`s around, we keep just the parts we
+/// use: the tags (with each distinct string stored once), the nodes of each
+/// way, and the nodes in each stop area. Coordinates are kept in the same
+/// fixed-point form as the PBF format, and the edges are stored as a
+/// compressed sparse row array, rather than petgraph's linked lists.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CompactMap {
+    node_ids: Vec<i64>,
+    coords: Vec<Coord>,
+    // One bit per vertex, set for stations, as we look that up a lot.
+    stations: Vec<u64>,
+    // The edges from vertex `v` are at `edge_start[v]..edge_start[v + 1]`.
+    edge_start: Vec<u32>,
+    edge_target: Vec<Vertex>,
+    edge_via: Vec<u32>,
+    // Sorted by id, so we can look them up with a binary search.
+    elements: Vec<Element>,
+    tags: Vec<(u32, u32)>,
+    members: Vec<Vertex>,
+    strings: Vec<String>,
+    vertex_by_crs: Vec<(String, Vertex)>,
+}
+
+/// A position in decimicro-degrees (ie: 1e-7 degrees), as stored in PBF
+/// files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Coord {
+    pub lat: i32,
+    pub lon: i32,
+}
+
+// Anything we've kept the tags of.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Element {
+    id: OsmId,
+    tags: (u32, u32),
+    members: (u32, u32),
+}
+
+/// How much memory each part of a `CompactMap` takes up.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub vertices: usize,
+    pub edges: usize,
+    pub elements: usize,
+    pub strings: usize,
+    pub vertex_bytes: usize,
+    pub edge_bytes: usize,
+    pub element_bytes: usize,
+    pub string_bytes: usize,
+}
+
+#[derive(Default)]
+struct Builder {
+    vertex_by_id: HashMap<i64, Vertex>,
+    node_ids: Vec<i64>,
+    coords: Vec<Coord>,
+    stations: Vec<Vertex>,
+    edges: Vec<(Vertex, Vertex, u32)>,
+    elements: Vec<Element>,
+    tags: Vec<(u32, u32)>,
+    members: Vec<Vertex>,
+    strings: Vec<String>,
+    string_index: HashMap<String, u32>,
+    crs: Vec<(String, Vertex)>,
+}
+
+impl Coord {
+    // Nodes we've only seen as part of a way, and never read ourselves.
+    pub const UNKNOWN: Coord = Coord {
+        lat: i32::MIN,
+        lon: i32::MIN,
+    };
+
+    pub fn is_known(&self) -> bool {
+        *self != Self::UNKNOWN
+    }
+
+    pub fn lat(&self) -> f64 {
+        f64::from(self.lat) * 1e-7
+    }
+
+    pub fn lon(&self) -> f64 {
+        f64::from(self.lon) * 1e-7
+    }
+}
+
+impl CompactMap {
+    /// Picks out the same objects as `network::Map::from_reader`.
+    pub fn from_reader(objs: impl Iterator<Item = Result<OsmObj>>) -> Result<Self> {
+        let mut builder = Builder::default();
+        for it in objs {
+            match it.context("Read item")? {
+                OsmObj::Node(n) => builder.add_node(n),
+                OsmObj::Way(w) => builder.add_way(w),
+                OsmObj::Relation(r) => builder.add_rel(r),
+            }
+        }
+        Ok(builder.finish())
+    }
+
+    /// Builds the map, and then goes back over the file for the positions
+    /// of all the untagged nodes along the ways.
+    pub fn from_input(input: &Input) -> Result<Self> {
+        let mut map = Self::from_reader(input.par_objs()?)?;
+        let missing = map.resolve_coords(input.par_objs()?)?;
+        if missing > 0 {
+            warn!("{} way nodes are missing from {:?}", missing, input.path());
+        }
+        Ok(map)
+    }
+
+    /// Fills in the coordinates of any vertices we only know about as part
+    /// of a way (or stop area), from a second pass over the input. Returns
+    /// how many we still don't have, which is normal for ways that cross
+    /// the edge of an extract.
+    pub fn resolve_coords(&mut self, objs: impl Iterator<Item = Result<OsmObj>>) -> Result<usize> {
+        let missing = self.coords.iter().filter(|c| !c.is_known()).count();
+        resolve_nodes(objs, missing, |node| {
+            let v = match self.vertex(node.id) {
+                Some(v) => v,
+                None => return false,
+            };
+            let coord = &mut self.coords[v as usize];
+            if coord.is_known() {
+                return false;
+            }
+            *coord = Coord {
+                lat: node.decimicro_lat,
+                lon: node.decimicro_lon,
+            };
+            true
+        })
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn vertex(&self, id: NodeId) -> Option<Vertex> {
+        self.node_ids.binary_search(&id.0).ok().map(|v| v as Vertex)
+    }
+
+    pub fn node_id(&self, v: Vertex) -> NodeId {
+        NodeId(self.node_ids[v as usize])
+    }
+
+    /// Where `v` is, if we've seen the node itself.
+    pub fn coord(&self, v: Vertex) -> Option<Coord> {
+        Some(self.coords[v as usize]).filter(Coord::is_known)
+    }
+
+    /// The vertices joined to `v`, and the way or stop area that joins them.
+    pub fn edges(&self, v: Vertex) -> impl Iterator<Item = (Vertex, OsmId)> + '_ {
+        let start = self.edge_start[v as usize] as usize;
+        let end = self.edge_start[v as usize + 1] as usize;
+        self.edge_target[start..end]
+            .iter()
+            .zip(&self.edge_via[start..end])
+            .map(move |(&target, &via)| (target, self.elements[via as usize].id))
+    }
+
+    pub fn vertex_by_crs(&self, crs: &str) -> Option<Vertex> {
+        self.vertex_by_crs
+            .binary_search_by(|(c, _)| c.as_str().cmp(crs))
+            .ok()
+            .map(|i| self.vertex_by_crs[i].1)
+    }
+
+    pub fn crses(&self) -> impl Iterator<Item = &str> + '_ {
+        self.vertex_by_crs.iter().map(|(crs, _)| crs.as_str())
+    }
+
+    /// Rebuilds the object with the given id, if we kept it. Only the parts
+    /// we keep come back, so relations only have their node members, with
+    /// no roles.
+    pub fn obj(&self, id: OsmId) -> Option<OsmObj> {
+        let el = &self.elements[self.elements.binary_search_by_key(&id, |e| e.id).ok()?];
+        let mut tags = Tags::new();
+        for &(k, v) in &self.tags[el.tags.0 as usize..el.tags.1 as usize] {
+            tags.insert(
+                self.strings[k as usize].clone(),
+                self.strings[v as usize].clone(),
+            );
+        }
+        let members = &self.members[el.members.0 as usize..el.members.1 as usize];
+        let obj = match id {
+            OsmId::Node(id) => {
+                let coord = self.vertex(id).map(|v| self.coords[v as usize])?;
+                Node {
+                    id,
+                    tags,
+                    decimicro_lat: coord.lat,
+                    decimicro_lon: coord.lon,
+                }
+                .into()
+            }
+            OsmId::Way(id) => Way {
+                id,
+                tags,
+                nodes: members.iter().map(|&v| self.node_id(v)).collect(),
+            }
+            .into(),
+            OsmId::Relation(id) => Relation {
+                id,
+                tags,
+                refs: members
+                    .iter()
+                    .map(|&v| Ref {
+                        member: self.node_id(v).into(),
+                        role: Default::default(),
+                    })
+                    .collect(),
+            }
+            .into(),
+        };
+        Some(obj)
+    }
+
+    /// The node at `v`. Nodes we didn't keep the tags of come back
+    /// untagged, as long as we know where they are.
+    pub fn obj_by_vertex(&self, v: Vertex) -> Option<OsmObj> {
+        let id = self.node_id(v);
+        self.obj(id.into()).or_else(|| {
+            let coord = self.coord(v)?;
+            Some(
+                Node {
+                    id,
+                    tags: Tags::new(),
+                    decimicro_lat: coord.lat,
+                    decimicro_lon: coord.lon,
+                }
+                .into(),
+            )
+        })
+    }
+
+    /// Whether `v` is a station (see `network::is_station`).
+    pub fn is_station(&self, v: Vertex) -> bool {
+        self.stations[v as usize / 64] & (1 << (v % 64)) != 0
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            vertices: self.node_ids.len(),
+            edges: self.edge_target.len() / 2,
+            elements: self.elements.len(),
+            strings: self.strings.len(),
+            vertex_bytes: size_of_val(&*self.node_ids)
+                + size_of_val(&*self.coords)
+                + size_of_val(&*self.stations),
+            edge_bytes: size_of_val(&*self.edge_start)
+                + size_of_val(&*self.edge_target)
+                + size_of_val(&*self.edge_via),
+            element_bytes: size_of_val(&*self.elements)
+                + size_of_val(&*self.tags)
+                + size_of_val(&*self.members),
+            string_bytes: size_of_val(&*self.strings)
+                + self.strings.iter().map(string_bytes).sum::<usize>()
+                + size_of_val(&*self.vertex_by_crs)
+                + self
+                    .vertex_by_crs
+                    .iter()
+                    .map(|(crs, _)| string_bytes(crs))
+                    .sum::<usize>(),
+        }
+    }
+}
+
+impl Builder {
+    fn add_node(&mut self, node: Node) {
+        if !is_network(&node.tags) {
+            return;
+        }
+        let v = self.vertex(node.id);
+        self.coords[v as usize] = Coord {
+            lat: node.decimicro_lat,
+            lon: node.decimicro_lon,
+        };
+        if let Some(crs) = node.tags.get("ref:crs") {
+            self.crs.push((crs.clone(), v));
+        }
+        if is_station(&node.tags) {
+            self.stations.push(v);
+        }
+        self.add_element(node.id.into(), &node.tags, Vec::new());
+    }
+
+    fn add_way(&mut self, w: Way) {
+        if !is_network(&w.tags) {
+            return;
+        }
+        let nodes = w.nodes.iter().map(|&n| self.vertex(n)).collect::<Vec<_>>();
+        let el = self.elements.len() as u32;
+        for (&a, &b) in nodes.iter().zip(nodes.iter().skip(1)) {
+            self.edges.push((a, b, el));
+        }
+        self.add_element(w.id.into(), &w.tags, nodes);
+    }
+
+    fn add_rel(&mut self, r: Relation) {
+        if r.tags.get("public_transport").map(|s| &**s) != Some("stop_area") {
+            return;
+        }
+        let mut nodes = r
+            .refs
+            .iter()
+            .flat_map(|r| r.member.node())
+            .collect::<Vec<_>>();
+        nodes.sort();
+        nodes.dedup();
+        let nodes = nodes
+            .into_iter()
+            .map(|n| self.vertex(n))
+            .collect::<Vec<_>>();
+        let el = self.elements.len() as u32;
+        for (i, &a) in nodes.iter().enumerate() {
+            for &b in &nodes[i + 1..] {
+                self.edges.push((a, b, el));
+            }
+        }
+        self.add_element(r.id.into(), &r.tags, nodes);
+    }
+
+    fn add_element(&mut self, id: OsmId, tags: &Tags, members: Vec<Vertex>) {
+        let tags_start = self.tags.len() as u32;
+        for (k, v) in tags.iter() {
+            let pair = (self.string(k), self.string(v));
+            self.tags.push(pair);
+        }
+        let members_start = self.members.len() as u32;
+        self.members.extend(members);
+        self.elements.push(Element {
+            id,
+            tags: (tags_start, self.tags.len() as u32),
+            members: (members_start, self.members.len() as u32),
+        });
+    }
+
+    fn vertex(&mut self, id: NodeId) -> Vertex {
+        let Self {
+            vertex_by_id,
+            node_ids,
+            coords,
+            ..
+        } = self;
+        *vertex_by_id.entry(id.0).or_insert_with(|| {
+            node_ids.push(id.0);
+            coords.push(Coord::UNKNOWN);
+            (node_ids.len() - 1) as Vertex
+        })
+    }
+
+    fn string(&mut self, s: &str) -> u32 {
+        let Self {
+            strings,
+            string_index,
+            ..
+        } = self;
+        *string_index.entry(s.into()).or_insert_with(|| {
+            strings.push(s.into());
+            (strings.len() - 1) as u32
+        })
+    }
+
+    // Renumbers everything so that vertices and elements are in id order,
+    // and packs the edges into rows.
+    fn finish(self) -> CompactMap {
+        let Builder {
+            vertex_by_id,
+            node_ids,
+            coords,
+            stations: station_list,
+            edges,
+            mut elements,
+            mut tags,
+            members,
+            mut strings,
+            string_index,
+            mut crs,
+        } = self;
+        drop(vertex_by_id);
+        drop(string_index);
+        tags.shrink_to_fit();
+        strings.shrink_to_fit();
+
+        let mut order = (0..node_ids.len() as Vertex).collect::<Vec<_>>();
+        order.sort_by_key(|&v| node_ids[v as usize]);
+        let mut renumber = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            renumber[old as usize] = new as Vertex;
+        }
+        let node_ids = order
+            .iter()
+            .map(|&v| node_ids[v as usize])
+            .collect::<Vec<_>>();
+        let coords = order
+            .iter()
+            .map(|&v| coords[v as usize])
+            .collect::<Vec<_>>();
+        let members = members
+            .into_iter()
+            .map(|v| renumber[v as usize])
+            .collect::<Vec<_>>();
+        let mut stations = vec![0u64; node_ids.len().div_ceil(64)];
+        for v in station_list {
+            let v = renumber[v as usize];
+            stations[v as usize / 64] |= 1 << (v % 64);
+        }
+
+        let mut el_order = (0..elements.len() as u32).collect::<Vec<_>>();
+        el_order.sort_by_key(|&e| elements[e as usize].id);
+        let mut el_renumber = vec![0; el_order.len()];
+        for (new, &old) in el_order.iter().enumerate() {
+            el_renumber[old as usize] = new as u32;
+        }
+        elements.sort_by_key(|e| e.id);
+        elements.shrink_to_fit();
+
+        let mut edge_start = vec![0u32; node_ids.len() + 1];
+        for &(a, b, _) in &edges {
+            edge_start[renumber[a as usize] as usize + 1] += 1;
+            edge_start[renumber[b as usize] as usize + 1] += 1;
+        }
+        for v in 0..node_ids.len() {
+            edge_start[v + 1] += edge_start[v];
+        }
+        let mut next = edge_start.clone();
+        let mut edge_target = vec![0; edges.len() * 2];
+        let mut edge_via = vec![0; edges.len() * 2];
+        for (a, b, via) in edges {
+            let (a, b) = (renumber[a as usize], renumber[b as usize]);
+            let via = el_renumber[via as usize];
+            for (from, to) in [(a, b), (b, a)] {
+                let slot = next[from as usize] as usize;
+                edge_target[slot] = to;
+                edge_via[slot] = via;
+                next[from as usize] += 1;
+            }
+        }
+
+        // As with `network::Map`, the last station with a given code wins.
+        for (_, v) in crs.iter_mut() {
+            *v = renumber[*v as usize];
+        }
+        crs.reverse();
+        crs.sort_by(|a, b| a.0.cmp(&b.0));
+        crs.dedup_by(|a, b| a.0 == b.0);
+
+        CompactMap {
+            node_ids,
+            coords,
+            stations,
+            edge_start,
+            edge_target,
+            edge_via,
+            elements,
+            tags,
+            members,
+            strings,
+            vertex_by_crs: crs,
+        }
+    }
+}
+
+// Short strings are stored inline.
+fn string_bytes(s: &String) -> usize {
+    if s.is_inline() {
+        0
+    } else {
+        s.len()
+    }
+}
+
+impl Stats {
+    pub fn total_bytes(&self) -> usize {
+        self.vertex_bytes + self.edge_bytes + self.element_bytes + self.string_bytes
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "vertices\t{}\t{}", self.vertices, self.vertex_bytes)?;
+        writeln!(f, "edges\t{}\t{}", self.edges, self.edge_bytes)?;
+        writeln!(f, "elements\t{}\t{}", self.elements, self.element_bytes)?;
+        writeln!(f, "strings\t{}\t{}", self.strings, self.string_bytes)?;
+        write!(f, "total\t\t{}", self.total_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use osmpbfreader::{RelationId, WayId};
+
+    use super::*;
+    use crate::fixtures;
+
+    fn map(objs: &[OsmObj]) -> CompactMap {
+        CompactMap::from_reader(objs.iter().cloned().map(Ok)).unwrap()
+    }
+
+    fn node(id: i64, lat: i32, lon: i32, tags: &[(&str, &str)]) -> OsmObj {
+        let mut node = Node {
+            id: NodeId(id),
+            tags: Tags::new(),
+            decimicro_lat: lat,
+            decimicro_lon: lon,
+        };
+        for &(k, v) in tags {
+            node.tags.insert(k.into(), v.into());
+        }
+        node.into()
+    }
+
+    fn rail(id: i64, nodes: &[i64]) -> OsmObj {
+        let mut tags = Tags::new();
+        tags.insert("railway".into(), "rail".into());
+        Way {
+            id: WayId(id),
+            tags,
+            nodes: nodes.iter().map(|&n| NodeId(n)).collect(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn keeps_the_edges_that_went_in() {
+        // Out of id order, so that everything gets renumbered.
+        let mut objs = vec![
+            node(30, 0, 0, &[("railway", "station")]),
+            rail(12, &[30, 10, 20]),
+            rail(11, &[20, 40]),
+        ];
+        objs.extend(fixtures::objs());
+        let map = map(&objs);
+
+        let mut expected = BTreeSet::new();
+        for obj in &objs {
+            let nodes = match obj {
+                OsmObj::Way(w) if w.tags.contains_key("railway") => {
+                    w.nodes.windows(2).map(|p| (p[0], p[1])).collect::<Vec<_>>()
+                }
+                OsmObj::Relation(r) => {
+                    let ns = r
+                        .refs
+                        .iter()
+                        .flat_map(|r| r.member.node())
+                        .collect::<Vec<_>>();
+                    let mut pairs = Vec::new();
+                    for (i, &a) in ns.iter().enumerate() {
+                        pairs.extend(ns[i + 1..].iter().map(|&b| (a, b)));
+                    }
+                    pairs
+                }
+                _ => continue,
+            };
+            for (a, b) in nodes {
+                expected.insert((a, b, obj.id()));
+                expected.insert((b, a, obj.id()));
+            }
+        }
+
+        let mut got = BTreeSet::new();
+        for v in 0..map.vertex_count() as Vertex {
+            for (w, via) in map.edges(v) {
+                got.insert((map.node_id(v), map.node_id(w), via));
+            }
+        }
+        assert_eq!(got, expected);
+        assert!(got.contains(&(NodeId(1), NodeId(5), RelationId(20).into())));
+        assert_eq!(map.stats().edges, expected.len() / 2);
+
+        // Vertices are in id order.
+        let ids = (0..map.vertex_count() as Vertex)
+            .map(|v| map.node_id(v).0)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5, 10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn stores_each_string_once() {
+        let objs = fixtures::objs();
+        let map = map(&objs);
+        // "railway" and "name" are used more than once, as is "station".
+        let mut strings = BTreeSet::new();
+        for obj in &objs {
+            for (k, v) in obj.tags().iter() {
+                strings.insert(k.to_string());
+                strings.insert(v.to_string());
+            }
+        }
+        assert_eq!(map.stats().strings, strings.len());
+        assert_eq!(map.strings.len(), strings.len());
+
+        for obj in &objs {
+            if let Some(kept) = map.obj(obj.id()) {
+                assert_eq!(kept.tags(), obj.tags());
+            }
+        }
+        assert_eq!(map.obj(WayId(99).into()), None);
+    }
+
+    #[test]
+    fn keeps_coordinates_exactly() {
+        let objs = vec![
+            node(1, 515_007_292, -1_246_254, &[("railway", "station")]),
+            node(2, -899_999_999, 1_799_999_999, &[("railway", "halt")]),
+            rail(10, &[1, 2, 3]),
+        ];
+        let map = map(&objs);
+        let coord = map.coord(map.vertex(NodeId(1)).unwrap()).unwrap();
+        assert_eq!((coord.lat, coord.lon), (515_007_292, -1_246_254));
+        assert!((coord.lat() - 51.5007292).abs() < 1e-12);
+        assert!((coord.lon() - -0.1246254).abs() < 1e-12);
+        let coord = map.coord(map.vertex(NodeId(2)).unwrap()).unwrap();
+        assert!((coord.lat() - -89.9999999).abs() < 1e-12);
+        assert!((coord.lon() - 179.9999999).abs() < 1e-12);
+        for (id, obj) in [(1, &objs[0]), (2, &objs[1])] {
+            assert_eq!(
+                map.obj_by_vertex(map.vertex(NodeId(id)).unwrap()).as_ref(),
+                Some(obj)
+            );
+        }
+
+        // Only seen as part of the way, until we go back for it.
+        let mut map = map;
+        let v = map.vertex(NodeId(3)).unwrap();
+        assert_eq!(map.coord(v), None);
+        let missing = map
+            .resolve_coords(vec![Ok(node(3, 10, -10, &[]))].into_iter())
+            .unwrap();
+        assert_eq!(missing, 0);
+        assert_eq!(map.coord(v), Some(Coord { lat: 10, lon: -10 }));
+    }
+
+    #[test]
+    fn knows_which_vertices_are_stations() {
+        // More than one word of the bitset, with the stations read in the
+        // opposite order to their ids.
+        let stations = [0, 63, 64, 127, 128, 129];
+        let mut objs = Vec::new();
+        for id in (0..130).rev() {
+            let tags: &[_] = if stations.contains(&id) {
+                &[("railway", "station")]
+            } else {
+                &[("railway", "signal")]
+            };
+            objs.push(node(id, 0, 0, tags));
+        }
+        objs.push(rail(1000, &(0..130).collect::<Vec<_>>()));
+        let map = map(&objs);
+        for v in 0..map.vertex_count() as Vertex {
+            let id = map.node_id(v).0;
+            assert_eq!(map.is_station(v), stations.contains(&id), "n{}", id);
+        }
+    }
+
+    #[test]
+    fn the_last_station_with_a_code_wins() {
+        let objs = vec![
+            node(7, 0, 0, &[("railway", "station"), ("ref:crs", "AAA")]),
+            node(3, 0, 0, &[("railway", "station"), ("ref:crs", "AAA")]),
+            node(5, 0, 0, &[("railway", "station"), ("ref:crs", "BBB")]),
+        ];
+        let map = map(&objs);
+        assert_eq!(map.vertex_by_crs("AAA"), map.vertex(NodeId(3)));
+        assert_eq!(map.vertex_by_crs("BBB"), map.vertex(NodeId(5)));
+        assert_eq!(map.vertex_by_crs("CCC"), None);
+        assert_eq!(map.crses().collect::<Vec<_>>(), vec!["AAA", "BBB"]);
+    }
+
+    #[test]
+    fn adds_up_the_memory_used() {
+        let map = map(&fixtures::objs());
+        let stats = map.stats();
+        assert_eq!(stats.vertices, 5);
+        // The three tagged nodes, the way and the stop area.
+        assert_eq!(stats.elements, 5);
+        // An id and a coordinate for each vertex, and one word of stations.
+        assert_eq!(stats.vertex_bytes, 5 * 8 + 5 * 8 + 8);
+        // A start for each vertex (and one past the end), and a target and
+        // a way for each end of each edge.
+        assert_eq!(stats.edge_bytes, 6 * 4 + stats.edges * 2 * (4 + 4));
+        assert_eq!(
+            stats.total_bytes(),
+            stats.vertex_bytes + stats.edge_bytes + stats.element_bytes + stats.string_bytes
+        );
+        let text = stats.to_string();
+        assert!(text.starts_with("vertices\t5\t88\n"));
+        assert!(text.ends_with(&format!("total\t\t{}", stats.total_bytes())));
+    }
+}
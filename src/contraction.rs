@@ -0,0 +1,591 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compact::{CompactMap, Vertex},
+    routing::{length, Queued},
+};
+
+/// A contraction hierarchy over the stations and junctions of a
+/// `CompactMap`, for answering lots of distance queries quickly, after
+/// Geisberger et al., "Contraction Hierarchies: Faster and Simpler
+/// Hierarchical Routing in Road Networks" (2008).
+///
+/// We boil the network down to the stations, junctions and ends of lines,
+/// joined by the track between them, and then take those out one at a
+/// time, least important first. Wherever the only shortest way between two
+/// of the neighbours of the one we're taking out went through it, we join
+/// them up with a shortcut. A query then only has to search upwards (to
+/// more important vertices) from each end, which only ever visits a few
+/// hundred vertices.
+///
+/// The hierarchy only has the track in it. As with `ShortestPaths`, we go
+/// between the members of a stop area only to get on the track at the
+/// start, or off it onto a station at the end, so we start each query from
+/// everything in a stop area with either end.
+///
+/// This only gives distances between the vertices we kept (see `contains`),
+/// not the routes themselves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hierarchy {
+    // The vertices we kept, in order, so that where one is in here is its
+    // id in the rest of the hierarchy.
+    vertices: Vec<Vertex>,
+    // The edges up from each vertex to more important ones, as rows.
+    first: Vec<u32>,
+    target: Vec<u32>,
+    weight: Vec<f64>,
+    stop_areas: StopAreas,
+    // The ids of the stations in stop areas, which we can get off onto.
+    stations: HashSet<Vertex>,
+}
+
+// A graph we can take vertices out of, by their id in the hierarchy.
+type Graph = Vec<HashMap<Vertex, f64>>;
+
+// The other members of the stop areas each vertex is in, by id, and how
+// far away they are.
+type StopAreas = HashMap<Vertex, Vec<(Vertex, f64)>>;
+
+/// How many vertices a witness search (for a way round the vertex we're
+/// taking out) can visit before it gives up and we add a shortcut anyway.
+/// Shortcuts we didn't need make queries a little slower, but never wrong.
+const WITNESS_LIMIT: usize = 500;
+
+impl Hierarchy {
+    pub fn new(map: &CompactMap) -> Self {
+        let (vertices, mut graph, stop_areas) = core(map);
+        let stations = stop_areas
+            .keys()
+            .cloned()
+            .filter(|&id| map.is_station(vertices[id as usize]))
+            .collect();
+        let edges = graph.iter().map(HashMap::len).sum::<usize>() / 2;
+        info!(
+            "Contracting {} stations and junctions, with {} edges between them",
+            vertices.len(),
+            edges
+        );
+
+        let up = contract(&mut graph);
+        let mut first = Vec::with_capacity(vertices.len() + 1);
+        let mut target = Vec::new();
+        let mut weight = Vec::new();
+        for edges in up {
+            first.push(target.len() as u32);
+            for (t, w) in edges {
+                target.push(t);
+                weight.push(w);
+            }
+        }
+        first.push(target.len() as u32);
+        info!("Added {} shortcuts", target.len().saturating_sub(edges));
+
+        Hierarchy {
+            vertices,
+            first,
+            target,
+            weight,
+            stop_areas,
+            stations,
+        }
+    }
+
+    /// How many vertices we kept.
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Whether we can answer queries to and from `v`: we keep stations
+    /// (and anything else with a CRS code, or in a stop area), junctions
+    /// and the ends of lines, but not the vertices in between.
+    pub fn contains(&self, v: Vertex) -> bool {
+        self.id(v).is_some()
+    }
+
+    /// The distance along the track from `from` to `to`, in metres, the
+    /// same as `ShortestPaths` would find. `None` if there's no way there,
+    /// or we didn't keep one of them.
+    pub fn distance(&self, from: Vertex, to: Vertex) -> Option<f64> {
+        let (s, t) = (self.id(from)?, self.id(to)?);
+        if s == t {
+            return Some(0.0);
+        }
+        let stop_area = |v| self.stop_areas.get(&v).into_iter().flatten().cloned();
+        let onto = stop_area(s);
+        let off = Some(t)
+            .filter(|t| self.stations.contains(t))
+            .into_iter()
+            .flat_map(stop_area);
+        let mut searches = [
+            Search::new(Some((s, 0.0)).into_iter().chain(onto)),
+            Search::new(Some((t, 0.0)).into_iter().chain(off)),
+        ];
+        let mut best = f64::INFINITY;
+        loop {
+            // Carry on with whichever search has the nearest vertex next,
+            // until neither of them can find anything shorter.
+            let side = match (searches[0].peek(), searches[1].peek()) {
+                (Some(a), Some(b)) if a.min(b) < best => usize::from(b < a),
+                (Some(a), None) if a < best => 0,
+                (None, Some(b)) if b < best => 1,
+                _ => break,
+            };
+            let (v, d) = match searches[side].settle(self) {
+                Some(next) => next,
+                None => continue,
+            };
+            if let Some(&other) = searches[1 - side].dist.get(&v) {
+                best = best.min(d + other);
+            }
+        }
+        Some(best).filter(|d| d.is_finite())
+    }
+
+    fn id(&self, v: Vertex) -> Option<Vertex> {
+        self.vertices.binary_search(&v).ok().map(|i| i as Vertex)
+    }
+
+    fn up(&self, id: Vertex) -> impl Iterator<Item = (Vertex, f64)> + '_ {
+        let start = self.first[id as usize] as usize;
+        let end = self.first[id as usize + 1] as usize;
+        self.target[start..end]
+            .iter()
+            .cloned()
+            .zip(self.weight[start..end].iter().cloned())
+    }
+}
+
+// Dijkstra's algorithm upwards through the hierarchy, from one end of a
+// query.
+struct Search {
+    dist: HashMap<Vertex, f64>,
+    queue: BinaryHeap<Queued>,
+}
+
+impl Search {
+    fn new(sources: impl IntoIterator<Item = (Vertex, f64)>) -> Self {
+        let mut search = Search {
+            dist: HashMap::new(),
+            queue: BinaryHeap::new(),
+        };
+        for (v, d) in sources {
+            if d < search.dist.get(&v).cloned().unwrap_or(f64::INFINITY) {
+                search.dist.insert(v, d);
+                search.queue.push(Queued(d, v));
+            }
+        }
+        search
+    }
+
+    fn peek(&self) -> Option<f64> {
+        self.queue.peek().map(|q| q.0)
+    }
+
+    // Takes the nearest vertex off the queue, and if we haven't already
+    // found a shorter way there, goes on up from it. If there's a shorter
+    // way to it down from a more important vertex, then this can't be on
+    // the shortest route, so we don't bother going on from it ("stall on
+    // demand").
+    fn settle(&mut self, hierarchy: &Hierarchy) -> Option<(Vertex, f64)> {
+        let Queued(d, v) = self.queue.pop()?;
+        if d > self.dist[&v] {
+            return None;
+        }
+        let stalled = hierarchy
+            .up(v)
+            .any(|(above, w)| self.dist.get(&above).map(|&a| a + w < d).unwrap_or(false));
+        if stalled {
+            return None;
+        }
+        for (next, w) in hierarchy.up(v) {
+            let nd = d + w;
+            if nd < self.dist.get(&next).cloned().unwrap_or(f64::INFINITY) {
+                self.dist.insert(next, nd);
+                self.queue.push(Queued(nd, next));
+            }
+        }
+        Some((v, d))
+    }
+}
+
+// The stations, junctions and ends of lines, and how far it is along the
+// track between neighbouring ones, and to the other members of the stop
+// areas they're in.
+fn core(map: &CompactMap) -> (Vec<Vertex>, Graph, StopAreas) {
+    let neighbours = |v: Vertex, ways: bool| {
+        let mut ns = HashMap::<Vertex, f64>::new();
+        for (w, via) in map.edges(v) {
+            let len = length(map, v, w);
+            if via.is_way() == ways && w != v && len.is_finite() {
+                let d = ns.entry(w).or_insert(len);
+                *d = d.min(len);
+            }
+        }
+        ns
+    };
+    let crses = map
+        .crses()
+        .filter_map(|crs| map.vertex_by_crs(crs))
+        .collect::<HashSet<_>>();
+    let mut vertices = Vec::new();
+    for v in 0..map.vertex_count() as Vertex {
+        let degree = neighbours(v, true).len();
+        if (degree > 0 && degree != 2)
+            || map.is_station(v)
+            || crses.contains(&v)
+            || !neighbours(v, false).is_empty()
+        {
+            vertices.push(v);
+        }
+    }
+    let id = |v: Vertex| vertices.binary_search(&v).ok().map(|i| i as Vertex);
+
+    let mut stop_areas = HashMap::new();
+    for (a, &v) in vertices.iter().enumerate() {
+        let members = neighbours(v, false)
+            .into_iter()
+            .filter_map(|(w, len)| Some((id(w)?, len)))
+            .collect::<Vec<_>>();
+        if !members.is_empty() {
+            stop_areas.insert(a as Vertex, members);
+        }
+    }
+
+    let mut graph = vec![HashMap::new(); vertices.len()];
+    for (a, &v) in vertices.iter().enumerate() {
+        for (first, len) in neighbours(v, true) {
+            // Follow the track through the vertices in between, which only
+            // have one way on.
+            let (mut prev, mut here, mut total) = (v, first, len);
+            let b = loop {
+                if let Some(b) = id(here) {
+                    break Some(b);
+                }
+                let on = neighbours(here, true).into_iter().find(|&(w, _)| w != prev);
+                match on {
+                    Some((next, len)) => {
+                        total += len;
+                        prev = here;
+                        here = next;
+                    }
+                    None => break None,
+                }
+            };
+            if let Some(b) = b.filter(|&b| b != a as Vertex) {
+                let d = graph[a].entry(b).or_insert(total);
+                *d = f64::min(*d, total);
+            }
+        }
+    }
+    (vertices, graph, stop_areas)
+}
+
+// Takes every vertex out of `graph`, least important first, and returns
+// the edges up from each one to the more important ones.
+fn contract(graph: &mut Graph) -> Vec<Vec<(Vertex, f64)>> {
+    let n = graph.len();
+    let mut up = vec![Vec::new(); n];
+    let mut contracted = vec![false; n];
+    // How many neighbours each has lost, and how far up the hierarchy it
+    // would go, so that we spread the contractions out across the network
+    // rather than working along a line.
+    let mut lost = vec![0i64; n];
+    let mut level = vec![0i64; n];
+    let priority = |graph: &Graph, lost: &[i64], level: &[i64], v: Vertex, shortcuts: usize| {
+        2 * shortcuts as i64 - graph[v as usize].len() as i64 + lost[v as usize] + level[v as usize]
+    };
+
+    let mut current = (0..n as Vertex)
+        .map(|v| priority(graph, &lost, &level, v, shortcuts(graph, v).len()))
+        .collect::<Vec<_>>();
+    let mut queue = current
+        .iter()
+        .enumerate()
+        .map(|(v, &p)| Reverse((p, v as Vertex)))
+        .collect::<BinaryHeap<_>>();
+    while let Some(Reverse((p, v))) = queue.pop() {
+        if contracted[v as usize] || p != current[v as usize] {
+            continue;
+        }
+        // Taking other vertices out will have changed how important this
+        // one is, so check it's still the least important.
+        let shortcuts = shortcuts(graph, v);
+        let p = priority(graph, &lost, &level, v, shortcuts.len());
+        if let Some(Reverse((next, _))) = queue.peek() {
+            if p > *next {
+                current[v as usize] = p;
+                queue.push(Reverse((p, v)));
+                continue;
+            }
+        }
+
+        let neighbours = graph[v as usize].drain().collect::<Vec<_>>();
+        for &(u, w) in &neighbours {
+            graph[u as usize].remove(&v);
+            lost[u as usize] += 1;
+            level[u as usize] = level[u as usize].max(level[v as usize] + 1);
+            up[v as usize].push((u, w));
+        }
+        for (a, b, w) in shortcuts {
+            for &(x, y) in &[(a, b), (b, a)] {
+                let d = graph[x as usize].entry(y).or_insert(w);
+                *d = d.min(w);
+            }
+        }
+        contracted[v as usize] = true;
+
+        for &(u, _) in &neighbours {
+            let p = priority(graph, &lost, &level, u, self::shortcuts(graph, u).len());
+            current[u as usize] = p;
+            queue.push(Reverse((p, u)));
+        }
+    }
+    up
+}
+
+// The shortcuts we'd need to add between the neighbours of `v` if we took
+// it out: those where we can't find another way round at least as short.
+fn shortcuts(graph: &Graph, v: Vertex) -> Vec<(Vertex, Vertex, f64)> {
+    let neighbours = graph[v as usize]
+        .iter()
+        .map(|(&u, &w)| (u, w))
+        .collect::<Vec<_>>();
+    let mut shortcuts = Vec::new();
+    for &(a, to_a) in &neighbours {
+        let others = neighbours
+            .iter()
+            .filter(|&&(b, _)| b > a)
+            .collect::<Vec<_>>();
+        let limit = match others.iter().map(|&&(_, w)| to_a + w).reduce(f64::max) {
+            Some(limit) => limit,
+            None => continue,
+        };
+        let targets = others.iter().map(|&&(b, _)| b).collect::<Vec<_>>();
+        let witness = witnesses(graph, a, v, &targets, limit);
+        for &&(b, to_b) in &others {
+            let via = to_a + to_b;
+            if witness.get(&b).map(|&d| d > via).unwrap_or(true) {
+                shortcuts.push((a, b, via));
+            }
+        }
+    }
+    shortcuts
+}
+
+// How far it is from `from` to each of `targets` (and whatever else we
+// come across on the way), up to `limit`, without going through `without`.
+fn witnesses(
+    graph: &Graph,
+    from: Vertex,
+    without: Vertex,
+    targets: &[Vertex],
+    limit: f64,
+) -> HashMap<Vertex, f64> {
+    let mut dist = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    dist.insert(from, 0.0);
+    queue.push(Queued(0.0, from));
+    let mut settled = 0;
+    let mut left = targets.len();
+    while let Some(Queued(d, v)) = queue.pop() {
+        if d > dist[&v] {
+            continue;
+        }
+        settled += 1;
+        if targets.contains(&v) {
+            left -= 1;
+        }
+        if d > limit || settled > WITNESS_LIMIT || left == 0 {
+            break;
+        }
+        for (&next, &w) in &graph[v as usize] {
+            let nd = d + w;
+            if next != without && nd < dist.get(&next).cloned().unwrap_or(f64::INFINITY) {
+                dist.insert(next, nd);
+                queue.push(Queued(nd, next));
+            }
+        }
+    }
+    dist
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::NodeId;
+
+    use super::*;
+    use crate::{fixtures, routing::one_to_many};
+
+    // A network with nodes at `coords` (in degrees) and ways along each of
+    // `ways` (by index into `coords`). The nodes in `stations` are tagged as
+    // stations; the rest are plain track.
+    fn map(coords: &[(f64, f64)], ways: &[Vec<usize>], stations: &[usize]) -> CompactMap {
+        let ways = ways.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        fixtures::network(coords, &ways, &[], stations)
+    }
+
+    // Checks the hierarchy against Dijkstra for every pair of `stations`.
+    fn check(map: &CompactMap, hierarchy: &Hierarchy, stations: &[usize]) {
+        let vertices = stations
+            .iter()
+            .map(|&i| map.vertex(NodeId(i as i64 + 1)).unwrap())
+            .collect::<Vec<_>>();
+        for &from in &vertices {
+            let expected = one_to_many(map, from, &vertices);
+            for (&to, expected) in vertices.iter().zip(expected) {
+                let got = hierarchy.distance(from, to);
+                match (got, expected) {
+                    (Some(got), Some(expected)) => assert!(
+                        (got - expected).abs() < 1e-6,
+                        "{} to {}: {} != {}",
+                        from,
+                        to,
+                        got,
+                        expected
+                    ),
+                    (got, expected) => assert_eq!(got, expected, "{} to {}", from, to),
+                }
+            }
+        }
+    }
+
+    // The same sequence of not-very-random numbers every time.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    #[test]
+    fn matches_dijkstra_on_a_grid() {
+        let n = 15;
+        let mut rng = Lcg(42);
+        let coords = (0..n * n)
+            .map(|i| {
+                let (row, col) = (i / n, i % n);
+                (
+                    51.0 + row as f64 * 0.01 + rng.next() * 0.005,
+                    -1.0 + col as f64 * 0.015 + rng.next() * 0.005,
+                )
+            })
+            .collect::<Vec<_>>();
+        // Lines along each row and column, with some gaps, so that there
+        // are plenty of junctions, plain track, and dead ends.
+        let mut ways = Vec::new();
+        for i in 0..n {
+            let mut row = Vec::new();
+            let mut col = Vec::new();
+            for j in 0..n {
+                row.push(i * n + j);
+                col.push(j * n + i);
+                if rng.next() < 0.15 {
+                    ways.push(std::mem::take(&mut row));
+                }
+                if rng.next() < 0.15 {
+                    ways.push(std::mem::take(&mut col));
+                }
+            }
+            ways.push(row);
+            ways.push(col);
+        }
+        ways.retain(|w| w.len() >= 2);
+        let stations = (0..n * n).filter(|_| rng.next() < 0.2).collect::<Vec<_>>();
+        // And some stop areas across the corners of the squares.
+        let stop_areas = (0..(n - 1) * n)
+            .filter(|&i| i % n != n - 1 && rng.next() < 0.05)
+            .map(|i| vec![i, i + n + 1])
+            .collect::<Vec<_>>();
+
+        let ways = ways.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let stop_areas = stop_areas.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let map = fixtures::network(&coords, &ways, &stop_areas, &stations);
+        let hierarchy = Hierarchy::new(&map);
+        assert!(hierarchy.len() < n * n);
+        let mut ends = stations.clone();
+        ends.extend(stop_areas.iter().flat_map(|s| s.iter()));
+        check(&map, &hierarchy, &ends);
+    }
+
+    #[test]
+    fn stations_along_a_line_and_round_a_loop() {
+        // A line from 0 to 4, with a loop from 4 round through 5 to 7 and
+        // back, and stations partway along both.
+        let coords = [
+            (51.00, 0.00),
+            (51.01, 0.00),
+            (51.02, 0.00),
+            (51.03, 0.00),
+            (51.04, 0.00),
+            (51.05, 0.01),
+            (51.06, 0.00),
+            (51.05, -0.01),
+        ];
+        let ways = vec![vec![0, 1, 2, 3, 4], vec![4, 5, 6, 7, 4]];
+        let stations = [0, 2, 4, 6, 7];
+        let map = map(&coords, &ways, &stations);
+        let hierarchy = Hierarchy::new(&map);
+        assert!(!hierarchy.contains(map.vertex(NodeId(2)).unwrap()));
+        check(&map, &hierarchy, &stations);
+    }
+
+    #[test]
+    fn nothing_between_separate_networks() {
+        let coords = [(51.0, 0.0), (51.1, 0.0), (52.0, 0.0), (52.1, 0.0)];
+        let ways = vec![vec![0, 1], vec![2, 3]];
+        let stations = [0, 1, 2, 3];
+        let map = map(&coords, &ways, &stations);
+        let hierarchy = Hierarchy::new(&map);
+        assert_eq!(
+            hierarchy.distance(
+                map.vertex(NodeId(1)).unwrap(),
+                map.vertex(NodeId(3)).unwrap()
+            ),
+            None
+        );
+        check(&map, &hierarchy, &stations);
+    }
+
+    #[test]
+    fn on_and_off_through_stop_areas() {
+        // Two lines, 0-1-2 and 6-3-4, with a stop area around the
+        // platforms at 2 and 3, and the station 5 off to the side. We can
+        // get on and off through the stop area, but not change lines.
+        let coords = [
+            (51.0, 0.0),
+            (51.0, 0.01),
+            (51.0, 0.02),
+            (51.0001, 0.02),
+            (51.0, 0.03),
+            (51.0005, 0.02),
+            (51.01, 0.0201),
+        ];
+        let map = fixtures::network(
+            &coords,
+            &[&[0, 1, 2], &[6, 3, 4]],
+            &[&[2, 3, 5]],
+            &[0, 5, 6],
+        );
+        let hierarchy = Hierarchy::new(&map);
+        let v = |i: i64| map.vertex(NodeId(i + 1)).unwrap();
+        assert!(hierarchy.distance(v(0), v(5)).is_some());
+        assert!(hierarchy.distance(v(5), v(6)).is_some());
+        assert_eq!(hierarchy.distance(v(0), v(6)), None);
+        check(&map, &hierarchy, &[0, 2, 3, 4, 5, 6]);
+    }
+}
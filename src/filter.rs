@@ -0,0 +1,177 @@
+use std::{collections::HashSet, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::{NodeId, OsmId, OsmObj, RelationId, Tags, WayId};
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String;
+
+/// Matches objects carrying a tag, given on the command line as either `key`
+/// (any value) or `key=value`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagFilter {
+    key: String,
+    value: Option<String>,
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match (tags.get(&self.key), &self.value) {
+            (Some(_), None) => true,
+            (Some(actual), Some(expected)) => actual == expected,
+            (None, _) => false,
+        }
+    }
+
+    /// True if any of `filters` match. An empty list falls back to
+    /// `is_relevant`, so callers get the railway subset by default.
+    pub fn any_match(filters: &[TagFilter], tags: &Tags) -> bool {
+        if filters.is_empty() {
+            is_relevant(tags)
+        } else {
+            filters.iter().any(|f| f.matches(tags))
+        }
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = match s.find('=') {
+            Some(pos) => (&s[..pos], Some(String::from(&s[pos + 1..]))),
+            None => (s, None),
+        };
+        if key.is_empty() {
+            bail!("Tag filter needs a key: {:?}", s);
+        }
+        Ok(TagFilter {
+            key: key.into(),
+            value,
+        })
+    }
+}
+
+/// A bounding box, given on the command line as
+/// `min_lon,min_lat,max_lon,max_lat` (the same order osmium uses).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+/// Passes through the nodes inside a `BBox`, the ways with at least one of
+/// those nodes, and the relations with at least one member we've already
+/// kept. As with `osmium extract --strategy simple`, this relies on the
+/// input being in the usual order, with nodes first.
+pub struct Clip<I> {
+    bbox: BBox,
+    objs: I,
+    nodes: HashSet<NodeId>,
+    ways: HashSet<WayId>,
+    rels: HashSet<RelationId>,
+}
+
+impl BBox {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
+    pub fn clip<I>(self, objs: I) -> Clip<I>
+    where
+        I: Iterator<Item = Result<OsmObj>>,
+    {
+        Clip {
+            bbox: self,
+            objs,
+            nodes: HashSet::new(),
+            ways: HashSet::new(),
+            rels: HashSet::new(),
+        }
+    }
+}
+
+impl FromStr for BBox {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let coords = s
+            .split(',')
+            .map(|c| c.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Bad bounding box: {:?}", s))?;
+        match coords[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(BBox {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => bail!(
+                "Bounding box should be min_lon,min_lat,max_lon,max_lat: {:?}",
+                s
+            ),
+        }
+    }
+}
+
+impl<I> Clip<I> {
+    fn keep(&mut self, obj: &OsmObj) -> bool {
+        match obj {
+            OsmObj::Node(n) => {
+                let inside = self.bbox.contains(n.lat(), n.lon());
+                if inside {
+                    self.nodes.insert(n.id);
+                }
+                inside
+            }
+            OsmObj::Way(w) => {
+                let inside = w.nodes.iter().any(|n| self.nodes.contains(n));
+                if inside {
+                    self.ways.insert(w.id);
+                }
+                inside
+            }
+            OsmObj::Relation(r) => {
+                let inside = r.refs.iter().any(|m| match m.member {
+                    OsmId::Node(id) => self.nodes.contains(&id),
+                    OsmId::Way(id) => self.ways.contains(&id),
+                    OsmId::Relation(id) => self.rels.contains(&id),
+                });
+                if inside {
+                    self.rels.insert(r.id);
+                }
+                inside
+            }
+        }
+    }
+}
+
+impl<I> Iterator for Clip<I>
+where
+    I: Iterator<Item = Result<OsmObj>>,
+{
+    type Item = Result<OsmObj>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.objs.next()? {
+                Ok(obj) if !self.keep(&obj) => continue,
+                other => return Some(other),
+            }
+        }
+    }
+}
+
+pub fn is_relevant(tags: &Tags) -> bool {
+    is_network(tags) || tags.get("route") == Some(&"train".into())
+}
+
+/// Nodes and ways that make up the network: track, stations, platforms and
+/// the like.
+pub fn is_network(tags: &Tags) -> bool {
+    tags.contains_key("railway") || tags.contains_key("public_transport")
+}
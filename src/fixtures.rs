@@ -0,0 +1,147 @@
+//! Small bits of OSM data for the tests to read and write.
+
+use std::{env, path::PathBuf, process};
+
+use anyhow::Result;
+use osmpbfreader::{Node, NodeId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+
+use osmrail::compact::CompactMap;
+
+/// A station on a short line, and a stop area around it, in the usual order
+/// (nodes, then ways, then relations, each by id). The tags have characters
+/// that need escaping in XML, and the coordinates are either side of the
+/// meridian. (Strings are kept short: smartstring 0.2 mangles longer ones
+/// with recent compilers.)
+pub(crate) fn objs() -> Vec<OsmObj> {
+    let mut objs = Vec::<OsmObj>::new();
+    for (id, lat, lon, tags) in [
+        (
+            1,
+            51.5007292,
+            -0.1246254,
+            &[
+                ("railway", "station"),
+                ("name", "Fish & <\"Chips\"> 'Ok'"),
+                ("ref:crs", "FAC"),
+            ][..],
+        ),
+        (2, 51.5010000, -0.1200000, &[][..]),
+        (3, 51.5020000, 0.0000001, &[][..]),
+        (4, 51.5030000, 0.1200000, &[("railway", "buffer_stop")][..]),
+        (
+            5,
+            51.5007000,
+            -0.1246000,
+            &[("public_transport", "platform")][..],
+        ),
+    ] {
+        objs.push(
+            Node {
+                id: NodeId(id),
+                tags: tags_of(tags),
+                decimicro_lat: (lat * 1e7_f64).round() as i32,
+                decimicro_lon: (lon * 1e7_f64).round() as i32,
+            }
+            .into(),
+        );
+    }
+    objs.push(
+        Way {
+            id: WayId(10),
+            tags: tags_of(&[("railway", "rail"), ("maxspeed", "60 mph")]),
+            nodes: vec![NodeId(1), NodeId(2), NodeId(3), NodeId(4)],
+        }
+        .into(),
+    );
+    objs.push(
+        Relation {
+            id: RelationId(20),
+            tags: tags_of(&[("public_transport", "stop_area"), ("name", "Fish & Chips")]),
+            refs: vec![
+                member(NodeId(1).into(), "station"),
+                member(NodeId(5).into(), "platform"),
+                member(WayId(10).into(), ""),
+                member(RelationId(21).into(), "sub area"),
+            ],
+        }
+        .into(),
+    );
+    objs
+}
+
+/// A network with nodes at `coords` (in degrees), ways along each of `ways`
+/// and stop areas around each of `stop_areas` (both by index into
+/// `coords`). The nodes in `stations` are tagged as stations; the rest are
+/// plain track. Node `i` gets id `i + 1`.
+pub(crate) fn network(
+    coords: &[(f64, f64)],
+    ways: &[&[usize]],
+    stop_areas: &[&[usize]],
+    stations: &[usize],
+) -> CompactMap {
+    let mut objs = Vec::<OsmObj>::new();
+    for (i, &(lat, lon)) in coords.iter().enumerate() {
+        let tags: &[_] = if stations.contains(&i) {
+            &[("railway", "station")]
+        } else {
+            &[]
+        };
+        objs.push(
+            Node {
+                id: NodeId(i as i64 + 1),
+                tags: tags_of(tags),
+                decimicro_lat: (lat * 1e7_f64).round() as i32,
+                decimicro_lon: (lon * 1e7_f64).round() as i32,
+            }
+            .into(),
+        );
+    }
+    for (i, nodes) in ways.iter().enumerate() {
+        objs.push(
+            Way {
+                id: WayId(i as i64 + 1),
+                tags: tags_of(&[("railway", "rail")]),
+                nodes: nodes.iter().map(|&n| NodeId(n as i64 + 1)).collect(),
+            }
+            .into(),
+        );
+    }
+    for (i, nodes) in stop_areas.iter().enumerate() {
+        objs.push(
+            Relation {
+                id: RelationId(i as i64 + 1),
+                tags: tags_of(&[("public_transport", "stop_area")]),
+                refs: nodes
+                    .iter()
+                    .map(|&n| member(NodeId(n as i64 + 1).into(), ""))
+                    .collect(),
+            }
+            .into(),
+        );
+    }
+    let all = || objs.iter().cloned().map(Result::Ok);
+    let mut map = CompactMap::from_reader(all()).unwrap();
+    map.resolve_coords(all()).unwrap();
+    map
+}
+
+fn tags_of(tags: &[(&str, &str)]) -> Tags {
+    let mut out = Tags::new();
+    for &(k, v) in tags {
+        out.insert(k.into(), v.into());
+    }
+    out
+}
+
+fn member(member: osmpbfreader::OsmId, role: &str) -> Ref {
+    Ref {
+        member,
+        role: role.into(),
+    }
+}
+
+/// Somewhere for a test to write a file called `name`. Tests run in
+/// parallel, so each should use its own name.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("osmrail-test-{}-{}", process::id(), name))
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap, convert::TryFrom, fs::File, io::BufReader, path::Path, str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use geo::{BoundingRect, Contains, Coord, LineString, MultiPolygon, Point, Rect};
+use geojson::GeoJson;
+use log::{info, warn};
+use osmpbfreader::{NodeId, OsmObj, Way};
+
+use crate::input::{resolve_nodes, Input};
+
+/// A way, and where each of its nodes is, in (lon, lat) order as `geo`
+/// expects. Nodes we couldn't find (eg: outside of an extract) are `None`.
+pub type WayCoords = (Way, Vec<Option<Coord<f64>>>);
+
+/// Finds the ways matching `pred`, and works out where their nodes are,
+/// with a second pass over the input.
+pub fn way_lines<F>(input: &Input, mut pred: F) -> Result<Vec<WayCoords>>
+where
+    F: FnMut(&Way) -> bool,
+{
+    let mut ways = Vec::new();
+    let mut coords = HashMap::<NodeId, Option<Coord<f64>>>::new();
+    for it in input.par_objs()? {
+        match it.context("Read item")? {
+            OsmObj::Way(way) if pred(&way) => {
+                for &node in &way.nodes {
+                    coords.insert(node, None);
+                }
+                ways.push(way);
+            }
+            _ => (),
+        }
+    }
+
+    let missing = resolve_nodes(input.par_objs()?, coords.len(), |node| {
+        match coords.get_mut(&node.id) {
+            Some(coord @ None) => {
+                *coord = Some(Coord {
+                    x: node.lon(),
+                    y: node.lat(),
+                });
+                true
+            }
+            _ => false,
+        }
+    })?;
+
+    let ways = ways
+        .into_iter()
+        .map(|way| {
+            let line = way.nodes.iter().map(|n| coords[n]).collect::<Vec<_>>();
+            (way, line)
+        })
+        .collect::<Vec<_>>();
+    if missing > 0 {
+        let skipped = ways.iter().map(|(_, line)| split(line).1).sum::<usize>();
+        warn!(
+            "{} way nodes are missing from {:?}, leaving out {} segments",
+            missing,
+            input.path(),
+            skipped
+        );
+    }
+    Ok(ways)
+}
+
+/// Splits a way into the lines we can draw, breaking it wherever a node is
+/// missing rather than joining straight across the gap. Also returns how
+/// many segments were left out.
+pub fn split(coords: &[Option<Coord<f64>>]) -> (Vec<LineString<f64>>, usize) {
+    let mut lines = Vec::new();
+    let mut skipped = 0;
+    let mut line = Vec::new();
+    for (i, coord) in coords.iter().enumerate() {
+        match coord {
+            Some(c) => line.push(*c),
+            None => {
+                // The segments either side of this node, if there are any,
+                // and if we haven't already counted them.
+                skipped += usize::from(i > 0 && coords[i - 1].is_some());
+                skipped += usize::from(i + 1 < coords.len());
+                if line.len() >= 2 {
+                    lines.push(LineString(std::mem::take(&mut line)));
+                }
+                line.clear();
+            }
+        }
+    }
+    if line.len() >= 2 {
+        lines.push(LineString(line));
+    }
+    (lines, skipped)
+}
+
+/// A position, given on the command line as `lat,lon`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatLon {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl FromStr for LatLon {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let coords = s
+            .split(',')
+            .map(|c| c.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Bad position: {:?}", s))?;
+        match coords[..] {
+            [lat, lon] if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => {
+                Ok(LatLon { lat, lon })
+            }
+            _ => bail!("Position should be lat,lon: {:?}", s),
+        }
+    }
+}
+
+/// A set of named areas, read from the polygons in a GeoJSON file.
+#[derive(Debug, Default)]
+pub struct Regions {
+    regions: Vec<(String, Rect<f64>, MultiPolygon<f64>)>,
+}
+
+impl Regions {
+    /// Reads every `Polygon` and `MultiPolygon` feature in `path`, naming
+    /// each after its `name_property`, or numbering them if there isn't one.
+    pub fn from_geojson(path: &Path, name_property: Option<&str>) -> Result<Self> {
+        let f = File::open(path).with_context(|| format!("open {:?}", path))?;
+        let geojson = GeoJson::from_reader(BufReader::new(f))
+            .with_context(|| format!("read GeoJSON from {:?}", path))?;
+        let features = match geojson {
+            GeoJson::FeatureCollection(fc) => fc.features,
+            GeoJson::Feature(f) => vec![f],
+            GeoJson::Geometry(_) => bail!("Regions need to be features with a name: {:?}", path),
+        };
+
+        let mut regions = Vec::new();
+        for (i, feature) in features.into_iter().enumerate() {
+            let name = match name_property.map(|key| (key, feature.property(key))) {
+                Some((_, Some(serde_json::Value::String(s)))) => s.clone(),
+                Some((_, Some(other))) => other.to_string(),
+                Some((key, None)) => bail!("Region {} in {:?} has no {:?}", i, path, key),
+                None => i.to_string(),
+            };
+            let geometry = match feature.geometry {
+                Some(g) => geo::Geometry::<f64>::try_from(g)
+                    .with_context(|| format!("region {:?}", name))?,
+                None => continue,
+            };
+            let area = match geometry {
+                geo::Geometry::Polygon(p) => MultiPolygon(vec![p]),
+                geo::Geometry::MultiPolygon(mp) => mp,
+                _ => {
+                    warn!("Skipping region {:?}, which isn't a polygon", name);
+                    continue;
+                }
+            };
+            if let Some(bounds) = area.bounding_rect() {
+                regions.push((name, bounds, area));
+            }
+        }
+        info!("Read {} regions from {:?}", regions.len(), path);
+        Ok(Regions { regions })
+    }
+
+    /// The first region containing `point`, if any.
+    pub fn find(&self, point: Point<f64>) -> Option<&str> {
+        self.regions
+            .iter()
+            .find(|(_, bounds, area)| bounds.contains(&point) && area.contains(&point))
+            .map(|(name, _, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fixtures;
+
+    fn coord(x: f64) -> Option<Coord<f64>> {
+        Some(Coord { x, y: 0.0 })
+    }
+
+    fn xs(lines: &[LineString<f64>]) -> Vec<Vec<f64>> {
+        lines
+            .iter()
+            .map(|line| line.0.iter().map(|c| c.x).collect())
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_missing_nodes() {
+        let (lines, skipped) = split(&[coord(0.0), coord(1.0), coord(2.0)]);
+        assert_eq!((xs(&lines), skipped), (vec![vec![0.0, 1.0, 2.0]], 0));
+
+        let way = [coord(0.0), coord(1.0), None, coord(3.0), coord(4.0)];
+        let (lines, skipped) = split(&way);
+        assert_eq!(
+            (xs(&lines), skipped),
+            (vec![vec![0.0, 1.0], vec![3.0, 4.0]], 2)
+        );
+
+        // Nothing is drawn from a lone node, but it's not left out twice.
+        let way = [None, coord(1.0), None, None, coord(4.0), coord(5.0)];
+        let (lines, skipped) = split(&way);
+        assert_eq!((xs(&lines), skipped), (vec![vec![4.0, 5.0]], 4));
+    }
+
+    #[test]
+    fn finds_where_the_nodes_are() {
+        let path = fixtures::temp_path("way-lines.osm");
+        fs::write(
+            &path,
+            r#"<osm version="0.6">
+                <node id="1" lat="1" lon="10"/>
+                <node id="2" lat="2" lon="20"/>
+                <node id="4" lat="4" lon="40"/>
+                <way id="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/>
+                    <tag k="railway" v="rail"/></way>
+                <way id="2"><nd ref="1"/><nd ref="2"/></way>
+            </osm>"#,
+        )
+        .unwrap();
+        let lines = way_lines(&Input::open(&path).unwrap(), |way| {
+            way.tags.contains_key("railway")
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].0.id.0, 1);
+        let at = |lat: f64| {
+            Some(Coord {
+                x: lat * 10.0,
+                y: lat,
+            })
+        };
+        assert_eq!(lines[0].1, vec![at(1.0), at(2.0), None, at(4.0)]);
+    }
+}
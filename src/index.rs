@@ -0,0 +1,200 @@
+use geo::{HaversineDistance, Point};
+use osmpbfreader::OsmId;
+use rstar::{
+    primitives::{GeomWithData, Line},
+    PointDistance, RTree,
+};
+
+use crate::compact::{CompactMap, Vertex};
+
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// An R-tree over the vertices of a `CompactMap`, for finding what's near
+/// a point.
+///
+/// Points are stored on a sinusoidal projection (in metres), which keeps
+/// distances close enough to true over the few kilometres we usually care
+/// about. The distances we hand out are great-circle ones, though.
+pub struct SpatialIndex {
+    tree: RTree<GeomWithData<[f64; 2], Vertex>>,
+}
+
+impl SpatialIndex {
+    /// Indexes the vertices that we know the position of, and that `pred`
+    /// picks out (eg: only stations).
+    pub fn new<F>(map: &CompactMap, mut pred: F) -> Self
+    where
+        F: FnMut(Vertex) -> bool,
+    {
+        let points = (0..map.vertex_count() as Vertex)
+            .filter(|&v| pred(v))
+            .filter_map(|v| {
+                let c = map.coord(v)?;
+                Some(GeomWithData::new(project(c.lat(), c.lon()), v))
+            })
+            .collect();
+        SpatialIndex {
+            tree: RTree::bulk_load(points),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.size() == 0
+    }
+
+    /// The vertices nearest to (`lat`, `lon`) first, and how far away each
+    /// is, in metres.
+    pub fn nearest<'a>(
+        &'a self,
+        map: &'a CompactMap,
+        lat: f64,
+        lon: f64,
+    ) -> impl Iterator<Item = (Vertex, f64)> + 'a {
+        let here = Point::new(lon, lat);
+        self.tree
+            .nearest_neighbor_iter(&project(lat, lon))
+            .map(move |p| {
+                let c = map.coord(p.data).expect("indexed vertex has a position");
+                (
+                    p.data,
+                    here.haversine_distance(&Point::new(c.lon(), c.lat())),
+                )
+            })
+    }
+}
+
+/// The same, but for the edges of a `CompactMap`, so we can find the
+/// nearest point on the track, rather than just the nearest vertex.
+pub struct SegmentIndex {
+    tree: RTree<Segment>,
+}
+
+// An edge, from one vertex to another, via a way or stop area.
+type Segment = GeomWithData<Line<[f64; 2]>, (Vertex, Vertex, OsmId)>;
+
+/// Where a point lands on an edge. `along` is how far from `from` to `to`
+/// it is, from 0 to 1, and `distance` how far it had to move, in metres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snap {
+    pub from: Vertex,
+    pub to: Vertex,
+    pub via: OsmId,
+    pub along: f64,
+    pub lat: f64,
+    pub lon: f64,
+    pub distance: f64,
+}
+
+impl SegmentIndex {
+    /// Indexes the edges (once each) that we know both ends of, and whose
+    /// way or stop area `pred` picks out.
+    pub fn new<F>(map: &CompactMap, mut pred: F) -> Self
+    where
+        F: FnMut(OsmId) -> bool,
+    {
+        let mut segments = Vec::new();
+        for a in 0..map.vertex_count() as Vertex {
+            let ca = match map.coord(a) {
+                Some(c) => c,
+                None => continue,
+            };
+            for (b, via) in map.edges(a) {
+                if b <= a || !pred(via) {
+                    continue;
+                }
+                if let Some(cb) = map.coord(b) {
+                    let line = Line::new(project(ca.lat(), ca.lon()), project(cb.lat(), cb.lon()));
+                    segments.push(GeomWithData::new(line, (a, b, via)));
+                }
+            }
+        }
+        SegmentIndex {
+            tree: RTree::bulk_load(segments),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.size() == 0
+    }
+
+    /// The points on the track within `radius` metres of (`lat`, `lon`),
+    /// one for each edge, nearest first.
+    pub fn within(&self, map: &CompactMap, lat: f64, lon: f64, radius: f64) -> Vec<Snap> {
+        let here = Point::new(lon, lat);
+        let query = project(lat, lon);
+        let mut snaps = self
+            .tree
+            .locate_within_distance(query, radius * radius)
+            .map(|seg| {
+                let (from, to, via) = seg.data;
+                let line = seg.geom();
+                let len_2 = line.length_2();
+                let along = if len_2 > 0.0 {
+                    let d = line.from.distance_2(&line.nearest_point(&query));
+                    (d / len_2).sqrt().clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let (a, b) = (map.coord(from).expect("from"), map.coord(to).expect("to"));
+                let lat = a.lat() + (b.lat() - a.lat()) * along;
+                let lon = a.lon() + (b.lon() - a.lon()) * along;
+                Snap {
+                    from,
+                    to,
+                    via,
+                    along,
+                    lat,
+                    lon,
+                    distance: here.haversine_distance(&Point::new(lon, lat)),
+                }
+            })
+            .collect::<Vec<_>>();
+        snaps.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        snaps
+    }
+}
+
+pub(crate) fn project(lat: f64, lon: f64) -> [f64; 2] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [EARTH_RADIUS * lon * lat.cos(), EARTH_RADIUS * lat]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn finds_the_nearest_vertices() {
+        // 0.001 degrees of latitude is about 111m, and of longitude half
+        // that at 60 degrees north.
+        let map = fixtures::network(
+            &[(60.0, 0.0), (60.002, 0.0), (60.0, 0.001), (59.997, 0.0)],
+            &[&[0, 1], &[0, 2], &[0, 3]],
+            &[],
+            &[1, 3],
+        );
+        let index = SpatialIndex::new(&map, |_| true);
+        assert_eq!(index.len(), 4);
+        let nearest = index
+            .nearest(&map, 60.0, 0.0)
+            .map(|(v, d)| (map.node_id(v).0, (d * 10.0).round() / 10.0))
+            .collect::<Vec<_>>();
+        assert_eq!(nearest, vec![(1, 0.0), (3, 55.6), (2, 222.4), (4, 333.6)]);
+
+        let stations = SpatialIndex::new(&map, |v| map.is_station(v));
+        let nearest = stations
+            .nearest(&map, 59.999, 0.0)
+            .map(|(v, _)| map.node_id(v).0)
+            .collect::<Vec<_>>();
+        assert_eq!(nearest, vec![4, 2]);
+    }
+}
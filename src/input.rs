@@ -0,0 +1,462 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use log::info;
+use osmpbfreader::{blobs, fileformat::Blob, Node, OsmId, OsmObj, OsmPbfReader};
+use par_map::ParMap;
+
+use crate::{filter::BBox, xml::XmlReader};
+
+/// An OSM data file, in any of the formats we know how to read. Each call to
+/// `objs` re-opens the file, so it's cheap to make several passes over the
+/// same input.
+#[derive(Debug, Clone)]
+pub struct Input {
+    path: PathBuf,
+    format: Format,
+    compression: Compression,
+    bbox: Option<BBox>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pbf,
+    Xml,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+}
+
+/// A stream of objects from an `Input`, in file order.
+pub struct OsmObjs(Box<dyn Iterator<Item = Result<OsmObj>>>);
+
+// Owns the reader, unlike `OsmPbfReader::iter`, so that we can hand out
+// iterators without tying them to the lifetime of a local.
+struct PbfObjs<R> {
+    pbf: OsmPbfReader<R>,
+    block: Option<blobs::OsmObjs>,
+}
+
+// Hands out the raw (still compressed) blobs, so they can be decoded
+// elsewhere.
+struct PbfBlobs<R> {
+    pbf: OsmPbfReader<R>,
+}
+
+impl Input {
+    /// Works out the format from the file name where we can (eg:
+    /// `.osm.pbf`, `.osm.bz2`), and falls back to sniffing the first few
+    /// bytes of the file otherwise.
+    pub fn open(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let (format, compression) = match from_extension(&name) {
+            Some(it) => it,
+            None => sniff(path).with_context(|| format!("detect format of {:?}", path))?,
+        };
+
+        if format == Format::Pbf && compression != Compression::None {
+            bail!("Compressed PBF files are not supported: {:?}", path);
+        }
+
+        Ok(Input {
+            path: path.to_owned(),
+            format,
+            compression,
+            bbox: None,
+        })
+    }
+
+    /// Only read the part of the file inside `bbox` (see `BBox::clip`).
+    pub fn clip(mut self, bbox: Option<BBox>) -> Self {
+        self.bbox = bbox;
+        self
+    }
+
+    pub fn bbox(&self) -> Option<BBox> {
+        self.bbox
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn objs(&self) -> Result<OsmObjs> {
+        let it: Box<dyn Iterator<Item = Result<OsmObj>>> = match self.format {
+            Format::Pbf => Box::new(PbfObjs {
+                pbf: OsmPbfReader::new(self.open_file()?),
+                block: None,
+            }),
+            Format::Xml => Box::new(self.xml()?),
+        };
+        Ok(self.clipped(it))
+    }
+
+    /// The same objects, in the same order, as `objs`, but with the PBF
+    /// blocks decoded across all cores. Reading and decompressing XML
+    /// can't be split up, so that's still done sequentially.
+    pub fn par_objs(&self) -> Result<OsmObjs> {
+        let it: Box<dyn Iterator<Item = Result<OsmObj>>> = match self.format {
+            Format::Pbf => Box::new(
+                PbfBlobs {
+                    pbf: OsmPbfReader::new(self.open_file()?),
+                }
+                .par_flat_map(|blob| {
+                    blobs::result_blob_into_iter(blob).map(|obj| obj.context("Read item"))
+                }),
+            ),
+            Format::Xml => Box::new(self.xml()?),
+        };
+        Ok(self.clipped(it))
+    }
+
+    fn clipped(&self, it: Box<dyn Iterator<Item = Result<OsmObj>>>) -> OsmObjs {
+        match self.bbox {
+            Some(bbox) => OsmObjs(Box::new(bbox.clip(it))),
+            None => OsmObjs(it),
+        }
+    }
+
+    pub(crate) fn xml(&self) -> Result<XmlReader<Box<dyn BufRead>>> {
+        Ok(XmlReader::new(self.decompress(self.open_file()?)))
+    }
+
+    fn open_file(&self) -> Result<File> {
+        File::open(&self.path).with_context(|| format!("open {:?}", self.path))
+    }
+
+    /// Finds all the objects matching `pred`, and everything they refer to
+    /// (recursively). Works the same way as
+    /// `OsmPbfReader::get_objs_and_deps`, making as many passes over the
+    /// file as needed to resolve everything.
+    pub fn get_objs_and_deps<F>(&self, mut pred: F) -> Result<BTreeMap<OsmId, OsmObj>>
+    where
+        F: FnMut(&OsmObj) -> bool,
+    {
+        let mut objects = BTreeMap::new();
+        let mut deps = BTreeSet::new();
+        let mut first_pass = true;
+        loop {
+            let mut finished = true;
+            for obj in self.objs()? {
+                let obj = obj?;
+                if (!first_pass || !pred(&obj)) && !deps.contains(&obj.id()) {
+                    continue;
+                }
+                let members: Vec<OsmId> = match &obj {
+                    OsmObj::Relation(rel) => rel.refs.iter().map(|r| r.member).collect(),
+                    OsmObj::Way(way) => way.nodes.iter().map(|&n| n.into()).collect(),
+                    OsmObj::Node(_) => Vec::new(),
+                };
+                for member in members {
+                    if !objects.contains_key(&member) && deps.insert(member) {
+                        finished = false;
+                    }
+                }
+                deps.remove(&obj.id());
+                objects.insert(obj.id(), obj);
+            }
+            first_pass = false;
+            if finished {
+                break;
+            }
+        }
+        Ok(objects)
+    }
+
+    fn decompress(&self, f: File) -> Box<dyn BufRead> {
+        match self.compression {
+            Compression::None => Box::new(BufReader::new(f)),
+            Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(f))),
+            Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(f))),
+        }
+    }
+}
+
+/// The second pass of reading ways with their geometry: offers each node in
+/// `objs` to `place`, which says whether it was one of the `missing` nodes
+/// we were after. Stops as soon as we have them all, and returns how many
+/// we never found (which is normal for ways that cross the edge of an
+/// extract).
+pub fn resolve_nodes<F>(
+    objs: impl Iterator<Item = Result<OsmObj>>,
+    mut missing: usize,
+    mut place: F,
+) -> Result<usize>
+where
+    F: FnMut(&Node) -> bool,
+{
+    info!("Resolving coordinates for {} nodes", missing);
+    for it in objs {
+        if missing == 0 {
+            break;
+        }
+        if let OsmObj::Node(node) = it.context("Read item")? {
+            if place(&node) {
+                missing -= 1;
+            }
+        }
+    }
+    Ok(missing)
+}
+
+fn from_extension(name: &str) -> Option<(Format, Compression)> {
+    let (name, compression) = if let Some(name) = name.strip_suffix(".gz") {
+        (name, Compression::Gzip)
+    } else if let Some(name) = name.strip_suffix(".bz2") {
+        (name, Compression::Bzip2)
+    } else {
+        (name, Compression::None)
+    };
+
+    if name.ends_with(".pbf") {
+        Some((Format::Pbf, compression))
+    } else if name.ends_with(".osm") || name.ends_with(".osc") || name.ends_with(".xml") {
+        Some((Format::Xml, compression))
+    } else {
+        None
+    }
+}
+
+fn sniff(path: &Path) -> Result<(Format, Compression)> {
+    let mut magic = [0u8; 16];
+    let mut f = File::open(path)?;
+    let mut len = 0;
+    while len < magic.len() {
+        match f.read(&mut magic[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let magic = &magic[..len];
+    let text = magic.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(magic);
+
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok((Format::Xml, Compression::Gzip))
+    } else if magic.starts_with(b"BZh") {
+        Ok((Format::Xml, Compression::Bzip2))
+    } else if text.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<') {
+        Ok((Format::Xml, Compression::None))
+    } else if magic.windows(b"OSMHeader".len()).any(|w| w == b"OSMHeader") {
+        // A PBF file starts with the length of the first blob header, and
+        // then the header itself, which names the blob type.
+        Ok((Format::Pbf, Compression::None))
+    } else {
+        bail!("Unrecognised file format")
+    }
+}
+
+impl Iterator for OsmObjs {
+    type Item = Result<OsmObj>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<R: Read> Iterator for PbfBlobs<R> {
+    type Item = osmpbfreader::Result<Blob>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pbf.blobs().next()
+    }
+}
+
+impl<R: Read> Iterator for PbfObjs<R> {
+    type Item = Result<OsmObj>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(obj) = self.block.as_mut().and_then(|b| b.next()) {
+                return Some(obj.context("Read item"));
+            }
+            let blob = self.pbf.blobs().next()?;
+            self.block = Some(blobs::result_blob_into_iter(blob));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use bzip2::write::BzEncoder;
+    use flate2::write::GzEncoder;
+    use osmpbfreader::{Node, NodeId, Tags, Way, WayId};
+
+    use super::*;
+    use crate::{fixtures, pbf::PbfWriter, xml::XmlWriter};
+
+    fn xml() -> Vec<u8> {
+        let mut w = XmlWriter::new(Vec::new()).unwrap();
+        for obj in &fixtures::objs() {
+            w.write(obj).unwrap();
+        }
+        w.finish().unwrap()
+    }
+
+    fn pbf() -> Vec<u8> {
+        let mut w = PbfWriter::new(Vec::new()).unwrap();
+        for obj in fixtures::objs() {
+            w.write(obj).unwrap();
+        }
+        w.finish().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    fn bzip2(data: &[u8]) -> Vec<u8> {
+        let mut w = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    // Writes `data` to a file called `name`, opens it, and reads it all.
+    fn read(name: &str, data: &[u8]) -> Result<(Format, Compression, Vec<OsmObj>)> {
+        let path = fixtures::temp_path(name);
+        fs::write(&path, data).unwrap();
+        let objs = Input::open(&path).and_then(|input| {
+            let objs = input.objs()?.collect::<Result<Vec<_>>>()?;
+            Ok((input.format, input.compression, objs))
+        });
+        fs::remove_file(&path).unwrap();
+        objs
+    }
+
+    #[test]
+    fn format_from_extension() {
+        use Compression as C;
+        use Format::*;
+        for (name, expected) in [
+            ("gb.osm.pbf", Some((Pbf, C::None))),
+            ("GB.OSM.PBF", Some((Pbf, C::None))),
+            ("gb.osm", Some((Xml, C::None))),
+            ("gb.osm.gz", Some((Xml, C::Gzip))),
+            ("gb.osm.bz2", Some((Xml, C::Bzip2))),
+            ("000.osc.gz", Some((Xml, C::Gzip))),
+            ("gb.xml", Some((Xml, C::None))),
+            ("gb.pbf.gz", Some((Pbf, C::Gzip))),
+            ("gb", None),
+            ("gb.gz", None),
+            ("gb.osm.zip", None),
+        ] {
+            let lower = name.to_lowercase();
+            assert_eq!(from_extension(&lower), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_each_format_by_extension() {
+        let objs = fixtures::objs();
+        for (name, data, format, compression) in [
+            ("ext.osm.pbf", pbf(), Format::Pbf, Compression::None),
+            ("ext.osm", xml(), Format::Xml, Compression::None),
+            ("ext.osm.gz", gzip(&xml()), Format::Xml, Compression::Gzip),
+            (
+                "ext.osm.bz2",
+                bzip2(&xml()),
+                Format::Xml,
+                Compression::Bzip2,
+            ),
+        ] {
+            let got = read(name, &data).unwrap();
+            assert_eq!(got, (format, compression, objs.clone()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn sniffs_the_format_without_an_extension() {
+        let objs = fixtures::objs();
+        let mut bom = vec![0xef, 0xbb, 0xbf, b'\n', b' '];
+        bom.extend(xml());
+        for (name, data, format, compression) in [
+            ("sniff-pbf", pbf(), Format::Pbf, Compression::None),
+            ("sniff-xml", xml(), Format::Xml, Compression::None),
+            ("sniff-bom", bom, Format::Xml, Compression::None),
+            ("sniff-gz", gzip(&xml()), Format::Xml, Compression::Gzip),
+            ("sniff-bz2", bzip2(&xml()), Format::Xml, Compression::Bzip2),
+        ] {
+            let got = read(name, &data).unwrap();
+            assert_eq!(got, (format, compression, objs.clone()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_read() {
+        assert!(read("refuse-pbf.osm.pbf.gz", &gzip(&pbf())).is_err());
+        assert!(read("refuse-text", b"node,lat,lon\n").is_err());
+        assert!(read("refuse-empty", b"").is_err());
+    }
+
+    #[test]
+    fn par_objs_matches_objs() {
+        // Enough nodes and ways to fill several blocks.
+        let mut objs = Vec::<OsmObj>::new();
+        for i in 1..=20_000 {
+            let mut tags = Tags::new();
+            if i % 7 == 0 {
+                tags.insert("railway".into(), "signal".into());
+            }
+            objs.push(
+                Node {
+                    id: NodeId(i),
+                    tags,
+                    decimicro_lat: (i * 31) as i32,
+                    decimicro_lon: -(i * 17) as i32,
+                }
+                .into(),
+            );
+        }
+        for i in 1..=10_000 {
+            objs.push(
+                Way {
+                    id: WayId(i),
+                    tags: Default::default(),
+                    nodes: vec![NodeId(i), NodeId(i + 1)],
+                }
+                .into(),
+            );
+        }
+        objs.extend(fixtures::objs().into_iter().filter(|o| o.is_relation()));
+
+        let path = fixtures::temp_path("par-objs.osm.pbf");
+        let mut w = PbfWriter::new(File::create(&path).unwrap()).unwrap();
+        for obj in &objs {
+            w.write(obj.clone()).unwrap();
+        }
+        w.finish().unwrap();
+
+        let input = Input::open(&path).unwrap();
+        let seq = input.objs().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        let par = input
+            .par_objs()
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(seq.len(), objs.len());
+        assert!(seq == par, "par_objs differs from objs");
+    }
+}
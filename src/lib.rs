@@ -0,0 +1,24 @@
+pub mod cache;
+pub mod checkpoint;
+pub mod compact;
+pub mod contraction;
+pub mod filter;
+#[cfg(test)]
+mod fixtures;
+// So that the fixtures can be shared with the binary's tests.
+#[cfg(test)]
+extern crate self as osmrail;
+pub mod geometry;
+pub mod index;
+pub mod input;
+pub mod linref;
+pub mod lint;
+pub mod matching;
+pub mod network;
+pub mod osc;
+pub mod output;
+pub mod pbf;
+pub mod replication;
+pub mod routing;
+pub mod trace;
+pub mod xml;
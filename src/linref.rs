@@ -0,0 +1,603 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Context, Result};
+use geo::{HaversineDistance, Point};
+use log::{info, warn};
+use osmpbfreader::{NodeId, OsmObj, WayId};
+use rstar::{
+    primitives::{GeomWithData, Line},
+    PointDistance, RTree,
+};
+
+use crate::{
+    geometry::{split, way_lines, WayCoords},
+    index::project,
+    input::Input,
+};
+
+const METRES_PER_MILE: f64 = 1609.344;
+const CHAINS_PER_MILE: f64 = 80.0;
+
+// A segment of a line, with which line it is and where it comes in it.
+type Segment = GeomWithData<Line<[f64; 2]>, (usize, usize)>;
+
+/// Positions along the railway as an Engineer's Line Reference (ELR) and
+/// a mileage, the way Network Rail locates things.
+///
+/// Each ELR is made up of the ways tagged with it as `railway:ref` (or that
+/// are in a `route=tracks` relation tagged with it), joined up end to end
+/// into lines. Mileposts (`railway=milestone` nodes with a
+/// `railway:position`) near a line pin down the mileage along it; between
+/// them we interpolate, and beyond them we assume the mileage keeps going
+/// at the same rate. Lines with fewer than two mileposts are marked as
+/// uncalibrated: with none, they're measured from whichever end we happened
+/// to start at, and with one, we can't tell which way the mileage goes.
+pub struct LinearRef {
+    lines: Vec<ElrLine>,
+    tree: RTree<Segment>,
+}
+
+/// Where something is, both as a coordinate and a mileage. `distance` is
+/// how far (in metres) the point we were asked about was from the line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub elr: String,
+    pub miles: f64,
+    pub lat: f64,
+    pub lon: f64,
+    pub distance: f64,
+    pub calibrated: bool,
+}
+
+// One unbroken run of track for an ELR.
+#[derive(Debug)]
+struct ElrLine {
+    elr: String,
+    // (lat, lon), and how far along the line each is, in metres.
+    points: Vec<(f64, f64)>,
+    offsets: Vec<f64>,
+    // How far along the line each milepost is, and its mileage, in order
+    // along the line.
+    calibration: Vec<(f64, f64)>,
+}
+
+// A milepost, before we know which line it's on.
+struct Milepost {
+    elr: Option<String>,
+    lat: f64,
+    lon: f64,
+    miles: f64,
+}
+
+impl LinearRef {
+    /// Mileposts further than this (in metres) from any line of their ELR
+    /// are ignored.
+    pub const MILEPOST_RADIUS: f64 = 50.0;
+
+    pub fn from_input(input: &Input) -> Result<Self> {
+        // Relations come last, so we need a pass to find the route=tracks
+        // relations before we can pick out their ways.
+        let mut elr_by_way = HashMap::<WayId, String>::new();
+        let mut mileposts = Vec::new();
+        for it in input.par_objs()? {
+            match it.context("Read item")? {
+                OsmObj::Node(node) if node.tags.contains("railway", "milestone") => {
+                    let position = node
+                        .tags
+                        .get("railway:position:exact")
+                        .or_else(|| node.tags.get("railway:position"));
+                    match position.map(|p| parse_position(p)) {
+                        Some(Ok(miles)) => mileposts.push(Milepost {
+                            elr: node.tags.get("railway:ref").map(|s| s.to_string()),
+                            lat: node.lat(),
+                            lon: node.lon(),
+                            miles,
+                        }),
+                        Some(Err(e)) => warn!("Milepost n{}: {:#}", node.id.0, e),
+                        None => (),
+                    }
+                }
+                OsmObj::Relation(rel) if rel.tags.contains("route", "tracks") => {
+                    if let Some(elr) = rel.tags.get("railway:ref") {
+                        for way in rel.refs.iter().filter_map(|r| r.member.way()) {
+                            elr_by_way.insert(way, elr.to_string());
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let ways = way_lines(input, |way| {
+            way.tags.contains_key("railway")
+                && (way.tags.contains_key("railway:ref") || elr_by_way.contains_key(&way.id))
+        })?;
+        let mut by_elr = BTreeMap::<String, Vec<WayCoords>>::new();
+        for (way, line) in ways {
+            let elr = match way.tags.get("railway:ref") {
+                Some(elr) => elr.to_string(),
+                None => elr_by_way[&way.id].clone(),
+            };
+            by_elr.entry(elr).or_default().push((way, line));
+        }
+
+        let mut lines = Vec::new();
+        for (elr, ways) in by_elr {
+            for points in chain(ways) {
+                lines.push(ElrLine::new(elr.clone(), points));
+            }
+        }
+        let mut linref = LinearRef::new(lines);
+
+        let mut placed = 0;
+        for post in mileposts {
+            let nearest = linref.nearest(post.lat, post.lon, |line| {
+                post.elr
+                    .as_ref()
+                    .map(|elr| *elr == line.elr)
+                    .unwrap_or(true)
+            });
+            match nearest {
+                Some((l, offset, distance)) if distance <= Self::MILEPOST_RADIUS => {
+                    linref.lines[l].calibration.push((offset, post.miles));
+                    placed += 1;
+                }
+                _ => warn!(
+                    "No line near milepost at {},{} ({} miles)",
+                    post.lat, post.lon, post.miles
+                ),
+            }
+        }
+        for line in &mut linref.lines {
+            line.calibration
+                .sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        }
+        info!(
+            "{} lines of track, calibrated with {} mileposts",
+            linref.lines.len(),
+            placed
+        );
+
+        Ok(linref)
+    }
+
+    fn new(lines: Vec<ElrLine>) -> Self {
+        let mut segments = Vec::new();
+        for (l, line) in lines.iter().enumerate() {
+            for (s, pair) in line.points.windows(2).enumerate() {
+                let (a, b) = (pair[0], pair[1]);
+                let seg = Line::new(project(a.0, a.1), project(b.0, b.1));
+                segments.push(GeomWithData::new(seg, (l, s)));
+            }
+        }
+        LinearRef {
+            lines,
+            tree: RTree::bulk_load(segments),
+        }
+    }
+
+    /// The ELR and mileage of the nearest line to (`lat`, `lon`), as long
+    /// as it's within `radius` metres.
+    pub fn locate(&self, lat: f64, lon: f64, radius: f64) -> Option<Position> {
+        let (l, offset, distance) = self.nearest(lat, lon, |_| true)?;
+        if distance > radius {
+            return None;
+        }
+        let line = &self.lines[l];
+        let (lat, lon) = line.at(offset);
+        Some(Position {
+            elr: line.elr.clone(),
+            miles: line.miles(offset),
+            lat,
+            lon,
+            distance,
+            calibrated: line.is_calibrated(),
+        })
+    }
+
+    /// Where `miles` along `elr` is. There may be more than one place (eg:
+    /// on each track of a double-track line), or none at all.
+    pub fn position(&self, elr: &str, miles: f64) -> Vec<Position> {
+        self.lines
+            .iter()
+            .filter(|line| line.elr == elr)
+            .filter_map(|line| {
+                let offset = line.offset(miles)?;
+                let (lat, lon) = line.at(offset);
+                Some(Position {
+                    elr: line.elr.clone(),
+                    miles,
+                    lat,
+                    lon,
+                    distance: 0.0,
+                    calibrated: line.is_calibrated(),
+                })
+            })
+            .collect()
+    }
+
+    // The line nearest to a point (of those `pred` picks out), how far
+    // along it the nearest point is, and how far away that is.
+    fn nearest<F>(&self, lat: f64, lon: f64, pred: F) -> Option<(usize, f64, f64)>
+    where
+        F: Fn(&ElrLine) -> bool,
+    {
+        let query = project(lat, lon);
+        let seg = self
+            .tree
+            .nearest_neighbor_iter(&query)
+            .find(|seg| pred(&self.lines[seg.data.0]))?;
+        let (l, s) = seg.data;
+        let line = &self.lines[l];
+        let geom = seg.geom();
+        let len_2 = geom.length_2();
+        let t = if len_2 > 0.0 {
+            (geom.from.distance_2(&geom.nearest_point(&query)) / len_2)
+                .sqrt()
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let offset = line.offsets[s] + t * (line.offsets[s + 1] - line.offsets[s]);
+        let (plat, plon) = line.at(offset);
+        let distance = Point::new(lon, lat).haversine_distance(&Point::new(plon, plat));
+        Some((l, offset, distance))
+    }
+}
+
+impl ElrLine {
+    fn new(elr: String, points: Vec<(f64, f64)>) -> Self {
+        let mut offsets = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (i, p) in points.iter().enumerate() {
+            if i > 0 {
+                let q = points[i - 1];
+                total += Point::new(q.1, q.0).haversine_distance(&Point::new(p.1, p.0));
+            }
+            offsets.push(total);
+        }
+        ElrLine {
+            elr,
+            points,
+            offsets,
+            calibration: Vec::new(),
+        }
+    }
+
+    fn is_calibrated(&self) -> bool {
+        self.calibration.len() >= 2
+    }
+
+    fn length(&self) -> f64 {
+        self.offsets.last().cloned().unwrap_or(0.0)
+    }
+
+    fn at(&self, offset: f64) -> (f64, f64) {
+        let i = match self.offsets.binary_search_by(|o| o.total_cmp(&offset)) {
+            Ok(i) => return self.points[i],
+            Err(0) => return self.points[0],
+            Err(i) if i >= self.points.len() => return self.points[self.points.len() - 1],
+            Err(i) => i - 1,
+        };
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        let t = (offset - self.offsets[i]) / (self.offsets[i + 1] - self.offsets[i]);
+        (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+    }
+
+    // The mileage `offset` metres along, interpolating between the
+    // mileposts either side (or the last two, if we're past either end).
+    fn miles(&self, offset: f64) -> f64 {
+        let cal = &self.calibration;
+        match cal.len() {
+            0 => offset / METRES_PER_MILE,
+            1 => cal[0].1 + (offset - cal[0].0) / METRES_PER_MILE,
+            n => {
+                let i = cal
+                    .windows(2)
+                    .position(|w| offset <= w[1].0)
+                    .unwrap_or(n - 2);
+                let ((o0, m0), (o1, m1)) = (cal[i], cal[i + 1]);
+                if o1 == o0 {
+                    return m0;
+                }
+                m0 + (offset - o0) * (m1 - m0) / (o1 - o0)
+            }
+        }
+    }
+
+    // The first offset along the line with the given mileage, if any.
+    fn offset(&self, miles: f64) -> Option<f64> {
+        let mut breaks = vec![0.0];
+        breaks.extend(
+            self.calibration
+                .iter()
+                .map(|c| c.0)
+                .filter(|&o| o > 0.0 && o < self.length()),
+        );
+        breaks.push(self.length());
+        breaks.windows(2).find_map(|w| {
+            let (m0, m1) = (self.miles(w[0]), self.miles(w[1]));
+            let (lo, hi) = if m0 <= m1 { (m0, m1) } else { (m1, m0) };
+            if miles < lo || miles > hi {
+                return None;
+            }
+            if m1 == m0 {
+                return Some(w[0]);
+            }
+            Some(w[0] + (miles - m0) / (m1 - m0) * (w[1] - w[0]))
+        })
+    }
+}
+
+// Joins ways that share an end node into as few lines as we can, turning
+// them round where needed. Lines are broken wherever a node is missing.
+fn chain(ways: Vec<WayCoords>) -> Vec<Vec<(f64, f64)>> {
+    let mut ways = ways
+        .into_iter()
+        .filter(|(way, _)| way.nodes.len() >= 2)
+        .map(|(way, coords)| way.nodes.into_iter().zip(coords).collect::<Vec<_>>())
+        .map(Some)
+        .collect::<Vec<_>>();
+    let ends = |nodes: &[(NodeId, _)]| (nodes[0].0, nodes[nodes.len() - 1].0);
+
+    let mut lines = Vec::new();
+    for i in 0..ways.len() {
+        let mut nodes = match ways[i].take() {
+            Some(nodes) => nodes,
+            None => continue,
+        };
+        loop {
+            let (first, last) = ends(&nodes);
+            let next = ways.iter_mut().find(|w| match w {
+                Some(more) => {
+                    let (a, b) = ends(more);
+                    a == last || b == last || a == first || b == first
+                }
+                None => false,
+            });
+            let mut more = match next.and_then(Option::take) {
+                Some(more) => more,
+                None => break,
+            };
+            // The node they share is in both, so it's left out of `more`.
+            let (a, b) = ends(&more);
+            if a == last {
+                nodes.extend(more.drain(1..));
+            } else if b == last {
+                more.reverse();
+                nodes.extend(more.drain(1..));
+            } else {
+                if a == first {
+                    more.reverse();
+                }
+                more.pop();
+                more.append(&mut nodes);
+                nodes = more;
+            }
+        }
+        let coords = nodes.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+        for line in split(&coords).0 {
+            lines.push(line.0.iter().map(|c| (c.y, c.x)).collect());
+        }
+    }
+    lines
+}
+
+/// Reads a `railway:position` value: in kilometres, unless it starts with
+/// `mi:`. Returns miles.
+pub fn parse_position(s: &str) -> Result<f64> {
+    let s = s.trim();
+    let (number, per_mile) = match s.strip_prefix("mi:") {
+        Some(miles) => (miles, 1.0),
+        None => (s, METRES_PER_MILE / 1000.0),
+    };
+    let value = number
+        .trim()
+        .parse::<f64>()
+        .with_context(|| format!("Bad railway:position {:?}", s))?;
+    Ok(value / per_mile)
+}
+
+/// Reads a mileage, either as decimal miles (`12.5`), or miles and chains
+/// (`12m 40ch`, or `12m40c`), with a leading `-` for those before the zero
+/// point.
+pub fn parse_mileage(s: &str) -> Result<f64> {
+    let s = s.trim();
+    if let Ok(miles) = s.parse::<f64>() {
+        return Ok(miles);
+    }
+    let (sign, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s),
+    };
+    let (miles, chains) = match unsigned.find('m') {
+        Some(i) => (&unsigned[..i], &unsigned[i + 1..]),
+        None => bail!("Bad mileage {:?}; expected eg: 12.5 or 12m 40ch", s),
+    };
+    let chains = chains.trim().trim_end_matches("ch").trim_end_matches('c');
+    let miles = miles.trim().parse::<f64>().ok().filter(|&m| m >= 0.0);
+    let chains = if chains.is_empty() {
+        Ok(0.0)
+    } else {
+        chains.trim().parse::<f64>()
+    };
+    match (miles, chains) {
+        (Some(m), Ok(c)) if (0.0..CHAINS_PER_MILE).contains(&c) => {
+            Ok(sign * (m + c / CHAINS_PER_MILE))
+        }
+        _ => bail!("Bad mileage {:?}; expected eg: 12.5 or 12m 40ch", s),
+    }
+}
+
+/// Writes a mileage as miles and chains, eg: `12m 40ch`. Negative
+/// mileages (before the zero point) get a leading `-`.
+pub fn format_mileage(miles: f64) -> String {
+    let sign = if miles < 0.0 { "-" } else { "" };
+    let chains = (miles.abs() * CHAINS_PER_MILE).round() as u64;
+    format!(
+        "{}{}m {:02}ch",
+        sign,
+        chains / CHAINS_PER_MILE as u64,
+        chains % CHAINS_PER_MILE as u64
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::Coord;
+    use osmpbfreader::{Tags, Way};
+
+    use super::*;
+
+    #[test]
+    fn reads_mileages() {
+        assert_eq!(parse_mileage("12.5").unwrap(), 12.5);
+        assert_eq!(parse_mileage("12m 79ch").unwrap(), 12.0 + 79.0 / 80.0);
+        assert_eq!(parse_mileage(" 12m40c ").unwrap(), 12.5);
+        assert_eq!(parse_mileage("12m").unwrap(), 12.0);
+        assert_eq!(parse_mileage("-1m 20ch").unwrap(), -1.25);
+        assert_eq!(parse_mileage("-0.25").unwrap(), -0.25);
+        for bad in &[
+            "",
+            "12m 80ch",
+            "12m -1ch",
+            "-12m -1ch",
+            "m 20ch",
+            "12 miles",
+        ] {
+            assert!(parse_mileage(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn writes_mileages() {
+        assert_eq!(format_mileage(0.0), "0m 00ch");
+        assert_eq!(format_mileage(12.0 + 79.0 / 80.0), "12m 79ch");
+        assert_eq!(format_mileage(-1.25), "-1m 20ch");
+        // Rounded to the nearest chain, which may be the next mile.
+        assert_eq!(format_mileage(12.999), "13m 00ch");
+        assert_eq!(format_mileage(12.5 + 0.4 / 80.0), "12m 40ch");
+        for &miles in &[0.0, 0.5, 12.9875, -3.0125] {
+            assert_eq!(parse_mileage(&format_mileage(miles)).unwrap(), miles);
+        }
+    }
+
+    #[test]
+    fn reads_positions() {
+        assert_eq!(parse_position("mi:12.5").unwrap(), 12.5);
+        assert!((parse_position("1.609344").unwrap() - 1.0).abs() < 1e-12);
+        assert!(parse_position("12km").is_err());
+    }
+
+    // A way along `nodes`, with node `n` at (`n` / 1000, 0), unless it's
+    // one of the `missing` ones.
+    fn way_missing(nodes: &[i64], missing: &[i64]) -> WayCoords {
+        let way = Way {
+            id: WayId(nodes[0]),
+            tags: Tags::new(),
+            nodes: nodes.iter().map(|&n| NodeId(n)).collect(),
+        };
+        let coords = nodes
+            .iter()
+            .map(|n| {
+                Some(Coord {
+                    x: 0.0,
+                    y: *n as f64 / 1000.0,
+                })
+                .filter(|_| !missing.contains(n))
+            })
+            .collect();
+        (way, coords)
+    }
+
+    fn way(nodes: &[i64]) -> WayCoords {
+        way_missing(nodes, &[])
+    }
+
+    fn points(nodes: &[i64]) -> Vec<(f64, f64)> {
+        nodes.iter().map(|&n| (n as f64 / 1000.0, 0.0)).collect()
+    }
+
+    #[test]
+    fn chains_ways_end_to_end() {
+        // Joined every which way: 3-4 onto the end, 2-1 backwards onto the
+        // start, and 6-5 backwards onto the end.
+        let lines = chain(vec![
+            way(&[2, 3]),
+            way(&[3, 4]),
+            way(&[2, 1]),
+            way(&[10, 11]),
+            way(&[6, 5, 4]),
+        ]);
+        assert_eq!(lines, vec![points(&[1, 2, 3, 4, 5, 6]), points(&[10, 11])]);
+
+        // Going the way the first one does.
+        let lines = chain(vec![way(&[3, 2]), way(&[3, 4]), way(&[1, 2])]);
+        assert_eq!(lines, vec![points(&[4, 3, 2, 1])]);
+
+        assert!(chain(vec![way(&[1])]).is_empty());
+    }
+
+    #[test]
+    fn chains_ways_with_missing_nodes() {
+        // Still joined on the node they share, but broken where it is.
+        let lines = chain(vec![
+            way_missing(&[1, 2, 3], &[3]),
+            way_missing(&[3, 4, 5], &[3]),
+        ]);
+        assert_eq!(lines, vec![points(&[1, 2]), points(&[4, 5])]);
+
+        // Missing from the far end.
+        let lines = chain(vec![way(&[1, 2]), way_missing(&[2, 3, 4], &[4])]);
+        assert_eq!(lines, vec![points(&[1, 2, 3])]);
+        let lines = chain(vec![way(&[3, 4]), way_missing(&[1, 2, 3], &[1])]);
+        assert_eq!(lines, vec![points(&[2, 3, 4])]);
+
+        // Nothing left to draw on either side.
+        let lines = chain(vec![way_missing(&[1, 2], &[2]), way_missing(&[2, 3], &[2])]);
+        assert!(lines.is_empty());
+    }
+
+    // A line due north from (0, 0), with mileposts at `calibration`.
+    fn line(calibration: &[(f64, f64)]) -> ElrLine {
+        let mut line = ElrLine::new("ABC".into(), points(&[0, 10, 20, 30, 40]));
+        line.calibration = calibration.to_vec();
+        line
+    }
+
+    #[test]
+    fn interpolates_between_mileposts() {
+        let mile = METRES_PER_MILE;
+        let line = line(&[(mile, 10.0), (2.0 * mile, 10.5), (3.0 * mile, 11.0)]);
+        assert!(line.is_calibrated());
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(line.miles(1.5 * mile), 10.25));
+        assert!(close(line.miles(2.5 * mile), 10.75));
+        // Beyond the mileposts, at the rate of the nearest two.
+        assert!(close(line.miles(0.0), 9.5));
+        assert!(close(line.miles(4.0 * mile), 11.5));
+        assert!(close(line.offset(10.75).unwrap(), 2.5 * mile));
+        assert!(close(line.offset(9.5).unwrap(), 0.0));
+        assert_eq!(line.offset(9.0), None);
+
+        // Mileage going down along the line.
+        let line = self::line(&[(mile, 5.0), (3.0 * mile, 4.0)]);
+        assert!(close(line.miles(2.0 * mile), 4.5));
+        assert!(close(line.offset(4.5).unwrap(), 2.0 * mile));
+    }
+
+    #[test]
+    fn needs_two_mileposts_to_calibrate() {
+        let mile = METRES_PER_MILE;
+        let linref = LinearRef::new(vec![line(&[]), line(&[(mile, 10.0)])]);
+        assert!(linref.position("ABC", 0.5).iter().all(|p| !p.calibrated));
+        let at = linref.locate(0.01, 0.0, 10.0).unwrap();
+        assert!(!at.calibrated);
+
+        let linref = LinearRef::new(vec![line(&[(0.0, 10.0), (mile, 11.0)])]);
+        let at = linref.locate(0.01, 0.0, 10.0).unwrap();
+        assert!(at.calibrated);
+        assert_eq!(at.elr, "ABC");
+        assert!((at.miles - (10.0 + 0.01_f64.to_radians() * 6_371_008.8 / mile)).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,592 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::{NodeId, OsmId, OsmObj, Tags};
+use serde::Serialize;
+
+use crate::{filter::BBox, network::Feature};
+
+/// The checks we know how to make.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    /// Stations in Great Britain should have a `ref:crs` code.
+    StationWithoutCrs,
+    /// Each CRS code should only be used by one station.
+    DuplicateCrs,
+    /// Stop areas should have a station node in them.
+    StopAreaWithoutStation,
+    /// Railway ways need at least two nodes.
+    SingleNodeWay,
+    /// Track that stops dead should end with a `railway=buffer_stop`. We
+    /// can't tell a dead end from track that carries on past the edge of
+    /// the extract (or of `--bbox`), so those are reported too.
+    DeadEnd,
+    /// `electrified` and `voltage` should agree.
+    ElectrifiedVoltage,
+    /// `railway` values should be ones listed on the wiki.
+    UnknownRailway,
+}
+
+/// Something a rule found wrong with an object. `at` is a node we can use
+/// to show where it is.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub rule: Rule,
+    pub id: OsmId,
+    pub at: Option<NodeId>,
+    pub message: String,
+}
+
+/// Runs the rules over a stream of objects, which must be in the usual
+/// order (nodes, then ways, then relations). Some rules (eg: `DeadEnd`)
+/// can only tell once they've seen everything, so call `finish` to get
+/// the results.
+#[derive(Debug)]
+pub struct Linter {
+    rules: HashSet<Rule>,
+    crs_area: BBox,
+    findings: Vec<Finding>,
+    stations: HashSet<NodeId>,
+    buffer_stops: HashSet<NodeId>,
+    stations_by_crs: BTreeMap<String, Vec<NodeId>>,
+    // For each node on a piece of track, how many track segments end there,
+    // and a way to blame if it turns out to be a dead end.
+    track_ends: HashMap<NodeId, (u32, OsmId)>,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::StationWithoutCrs,
+        Rule::DuplicateCrs,
+        Rule::StopAreaWithoutStation,
+        Rule::SingleNodeWay,
+        Rule::DeadEnd,
+        Rule::ElectrifiedVoltage,
+        Rule::UnknownRailway,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::StationWithoutCrs => "station-without-crs",
+            Rule::DuplicateCrs => "duplicate-crs",
+            Rule::StopAreaWithoutStation => "stop-area-without-station",
+            Rule::SingleNodeWay => "single-node-way",
+            Rule::DeadEnd => "dead-end",
+            Rule::ElectrifiedVoltage => "electrified-voltage",
+            Rule::UnknownRailway => "unknown-railway",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Rule::ALL.iter().find(|r| r.name() == s) {
+            Some(rule) => Ok(*rule),
+            None => bail!(
+                "Unknown rule {:?}; expected one of: {}",
+                s,
+                Rule::ALL
+                    .iter()
+                    .map(|r| r.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// Roughly Great Britain, where CRS codes are used. This takes in a little
+/// of Ireland and France, but no stations there should be tagged as GB
+/// ones.
+pub const GREAT_BRITAIN: BBox = BBox {
+    min_lon: -8.2,
+    min_lat: 49.8,
+    max_lon: 1.8,
+    max_lat: 60.9,
+};
+
+// From https://wiki.openstreetmap.org/wiki/Key:railway (and the
+// OpenRailwayMap tagging pages it links to), including the lifecycle
+// prefixes used as values. Kept sorted.
+const RAILWAY_VALUES: &[&str] = &[
+    "abandoned",
+    "aei",
+    "blockpost",
+    "border",
+    "buffer_stop",
+    "construction",
+    "crossing",
+    "crossing_box",
+    "crossover",
+    "defect_detector",
+    "derail",
+    "dismantled",
+    "disused",
+    "fuel",
+    "funicular",
+    "halt",
+    "hump_yard",
+    "isolated_track_section",
+    "junction",
+    "level_crossing",
+    "light_rail",
+    "loading_gauge",
+    "lubricator",
+    "milestone",
+    "miniature",
+    "monorail",
+    "narrow_gauge",
+    "owner_change",
+    "phone",
+    "platform",
+    "platform_edge",
+    "power_supply",
+    "preheating",
+    "preserved",
+    "proposed",
+    "radio",
+    "rail",
+    "railway_crossing",
+    "razed",
+    "rolling_highway",
+    "roundhouse",
+    "sand_stop",
+    "service_station",
+    "signal",
+    "signal_box",
+    "site",
+    "spur_junction",
+    "station",
+    "stop",
+    "subway",
+    "subway_entrance",
+    "switch",
+    "train_protection",
+    "tram",
+    "tram_crossing",
+    "tram_level_crossing",
+    "tram_stop",
+    "tram_switch",
+    "traverser",
+    "turntable",
+    "vacancy_detection",
+    "ventilation_shaft",
+    "wash",
+    "water_crane",
+    "workshop",
+    "yard",
+];
+
+// Running lines, where a dead end means something. Disused and abandoned
+// lines are expected to stop anywhere.
+const TRACK_VALUES: &[&str] = &[
+    "rail",
+    "light_rail",
+    "subway",
+    "tram",
+    "narrow_gauge",
+    "monorail",
+    "funicular",
+    "miniature",
+    "preserved",
+];
+
+impl Linter {
+    pub fn new(rules: &[Rule], crs_area: BBox) -> Self {
+        Linter {
+            rules: rules.iter().cloned().collect(),
+            crs_area,
+            findings: Vec::new(),
+            stations: HashSet::new(),
+            buffer_stops: HashSet::new(),
+            stations_by_crs: BTreeMap::new(),
+            track_ends: HashMap::new(),
+        }
+    }
+
+    pub fn check(&mut self, obj: &OsmObj) {
+        let tags = obj.tags();
+        if let Some(railway) = tags.get("railway") {
+            if !RAILWAY_VALUES.contains(&railway.as_str()) {
+                self.report(
+                    Rule::UnknownRailway,
+                    obj,
+                    format!("Unknown railway={}", railway),
+                );
+            }
+        }
+        self.check_electrification(obj, tags);
+
+        self.note(obj);
+        match obj {
+            OsmObj::Node(node) => {
+                if !self.stations.contains(&node.id) {
+                    return;
+                }
+                match tags.get("ref:crs") {
+                    Some(crs) => self
+                        .stations_by_crs
+                        .entry(crs.to_string())
+                        .or_default()
+                        .push(node.id),
+                    None if self.crs_area.contains(node.lat(), node.lon())
+                        && is_main_line_station(tags) =>
+                    {
+                        self.report(
+                            Rule::StationWithoutCrs,
+                            obj,
+                            "Station has no ref:crs".into(),
+                        )
+                    }
+                    None => (),
+                }
+            }
+            OsmObj::Way(way) => {
+                if !tags.contains_key("railway") {
+                    return;
+                }
+                if way.nodes.len() < 2 {
+                    self.report(
+                        Rule::SingleNodeWay,
+                        obj,
+                        format!("Way has {} nodes", way.nodes.len()),
+                    );
+                }
+                let is_track = tags
+                    .get("railway")
+                    .map(|r| TRACK_VALUES.contains(&r.as_str()))
+                    .unwrap_or(false);
+                if is_track && self.rules.contains(&Rule::DeadEnd) {
+                    for pair in way.nodes.windows(2) {
+                        for node in pair {
+                            self.track_ends.entry(*node).or_insert((0, obj.id())).0 += 1;
+                        }
+                    }
+                }
+            }
+            OsmObj::Relation(rel) => {
+                if !tags.contains("public_transport", "stop_area") {
+                    return;
+                }
+                let has_station = rel
+                    .refs
+                    .iter()
+                    .filter_map(|r| r.member.node())
+                    .any(|n| self.stations.contains(&n));
+                if !has_station {
+                    self.report(
+                        Rule::StopAreaWithoutStation,
+                        obj,
+                        "Stop area has no station node".into(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Takes note of what other objects' checks need to know about this
+    /// one (whether it's a buffer stop, or a station), without checking it.
+    /// `check` does this itself, so this is for the objects being left out,
+    /// eg: by a tag filter, so that track ending at a buffer stop that's
+    /// been filtered out isn't reported as a dead end.
+    pub fn note(&mut self, obj: &OsmObj) {
+        if let OsmObj::Node(node) = obj {
+            if node.tags.contains("railway", "buffer_stop") {
+                self.buffer_stops.insert(node.id);
+            }
+            if Feature::of(obj) == Some(Feature::Station) {
+                self.stations.insert(node.id);
+            }
+        }
+    }
+
+    fn check_electrification(&mut self, obj: &OsmObj, tags: &Tags) {
+        let electrified = match tags.get("electrified") {
+            Some(e) => e.as_str(),
+            None => return,
+        };
+        // `voltage` may have several values, eg: `25000;750`.
+        let voltages: Vec<Option<f64>> = tags
+            .get("voltage")
+            .map(|v| v.split(';').map(|v| v.trim().parse::<f64>().ok()).collect())
+            .unwrap_or_default();
+        let message = match electrified {
+            "no" if voltages.iter().any(|v| v.map(|v| v > 0.0).unwrap_or(true)) => {
+                "electrified=no, but it has a voltage"
+            }
+            "no" => return,
+            _ if voltages.contains(&Some(0.0)) => "Electrified, but with no voltage",
+            _ => return,
+        };
+        self.report(Rule::ElectrifiedVoltage, obj, message.into());
+    }
+
+    fn report(&mut self, rule: Rule, obj: &OsmObj, message: String) {
+        if !self.rules.contains(&rule) {
+            return;
+        }
+        let at = match obj {
+            OsmObj::Node(n) => Some(n.id),
+            OsmObj::Way(w) => w.nodes.first().cloned(),
+            OsmObj::Relation(r) => r.refs.iter().find_map(|r| r.member.node()),
+        };
+        self.findings.push(Finding {
+            rule,
+            id: obj.id(),
+            at,
+            message,
+        });
+    }
+
+    /// Everything we've found, by rule and then id.
+    pub fn finish(mut self) -> Vec<Finding> {
+        if self.rules.contains(&Rule::DuplicateCrs) {
+            for (crs, nodes) in &self.stations_by_crs {
+                if nodes.len() < 2 {
+                    continue;
+                }
+                for &node in nodes {
+                    self.findings.push(Finding {
+                        rule: Rule::DuplicateCrs,
+                        id: node.into(),
+                        at: Some(node),
+                        message: format!("{} stations have ref:crs={}", nodes.len(), crs),
+                    });
+                }
+            }
+        }
+        for (&node, &(segments, way)) in &self.track_ends {
+            if segments == 1 && !self.buffer_stops.contains(&node) {
+                self.findings.push(Finding {
+                    rule: Rule::DeadEnd,
+                    id: way,
+                    at: Some(node),
+                    message: format!("Track ends at n{} without a buffer stop", node.0),
+                });
+            }
+        }
+
+        self.findings.sort_by_key(|f| (f.rule, f.id, f.at));
+        self.findings
+    }
+}
+
+/// Looks up where each finding is, with another pass over the input.
+pub fn locate(
+    findings: &[Finding],
+    objs: impl Iterator<Item = Result<OsmObj>>,
+) -> Result<HashMap<NodeId, (f64, f64)>> {
+    let mut wanted = findings.iter().filter_map(|f| f.at).collect::<HashSet<_>>();
+    let mut found = HashMap::new();
+    for it in objs {
+        if wanted.is_empty() {
+            break;
+        }
+        if let OsmObj::Node(node) = it.context("Read item")? {
+            if wanted.remove(&node.id) {
+                found.insert(node.id, (node.lat(), node.lon()));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Heritage and metro stations don't usually have CRS codes.
+fn is_main_line_station(tags: &Tags) -> bool {
+    !(tags.contains("usage", "tourism")
+        || tags.contains("railway:preserved", "yes")
+        || tags.contains_key("station") && !tags.contains("station", "train"))
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{Node, Ref, Relation, RelationId, Way, WayId};
+
+    use super::*;
+
+    fn tags(tags: &[(&str, &str)]) -> Tags {
+        tags.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    fn node(id: i64, lat: f64, t: &[(&str, &str)]) -> OsmObj {
+        Node {
+            id: NodeId(id),
+            tags: tags(t),
+            decimicro_lat: (lat * 1e7) as i32,
+            decimicro_lon: 0,
+        }
+        .into()
+    }
+
+    fn way(id: i64, nodes: &[i64], t: &[(&str, &str)]) -> OsmObj {
+        Way {
+            id: WayId(id),
+            tags: tags(t),
+            nodes: nodes.iter().map(|&n| NodeId(n)).collect(),
+        }
+        .into()
+    }
+
+    fn stop_area(id: i64, nodes: &[i64]) -> OsmObj {
+        Relation {
+            id: RelationId(id),
+            tags: tags(&[("public_transport", "stop_area")]),
+            refs: nodes
+                .iter()
+                .map(|&n| Ref {
+                    member: NodeId(n).into(),
+                    role: "".into(),
+                })
+                .collect(),
+        }
+        .into()
+    }
+
+    // Checks `objs` with `rule`, and `noted` without checking them.
+    fn lint(rule: Rule, objs: &[OsmObj], noted: &[OsmObj]) -> Vec<(OsmId, Option<NodeId>)> {
+        let mut linter = Linter::new(&[rule], GREAT_BRITAIN);
+        for obj in noted {
+            linter.note(obj);
+        }
+        for obj in objs {
+            linter.check(obj);
+        }
+        linter
+            .finish()
+            .into_iter()
+            .inspect(|f| assert_eq!(f.rule, rule))
+            .map(|f| (f.id, f.at))
+            .collect()
+    }
+
+    fn at(id: i64) -> (OsmId, Option<NodeId>) {
+        (NodeId(id).into(), Some(NodeId(id)))
+    }
+
+    #[test]
+    fn finds_stations_without_crs_in_the_crs_area() {
+        let station = &[("railway", "station")];
+        let objs = [
+            node(1, 51.5, station),
+            node(2, 51.5, &[("railway", "station"), ("ref:crs", "KGX")]),
+            // Outside of Great Britain.
+            node(3, 48.8, station),
+            // Heritage and metro stations.
+            node(4, 51.5, &[("railway", "station"), ("usage", "tourism")]),
+            node(5, 51.5, &[("railway", "station"), ("station", "subway")]),
+        ];
+        assert_eq!(lint(Rule::StationWithoutCrs, &objs, &[]), vec![at(1)]);
+    }
+
+    #[test]
+    fn finds_duplicate_crs() {
+        let objs = [
+            node(1, 51.5, &[("railway", "station"), ("ref:crs", "KGX")]),
+            node(2, 51.5, &[("railway", "station"), ("ref:crs", "EUS")]),
+            node(3, 51.5, &[("railway", "station"), ("ref:crs", "KGX")]),
+        ];
+        assert_eq!(lint(Rule::DuplicateCrs, &objs, &[]), vec![at(1), at(3)]);
+    }
+
+    #[test]
+    fn finds_stop_areas_without_stations() {
+        let objs = [
+            node(1, 51.5, &[("railway", "station")]),
+            node(2, 51.5, &[("public_transport", "platform")]),
+            stop_area(1, &[1, 2]),
+            stop_area(2, &[2]),
+        ];
+        assert_eq!(
+            lint(Rule::StopAreaWithoutStation, &objs, &[]),
+            vec![(RelationId(2).into(), Some(NodeId(2)))]
+        );
+        // Stations that aren't being checked still count.
+        assert_eq!(
+            lint(Rule::StopAreaWithoutStation, &objs[2..3], &objs[..1]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn finds_single_node_ways() {
+        let rail = &[("railway", "rail")];
+        let objs = [way(1, &[1, 2], rail), way(2, &[3], rail), way(3, &[4], &[])];
+        assert_eq!(
+            lint(Rule::SingleNodeWay, &objs, &[]),
+            vec![(WayId(2).into(), Some(NodeId(3)))]
+        );
+    }
+
+    #[test]
+    fn finds_conflicting_electrification() {
+        let objs = [
+            way(1, &[1, 2], &[("electrified", "no"), ("voltage", "25000")]),
+            way(
+                2,
+                &[1, 2],
+                &[("electrified", "contact_line"), ("voltage", "0")],
+            ),
+            way(3, &[1, 2], &[("electrified", "rail"), ("voltage", "750;0")]),
+            way(4, &[1, 2], &[("electrified", "no"), ("voltage", "0")]),
+            way(5, &[1, 2], &[("electrified", "no")]),
+            way(6, &[1, 2], &[("electrified", "rail"), ("voltage", "750")]),
+            way(7, &[1, 2], &[("voltage", "0")]),
+        ];
+        let found = lint(Rule::ElectrifiedVoltage, &objs, &[]);
+        assert_eq!(
+            found.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![WayId(1).into(), WayId(2).into(), WayId(3).into()]
+        );
+    }
+
+    #[test]
+    fn finds_unknown_railway_values() {
+        let objs = [
+            node(1, 51.5, &[("railway", "signal")]),
+            node(2, 51.5, &[("railway", "sigal")]),
+            way(1, &[1, 2], &[("railway", "rail")]),
+            way(2, &[1, 2], &[("railway", "Rail")]),
+        ];
+        assert_eq!(
+            lint(Rule::UnknownRailway, &objs, &[]),
+            vec![at(2), (WayId(2).into(), Some(NodeId(1)))]
+        );
+    }
+
+    #[test]
+    fn finds_dead_ends() {
+        let rail = &[("railway", "rail")];
+        // A line from 1 to 4, with a siding off 2 to 5 ending at a buffer
+        // stop, and a disused branch off 3 to 6.
+        let buffer_stop = [node(5, 51.5, &[("railway", "buffer_stop")])];
+        let objs = [
+            way(1, &[1, 2, 3], rail),
+            way(2, &[3, 4], rail),
+            way(3, &[2, 5], rail),
+            way(4, &[3, 6], &[("railway", "disused")]),
+        ];
+        let way_at = |way, node| (WayId(way).into(), Some(NodeId(node)));
+        assert_eq!(
+            lint(Rule::DeadEnd, &objs, &buffer_stop),
+            vec![way_at(1, 1), way_at(2, 4)]
+        );
+        // Without the buffer stop, the siding is a dead end too.
+        assert_eq!(
+            lint(Rule::DeadEnd, &objs, &[]),
+            vec![way_at(1, 1), way_at(2, 4), way_at(3, 5)]
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::HashSet;
+
+use geo::{HaversineDistance, Point};
+use log::{debug, warn};
+use osmpbfreader::OsmId;
+
+use crate::{
+    compact::{CompactMap, Vertex},
+    index::{SegmentIndex, Snap},
+    routing::{length, ShortestPaths},
+    trace::Fix,
+};
+
+/// How `Matcher` weighs things up, after Newson & Krumm, "Hidden Markov
+/// Map Matching Through Noise and Sparseness" (2009).
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+    /// How far (in metres) from a point we look for track.
+    pub radius: f64,
+    /// How many of the nearest edges to consider for each point.
+    pub candidates: usize,
+    /// The standard deviation of the GPS error, in metres.
+    pub sigma: f64,
+    /// How much (in metres) we expect the distance along the track between
+    /// two points to differ from the straight line between them. Larger
+    /// values put up with more roundabout routes.
+    pub beta: f64,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            radius: 50.0,
+            candidates: 8,
+            sigma: 10.0,
+            beta: 50.0,
+        }
+    }
+}
+
+/// Works out the most likely way a trace went along the track, with the
+/// Viterbi algorithm.
+pub struct Matcher<'a> {
+    map: &'a CompactMap,
+    segments: &'a SegmentIndex,
+    params: Params,
+}
+
+/// Where we think the `fix`th point really was. `snap` is `None` for
+/// points with no track nearby. `route` is how we got here from the last
+/// matched point: the vertices we passed through, with the edge we took to
+/// each. It's empty for the first point, after a break in the trace, or
+/// when we stayed on the same edge.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub fix: usize,
+    pub snap: Option<Snap>,
+    pub route: Vec<(Vertex, OsmId)>,
+}
+
+// The candidates for one (matched) point.
+struct Layer {
+    fix: usize,
+    snaps: Vec<Snap>,
+    score: Vec<f64>,
+    // The best candidate in the previous layer for each of ours, or `None`
+    // if the trace starts again here.
+    back: Vec<Option<usize>>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(map: &'a CompactMap, segments: &'a SegmentIndex, params: Params) -> Self {
+        Matcher {
+            map,
+            segments,
+            params,
+        }
+    }
+
+    /// Matches each of `fixes`, in order. Where there's no way along the
+    /// track from one point to the next (eg: the GPS dropped out for a long
+    /// time), we start again from scratch.
+    pub fn run(&self, fixes: &[Fix]) -> Vec<Step> {
+        let mut layers = Vec::<Layer>::new();
+        for (i, fix) in fixes.iter().enumerate() {
+            let mut snaps = self
+                .segments
+                .within(self.map, fix.lat, fix.lon, self.params.radius);
+            snaps.truncate(self.params.candidates);
+            if snaps.is_empty() {
+                debug!("No track near point {}", i);
+                continue;
+            }
+            let emission = snaps.iter().map(|s| self.emission(s)).collect::<Vec<_>>();
+
+            let (score, back) = match layers.last() {
+                Some(prev) => self.transitions(prev, &fixes[prev.fix], fix, &snaps, &emission),
+                None => (emission, vec![None; snaps.len()]),
+            };
+            if back.iter().all(Option::is_none) && !layers.is_empty() {
+                warn!("Can't get to point {} along the track; starting again", i);
+            }
+            layers.push(Layer {
+                fix: i,
+                snaps,
+                score,
+                back,
+            });
+        }
+
+        // Work back from the best final candidate, jumping to the best
+        // candidate in the previous layer wherever the trace starts again.
+        let mut chosen = vec![0; layers.len()];
+        let mut next = None;
+        for k in (0..layers.len()).rev() {
+            let c = next.unwrap_or_else(|| best(&layers[k].score));
+            chosen[k] = c;
+            next = layers[k].back[c];
+        }
+
+        let mut steps = Vec::with_capacity(fixes.len());
+        let mut k = 0;
+        for i in 0..fixes.len() {
+            if k < layers.len() && layers[k].fix == i {
+                let snap = layers[k].snaps[chosen[k]];
+                let route = match layers[k].back[chosen[k]] {
+                    Some(_) => {
+                        let prev = &layers[k - 1];
+                        let limit = self.limit(straight(&fixes[prev.fix], &fixes[i]));
+                        self.route(&prev.snaps[chosen[k - 1]], &snap, limit)
+                    }
+                    None => Vec::new(),
+                };
+                steps.push(Step {
+                    fix: i,
+                    snap: Some(snap),
+                    route,
+                });
+                k += 1;
+            } else {
+                steps.push(Step {
+                    fix: i,
+                    snap: None,
+                    route: Vec::new(),
+                });
+            }
+        }
+        steps
+    }
+
+    // In log space, leaving out the constant factors.
+    fn emission(&self, snap: &Snap) -> f64 {
+        let z = snap.distance / self.params.sigma;
+        -0.5 * z * z
+    }
+
+    fn transitions(
+        &self,
+        prev: &Layer,
+        from: &Fix,
+        to: &Fix,
+        snaps: &[Snap],
+        emission: &[f64],
+    ) -> (Vec<f64>, Vec<Option<usize>>) {
+        let straight = straight(from, to);
+        let mut score = vec![f64::NEG_INFINITY; snaps.len()];
+        let mut back = vec![None; snaps.len()];
+
+        for (p, prev_snap) in prev.snaps.iter().enumerate() {
+            if prev.score[p] == f64::NEG_INFINITY {
+                continue;
+            }
+            let distances = self.distances(prev_snap, snaps, straight);
+            for (q, d) in distances.into_iter().enumerate() {
+                let s = prev.score[p] - (d - straight).abs() / self.params.beta + emission[q];
+                if s > score[q] {
+                    score[q] = s;
+                    back[q] = Some(p);
+                }
+            }
+        }
+
+        // Nothing joins up, so start again with just the emissions.
+        if back.iter().all(Option::is_none) {
+            return (emission.to_vec(), back);
+        }
+        (score, back)
+    }
+
+    // How far along the track we'll look for the way from one point to the
+    // next, when they're `straight` metres apart. Trains don't take
+    // roundabout routes, so anything further than this is infinitely
+    // unlikely.
+    fn limit(&self, straight: f64) -> f64 {
+        3.0 * straight + 2.0 * self.params.radius + 1000.0
+    }
+
+    // The distance along the track from `from` to each of `to`, or infinity
+    // if it's much further than the points are apart.
+    fn distances(&self, from: &Snap, to: &[Snap], straight: f64) -> Vec<f64> {
+        let limit = self.limit(straight);
+        let mut paths = self.paths(from);
+        let mut targets = HashSet::new();
+        for s in to {
+            targets.insert(s.from);
+            targets.insert(s.to);
+        }
+        for (v, d) in &mut paths {
+            targets.remove(&v);
+            if targets.is_empty() || d > limit {
+                break;
+            }
+        }
+
+        to.iter()
+            .map(|s| {
+                let len = length(self.map, s.from, s.to);
+                let via_from = paths.distance(s.from).unwrap_or(f64::INFINITY) + s.along * len;
+                let via_to = paths.distance(s.to).unwrap_or(f64::INFINITY) + (1.0 - s.along) * len;
+                let mut d = via_from.min(via_to);
+                if (s.from, s.to) == (from.from, from.to) {
+                    d = d.min((s.along - from.along).abs() * len);
+                }
+                if d > limit {
+                    f64::INFINITY
+                } else {
+                    d
+                }
+            })
+            .collect()
+    }
+
+    // Along the track from a point partway along an edge, so we're already
+    // some way towards either end. Trains can't get from one line to
+    // another through a stop area, so we only go along ways.
+    fn paths(&self, snap: &Snap) -> ShortestPaths<'a> {
+        let len = length(self.map, snap.from, snap.to);
+        let sources = [
+            (snap.from, snap.along * len),
+            (snap.to, (1.0 - snap.along) * len),
+        ];
+        ShortestPaths::new(self.map, sources).track_only()
+    }
+
+    // How we got from `from` to `to`, if it's within `limit`.
+    fn route(&self, from: &Snap, to: &Snap, limit: f64) -> Vec<(Vertex, OsmId)> {
+        let len = length(self.map, to.from, to.to);
+        let mut paths = self.paths(from);
+        let (mut best, mut end) = (limit, None);
+        for (v, d) in &mut paths {
+            if d > best {
+                break;
+            }
+            for (target, rest) in [(to.from, to.along), (to.to, 1.0 - to.along)].iter() {
+                if v == *target && d + rest * len < best {
+                    best = d + rest * len;
+                    end = Some(v);
+                }
+            }
+        }
+        if (from.from, from.to) == (to.from, to.to) && (to.along - from.along).abs() * len <= best {
+            return Vec::new();
+        }
+
+        end.and_then(|end| paths.path_to(end))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(v, via)| (v, via.unwrap_or(from.via)))
+            .collect()
+    }
+}
+
+fn straight(from: &Fix, to: &Fix) -> f64 {
+    Point::new(from.lon, from.lat).haversine_distance(&Point::new(to.lon, to.lat))
+}
+
+fn best(score: &[f64]) -> usize {
+    score
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, WayId};
+
+    use super::*;
+    use crate::fixtures;
+
+    // A line (way 1) along the equator, with a station halfway along, and a
+    // loop (way 2) about 33m north of it, joined on at either end. Each
+    // 0.002 degrees is about 222m.
+    fn map() -> CompactMap {
+        let mut coords = (0..6).map(|i| (0.0, 0.002 * i as f64)).collect::<Vec<_>>();
+        coords.extend((1..5).map(|i| (0.0003, 0.002 * i as f64)));
+        fixtures::network(
+            &coords,
+            &[&[0, 1, 2, 3, 4, 5], &[0, 6, 7, 8, 9, 5]],
+            &[],
+            &[3],
+        )
+    }
+
+    fn fix(lat: f64, lon: f64) -> Fix {
+        Fix {
+            lat,
+            lon,
+            time: None,
+        }
+    }
+
+    fn run(map: &CompactMap, fixes: &[Fix]) -> Vec<Step> {
+        let segments = SegmentIndex::new(map, |via| matches!(via, OsmId::Way(_)));
+        Matcher::new(map, &segments, Params::default()).run(fixes)
+    }
+
+    fn ways(steps: &[Step]) -> Vec<Option<i64>> {
+        steps
+            .iter()
+            .map(|s| s.snap.map(|s| s.via.way().expect("way").0))
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_line() {
+        let map = map();
+        let fixes = (0..5)
+            .map(|i| fix(0.00002, 0.001 + 0.002 * i as f64))
+            .collect::<Vec<_>>();
+        let steps = run(&map, &fixes);
+        assert_eq!(ways(&steps), vec![Some(1); 5]);
+        for (step, fix) in steps.iter().zip(&fixes) {
+            let snap = step.snap.unwrap();
+            assert!((snap.lon - fix.lon).abs() < 1e-9, "{:?}", snap);
+            assert!(snap.lat.abs() < 1e-9, "{:?}", snap);
+        }
+
+        // Through each vertex in turn, and the station.
+        let route = steps
+            .iter()
+            .flat_map(|s| s.route.iter())
+            .map(|&(v, via)| (map.node_id(v).0, via))
+            .collect::<Vec<_>>();
+        let way = OsmId::Way(WayId(1));
+        assert_eq!(route, (2..=5).map(|n| (n, way)).collect::<Vec<_>>());
+        let stations = route
+            .iter()
+            .filter(|(n, _)| map.is_station(map.vertex(NodeId(*n)).unwrap()))
+            .map(|(n, _)| *n)
+            .collect::<Vec<_>>();
+        assert_eq!(stations, vec![4]);
+    }
+
+    #[test]
+    fn ignores_noise_towards_a_parallel_line() {
+        let map = map();
+        // The middle point is nearer the loop than the line, but getting
+        // there and back would mean going all the way round.
+        let fixes = [
+            fix(0.0, 0.003),
+            fix(0.0, 0.005),
+            fix(0.0002, 0.006),
+            fix(0.0, 0.007),
+            fix(0.0, 0.009),
+        ];
+        let segments = SegmentIndex::new(&map, |via| matches!(via, OsmId::Way(_)));
+        let nearest = segments.within(&map, 0.0002, 0.006, 50.0);
+        assert_eq!(nearest[0].via, OsmId::Way(WayId(2)));
+
+        assert_eq!(ways(&run(&map, &fixes)), vec![Some(1); 5]);
+    }
+
+    #[test]
+    fn starts_again_after_a_gap() {
+        // Two lines about 1km apart, that don't join up.
+        let map = fixtures::network(
+            &[
+                (0.0, 0.0),
+                (0.0, 0.002),
+                (0.0, 0.004),
+                (0.01, 0.0),
+                (0.01, 0.002),
+                (0.01, 0.004),
+            ],
+            &[&[0, 1, 2], &[3, 4, 5]],
+            &[],
+            &[],
+        );
+        let fixes = [
+            fix(0.0, 0.001),
+            fix(0.0, 0.003),
+            // Nowhere near either.
+            fix(0.005, 0.002),
+            fix(0.01, 0.001),
+            fix(0.01, 0.003),
+        ];
+        let steps = run(&map, &fixes);
+        assert_eq!(ways(&steps), vec![Some(1), Some(1), None, Some(2), Some(2)]);
+        assert_eq!(
+            steps.iter().map(|s| s.fix).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        // Each line is followed through its middle node, but there's no
+        // route from one to the other.
+        let routes = steps
+            .iter()
+            .map(|s| s.route.iter().map(|&(v, _)| map.node_id(v).0).collect())
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(routes, vec![vec![], vec![2], vec![], vec![], vec![5]]);
+    }
+}
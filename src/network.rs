@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use log::trace;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Relation, Tags, Way};
+use petgraph::{
+    graph::{NodeIndex, UnGraph},
+    visit::EdgeRef,
+};
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String;
+
+use crate::{filter::is_network, osc::Action};
+
+/// The railway network as a graph between nodes. Consecutive nodes of
+/// railway ways are joined, as are all the nodes in a stop area, with each
+/// edge labelled with the object it came from.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Map {
+    pub graph: UnGraph<NodeId, OsmId>,
+    pub vertex_by_osm_id: BTreeMap<NodeId, NodeIndex>,
+    pub osm_id_by_vertex: BTreeMap<NodeIndex, NodeId>,
+    pub objs: BTreeMap<OsmId, OsmObj>,
+    pub node_id_by_crs: BTreeMap<String, NodeId>,
+}
+
+impl Map {
+    pub fn from_reader(objs: impl Iterator<Item = Result<OsmObj>>) -> Result<Self> {
+        let mut map = Map::default();
+
+        for it in objs {
+            let it = it.context("Read item")?;
+            match it {
+                OsmObj::Node(n) => map.add_node(n),
+                OsmObj::Way(w) => map.add_way(w),
+                OsmObj::Relation(r) => map.add_rel(r),
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn obj_by_idx(&self, idx: NodeIndex) -> Option<OsmObj> {
+        self.id_by_idx(idx)
+            .and_then(|osm_id| self.objs.get(&osm_id.into()).cloned())
+    }
+
+    pub fn id_by_idx(&self, idx: NodeIndex) -> Option<NodeId> {
+        self.osm_id_by_vertex.get(&idx).cloned()
+    }
+
+    // We mostly just want railway=station here.
+    fn add_node(&mut self, node: Node) {
+        if !is_network(&node.tags) {
+            return;
+        }
+
+        let vid = self.index(node.id);
+        trace!(
+            "{:?}[{:?}]: {:?}, {},{}",
+            node.id,
+            vid,
+            node.tags,
+            node.lat(),
+            node.lon()
+        );
+        if let Some(crs) = node.tags.get("ref:crs") {
+            self.node_id_by_crs.insert(crs.clone(), node.id);
+        }
+        self.objs.insert(node.id.into(), node.into());
+    }
+
+    // "We mostly just care about routes here."
+    fn add_way(&mut self, w: Way) {
+        if !is_network(&w.tags) {
+            return;
+        }
+
+        trace!("{:?}: {:?}; {:?}", OsmId::from(w.id), w.tags, w.nodes);
+
+        for (a, b) in w.nodes.iter().cloned().zip(w.nodes.iter().skip(1).cloned()) {
+            let a_idx = self.index(a);
+            let b_idx = self.index(b);
+            trace!("\t{:?}[{:?}] -- {:?}[{:?}]", a, a_idx, b, b_idx);
+            self.graph.add_edge(a_idx, b_idx, w.id.into());
+        }
+        self.objs.insert(w.id.into(), w.into());
+    }
+
+    fn add_rel(&mut self, r: Relation) {
+        if r.tags.get("public_transport").map(|s| &**s) != Some("stop_area") {
+            return;
+        }
+
+        trace!("{:?}: {:?}; {:?}", OsmId::from(r.id), r.tags, r.refs);
+
+        let nodes = r.refs.iter().flat_map(|r| r.member.node());
+        for a in nodes.clone() {
+            let a_idx = self.index(a);
+            for b in nodes.clone().filter(|&b| a < b) {
+                let b_idx = self.index(b);
+                trace!("\t{:?}[{:?}] -- {:?}[{:?}]", a, a_idx, b, b_idx);
+                self.graph.add_edge(a_idx, b_idx, r.id.into());
+            }
+        }
+        self.objs.insert(r.id.into(), r.into());
+    }
+
+    /// Applies a single change from an OsmChange file, and tells you what
+    /// (if anything) of interest changed.
+    ///
+    /// Vertexes that end up with no edges (and aren't stations or the like
+    /// themselves) are removed from the graph, so the network doesn't keep
+    /// growing as changes come in. That moves another vertex into the gap,
+    /// so indexes from before a change may no longer be valid after it.
+    pub fn apply(&mut self, action: Action, obj: OsmObj) -> Option<Changed> {
+        let id = obj.id();
+        let old = self.remove(id);
+        let touched = old.as_ref().map(node_ids).unwrap_or_default();
+
+        let feature = Feature::of(&obj).or_else(|| old.as_ref().and_then(Feature::of));
+        let name = obj
+            .tags()
+            .get("name")
+            .or_else(|| old.as_ref().and_then(|o| o.tags().get("name")))
+            .cloned();
+
+        if action != Action::Delete {
+            match obj {
+                OsmObj::Node(n) => self.add_node(n),
+                OsmObj::Way(w) => self.add_way(w),
+                OsmObj::Relation(r) => self.add_rel(r),
+            }
+        }
+        for node_id in touched {
+            self.prune(node_id);
+        }
+
+        feature.map(|feature| Changed {
+            action,
+            feature,
+            id,
+            name,
+        })
+    }
+
+    fn remove(&mut self, id: OsmId) -> Option<OsmObj> {
+        let old = self.objs.remove(&id)?;
+        match &old {
+            OsmObj::Node(n) => {
+                if let Some(crs) = n.tags.get("ref:crs") {
+                    if self.node_id_by_crs.get(crs) == Some(&n.id) {
+                        self.node_id_by_crs.remove(crs);
+                    }
+                }
+            }
+            OsmObj::Way(w) => {
+                for (a, b) in w.nodes.iter().zip(w.nodes.iter().skip(1)) {
+                    self.unlink(*a, *b, id);
+                }
+            }
+            OsmObj::Relation(r) => {
+                let nodes = r.refs.iter().flat_map(|r| r.member.node());
+                for a in nodes.clone() {
+                    for b in nodes.clone().filter(|&b| a < b) {
+                        self.unlink(a, b, id);
+                    }
+                }
+            }
+        }
+        Some(old)
+    }
+
+    // Removes the edges between `a` and `b` that came from `id`.
+    fn unlink(&mut self, a: NodeId, b: NodeId, id: OsmId) {
+        let (a_idx, b_idx) = match (self.vertex_by_osm_id.get(&a), self.vertex_by_osm_id.get(&b)) {
+            (Some(a), Some(b)) => (*a, *b),
+            _ => return,
+        };
+        while let Some(edge) = self
+            .graph
+            .edges_connecting(a_idx, b_idx)
+            .find(|e| *e.weight() == id)
+            .map(|e| e.id())
+        {
+            self.graph.remove_edge(edge);
+        }
+    }
+
+    // Removes the vertex for `node_id` if nothing refers to it any more.
+    // petgraph fills the gap with the last vertex, so that gets re-indexed.
+    fn prune(&mut self, node_id: NodeId) {
+        let idx = match self.vertex_by_osm_id.get(&node_id) {
+            Some(&idx) => idx,
+            None => return,
+        };
+        if self.objs.contains_key(&node_id.into()) || self.graph.neighbors(idx).next().is_some() {
+            return;
+        }
+        self.vertex_by_osm_id.remove(&node_id);
+        self.osm_id_by_vertex.remove(&idx);
+        let last = NodeIndex::new(self.graph.node_count() - 1);
+        self.graph.remove_node(idx);
+        if last != idx {
+            let moved = self.graph[idx];
+            self.osm_id_by_vertex.remove(&last);
+            self.osm_id_by_vertex.insert(idx, moved);
+            self.vertex_by_osm_id.insert(moved, idx);
+        }
+    }
+
+    fn index(&mut self, node_id: NodeId) -> NodeIndex {
+        let Self {
+            graph,
+            vertex_by_osm_id,
+            osm_id_by_vertex,
+            ..
+        } = self;
+        *vertex_by_osm_id.entry(node_id).or_insert_with(|| {
+            let idx = graph.add_node(node_id);
+            osm_id_by_vertex.insert(idx, node_id);
+            idx
+        })
+    }
+}
+
+// The nodes that `obj` might have put in the graph.
+fn node_ids(obj: &OsmObj) -> Vec<NodeId> {
+    match obj {
+        OsmObj::Node(n) => vec![n.id],
+        OsmObj::Way(w) => w.nodes.clone(),
+        OsmObj::Relation(r) => r.refs.iter().flat_map(|r| r.member.node()).collect(),
+    }
+}
+
+/// The kinds of things we report on when the network changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Feature {
+    Station,
+    StopArea,
+    Section,
+    Route,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changed {
+    pub action: Action,
+    pub feature: Feature,
+    pub id: OsmId,
+    pub name: Option<String>,
+}
+
+impl Feature {
+    // Route relations aren't part of the graph, but we still want to know
+    // when they change.
+    pub fn of(obj: &OsmObj) -> Option<Self> {
+        let tags = obj.tags();
+        match obj {
+            OsmObj::Node(_) if is_station(tags) => Some(Feature::Station),
+            OsmObj::Way(_) if tags.contains_key("railway") => Some(Feature::Section),
+            OsmObj::Relation(_) if tags.contains("public_transport", "stop_area") => {
+                Some(Feature::StopArea)
+            }
+            OsmObj::Relation(_)
+                if tags.contains("route", "train")
+                    || tags.contains("route", "tracks")
+                    || tags.contains("route", "railway") =>
+            {
+                Some(Feature::Route)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether a node with these tags is a railway station (or a halt). Bus and
+/// coach stations are `public_transport=station` too, so those only count
+/// if they also have `train=yes`.
+pub fn is_station(tags: &Tags) -> bool {
+    tags.contains("railway", "station")
+        || tags.contains("railway", "halt")
+        || (tags.contains("public_transport", "station") && tags.contains("train", "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, RelationId, WayId};
+
+    use super::*;
+    use crate::fixtures;
+
+    fn map() -> Map {
+        Map::from_reader(fixtures::objs().into_iter().map(Ok)).unwrap()
+    }
+
+    fn obj(id: OsmId) -> OsmObj {
+        fixtures::objs().into_iter().find(|o| o.id() == id).unwrap()
+    }
+
+    // Every vertex is indexed both ways round, and nothing else is.
+    fn check_indexes(map: &Map) {
+        assert_eq!(map.vertex_by_osm_id.len(), map.graph.node_count());
+        assert_eq!(map.osm_id_by_vertex.len(), map.graph.node_count());
+        for (&node_id, &idx) in &map.vertex_by_osm_id {
+            assert_eq!(map.graph[idx], node_id);
+            assert_eq!(map.osm_id_by_vertex[&idx], node_id);
+        }
+    }
+
+    fn vertices(map: &Map) -> Vec<i64> {
+        map.vertex_by_osm_id.keys().map(|n| n.0).collect()
+    }
+
+    #[test]
+    fn reports_what_changed() {
+        let mut map = map();
+        let station = obj(NodeId(1).into());
+        let changed = map.apply(Action::Modify, station.clone()).unwrap();
+        assert_eq!(changed.action, Action::Modify);
+        assert_eq!(changed.feature, Feature::Station);
+        assert_eq!(changed.id, NodeId(1).into());
+        assert_eq!(changed.name.as_deref(), Some("Fish & <\"Chips\"> 'Ok'"));
+
+        // A deleted object doesn't come with its tags, so we go by the one
+        // we had.
+        let mut deleted = station;
+        if let OsmObj::Node(n) = &mut deleted {
+            n.tags.clear();
+        }
+        let changed = map.apply(Action::Delete, deleted).unwrap();
+        assert_eq!(changed.feature, Feature::Station);
+        assert!(changed.name.is_some());
+        assert!(map.node_id_by_crs.is_empty());
+
+        let mut plain = obj(NodeId(2).into());
+        assert_eq!(map.apply(Action::Create, plain.clone()), None);
+        if let OsmObj::Node(n) = &mut plain {
+            n.tags.insert("railway".into(), "halt".into());
+        }
+        let changed = map.apply(Action::Modify, plain).unwrap();
+        assert_eq!(changed.feature, Feature::Station);
+        assert_eq!(changed.name, None);
+        check_indexes(&map);
+    }
+
+    #[test]
+    fn only_railway_stations_are_stations() {
+        let station = |tags: &[(&str, &str)]| {
+            let mut t = Tags::new();
+            for &(k, v) in tags {
+                t.insert(k.into(), v.into());
+            }
+            is_station(&t)
+        };
+        assert!(station(&[("railway", "station")]));
+        assert!(station(&[("railway", "halt")]));
+        assert!(station(&[
+            ("public_transport", "station"),
+            ("train", "yes")
+        ]));
+        assert!(!station(&[("public_transport", "station")]));
+        assert!(!station(&[("public_transport", "station"), ("bus", "yes")]));
+        assert!(!station(&[("amenity", "bus_station")]));
+    }
+
+    #[test]
+    fn removes_vertices_nothing_refers_to() {
+        let mut map = map();
+        assert_eq!(vertices(&map), [1, 2, 3, 4, 5]);
+
+        // The track goes, but the buffer stop and the stop area are still
+        // there.
+        map.apply(Action::Delete, obj(WayId(10).into()));
+        assert_eq!(vertices(&map), [1, 4, 5]);
+        check_indexes(&map);
+
+        // The platform is still a platform, even on its own.
+        map.apply(Action::Delete, obj(RelationId(20).into()));
+        assert_eq!(vertices(&map), [1, 4, 5]);
+        check_indexes(&map);
+
+        for id in [1, 4, 5] {
+            map.apply(Action::Delete, obj(NodeId(id).into()));
+        }
+        assert!(vertices(&map).is_empty());
+        check_indexes(&map);
+    }
+
+    #[test]
+    fn keeps_vertices_still_in_use() {
+        let mut map = map();
+
+        // The station node goes, but the track still runs through it.
+        map.apply(Action::Delete, obj(NodeId(1).into()));
+        assert_eq!(vertices(&map), [1, 2, 3, 4, 5]);
+
+        // Cutting the way short leaves the end behind, but it's still a
+        // buffer stop.
+        let mut way = obj(WayId(10).into());
+        if let OsmObj::Way(w) = &mut way {
+            w.nodes.truncate(2);
+        }
+        map.apply(Action::Modify, way);
+        assert_eq!(vertices(&map), [1, 2, 4, 5]);
+        assert_eq!(map.graph.edge_count(), 2);
+        check_indexes(&map);
+    }
+
+    #[test]
+    fn does_not_grow_as_things_come_and_go() {
+        let mut map = map();
+        let (vertices, edges) = (map.graph.node_count(), map.graph.edge_count());
+        for i in 0..10 {
+            let mut way = obj(WayId(10).into());
+            if let OsmObj::Way(w) = &mut way {
+                w.id = WayId(100 + i);
+                w.nodes = vec![NodeId(1000 + i), NodeId(2000 + i), NodeId(1)];
+            }
+            map.apply(Action::Create, way.clone());
+            map.apply(Action::Delete, way);
+        }
+        assert_eq!(map.graph.node_count(), vertices);
+        assert_eq!(map.graph.edge_count(), edges);
+        check_indexes(&map);
+    }
+}
@@ -0,0 +1,57 @@
+use std::{io::BufRead, path::Path};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::OsmObj;
+use serde::Serialize;
+
+use crate::{
+    input::{Format, Input},
+    xml::XmlReader,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// Reads the changes from an OsmChange (`.osc`, `.osc.gz`) file, in file
+/// order.
+pub struct OscReader {
+    xml: XmlReader<Box<dyn BufRead>>,
+}
+
+impl OscReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let input = Input::open(path)?;
+        if input.format() != Format::Xml {
+            bail!("Change files must be OsmChange XML: {:?}", path);
+        }
+        let xml = input.xml()?;
+        Ok(OscReader { xml })
+    }
+
+    /// Reads an already decompressed change file.
+    pub fn new(r: Box<dyn BufRead>) -> Self {
+        OscReader {
+            xml: XmlReader::new(r),
+        }
+    }
+}
+
+impl Iterator for OscReader {
+    type Item = Result<(Action, OsmObj)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let obj = match self.xml.next()? {
+            Ok(obj) => obj,
+            Err(e) => return Some(Err(e)),
+        };
+        let action = self
+            .xml
+            .action()
+            .with_context(|| format!("{:?} is outside of any change section", obj.id()));
+        Some(action.map(|action| (action, obj)))
+    }
+}
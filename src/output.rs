@@ -0,0 +1,330 @@
+use std::{
+    io::{self, BufWriter, Stdout, Write},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Result};
+use csv::QuoteStyle;
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// How results get written to stdout. Every format carries the same fields;
+/// in `text` and `csv`, anything that isn't a plain value (eg: a list of
+/// ids, or a set of tags) is written as compact JSON. In `geojson`, records
+/// with a `lat` and `lon` become points, and everything else goes in the
+/// properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Tab separated, without a header. Tabs and line breaks in a value
+    /// are written as `\t`, `\n` and `\r`, so each record stays on one
+    /// line.
+    Text,
+    /// A single JSON array.
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    Csv,
+    /// A GeoJSON `FeatureCollection`.
+    GeoJson,
+}
+
+/// Writes a stream of records in the chosen format. Call `finish` once
+/// you're done, or the output may be incomplete.
+pub struct Output<W: Write> {
+    sink: Sink<W>,
+}
+
+enum Sink<W: Write> {
+    Table {
+        w: Box<csv::Writer<W>>,
+        header: bool,
+        escape: bool,
+        started: bool,
+    },
+    Json {
+        w: W,
+        started: bool,
+    },
+    GeoJson {
+        w: W,
+        started: bool,
+    },
+    Jsonl(W),
+}
+
+impl Output<BufWriter<Stdout>> {
+    pub fn stdout(format: Format) -> Self {
+        Self::new(format, BufWriter::new(io::stdout()))
+    }
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(format: Format, w: W) -> Self {
+        // Text output is for people, so we don't quote anything.
+        let table = |w, delimiter, quote, header, escape| Sink::Table {
+            w: Box::new(
+                csv::WriterBuilder::new()
+                    .delimiter(delimiter)
+                    .quote_style(quote)
+                    .has_headers(false)
+                    .from_writer(w),
+            ),
+            header,
+            escape,
+            started: false,
+        };
+        let sink = match format {
+            Format::Text => table(w, b'\t', QuoteStyle::Never, false, true),
+            Format::Csv => table(w, b',', QuoteStyle::Necessary, true, false),
+            Format::Json => Sink::Json { w, started: false },
+            Format::GeoJson => Sink::GeoJson { w, started: false },
+            Format::Jsonl => Sink::Jsonl(w),
+        };
+        Output { sink }
+    }
+
+    pub fn write<R: Serialize>(&mut self, record: &R) -> Result<()> {
+        match &mut self.sink {
+            Sink::Table {
+                w,
+                header,
+                escape,
+                started,
+            } => {
+                let fields = match serde_json::to_value(record)? {
+                    Value::Object(fields) => fields,
+                    other => bail!("Can't write {} as a table row", other),
+                };
+                if *header && !*started {
+                    w.write_record(fields.keys())?;
+                }
+                *started = true;
+                w.write_record(fields.values().map(|v| cell(v, *escape)))?;
+            }
+            Sink::Json { w, started } => {
+                w.write_all(if *started { b",\n" } else { b"[\n" })?;
+                *started = true;
+                serde_json::to_writer(&mut *w, record)?;
+            }
+            Sink::GeoJson { w, started } => {
+                w.write_all(if *started {
+                    b",\n"
+                } else {
+                    b"{\"type\":\"FeatureCollection\",\"features\":[\n"
+                })?;
+                *started = true;
+                serde_json::to_writer(&mut *w, &feature(serde_json::to_value(record)?)?)?;
+            }
+            Sink::Jsonl(w) => {
+                serde_json::to_writer(&mut *w, record)?;
+                w.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes out everything written so far, for output that goes on for a
+    /// while (eg: following a replication feed).
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.sink {
+            Sink::Table { w, .. } => w.flush()?,
+            Sink::Json { w, .. } | Sink::GeoJson { w, .. } | Sink::Jsonl(w) => w.flush()?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W> {
+        let mut w = match self.sink {
+            Sink::Table { w, .. } => w.into_inner().map_err(|e| anyhow!("{}", e.error()))?,
+            Sink::Json { mut w, started } => {
+                w.write_all(if started { b"\n]\n" } else { b"[]\n" })?;
+                w
+            }
+            Sink::GeoJson { mut w, started } => {
+                w.write_all(if started {
+                    b"\n]}\n"
+                } else {
+                    b"{\"type\":\"FeatureCollection\",\"features\":[]}\n"
+                })?;
+                w
+            }
+            Sink::Jsonl(w) => w,
+        };
+        w.flush()?;
+        Ok(w)
+    }
+}
+
+fn cell(value: &Value, escape: bool) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) if escape && s.contains(['\t', '\n', '\r']) => s
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Records without a position still become features, just with no geometry.
+fn feature(record: Value) -> Result<Value> {
+    let mut properties = match record {
+        Value::Object(fields) => fields,
+        other => bail!("Can't write {} as a GeoJSON feature", other),
+    };
+    let lat = properties.remove("lat").and_then(|v| v.as_f64());
+    let lon = properties.remove("lon").and_then(|v| v.as_f64());
+    let geometry = match (lat, lon) {
+        (Some(lat), Some(lon)) => json!({"type": "Point", "coordinates": [lon, lat]}),
+        _ => Value::Null,
+    };
+    Ok(json!({"type": "Feature", "geometry": geometry, "properties": properties}))
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "geojson" => Ok(Format::GeoJson),
+            other => bail!("Unknown output format: {:?}", other),
+        }
+    }
+}
+
+/// The short form of an id that osmium and friends use, eg: `n123`, `w45`.
+pub fn osm_ref(id: OsmId) -> String {
+    match id {
+        OsmId::Node(id) => format!("n{}", id.0),
+        OsmId::Way(id) => format!("w{}", id.0),
+        OsmId::Relation(id) => format!("r{}", id.0),
+    }
+}
+
+/// Reads an id written by `osm_ref`. Upper case (`N123`) is fine too.
+pub fn parse_osm_ref(s: &str) -> Result<OsmId> {
+    let (kind, id) = s.split_at(s.chars().next().map(char::len_utf8).unwrap_or(0));
+    let id = id
+        .parse::<i64>()
+        .map_err(|_| anyhow!("Bad OSM id {:?}; expected eg: n123, w45 or r6", s))?;
+    match kind {
+        "n" | "N" => Ok(NodeId(id).into()),
+        "w" | "W" => Ok(WayId(id).into()),
+        "r" | "R" => Ok(RelationId(id).into()),
+        _ => bail!("Bad OSM id {:?}; expected eg: n123, w45 or r6", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Record {
+        name: &'static str,
+        lat: Option<f64>,
+        lon: Option<f64>,
+        ids: Vec<u32>,
+    }
+
+    fn write(format: Format) -> String {
+        let mut out = Output::new(format, Vec::new());
+        out.write(&Record {
+            name: "Fish, \"Chips\"",
+            lat: Some(51.5),
+            lon: Some(-0.12),
+            ids: vec![1, 2],
+        })
+        .unwrap();
+        out.write(&Record {
+            name: "Tab\there\nand\r\nthere",
+            lat: None,
+            lon: None,
+            ids: vec![],
+        })
+        .unwrap();
+        String::from_utf8(out.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn writes_text() {
+        assert_eq!(
+            write(Format::Text),
+            "Fish, \"Chips\"\t51.5\t-0.12\t[1,2]\n\
+             Tab\\there\\nand\\r\\nthere\t\t\t[]\n"
+        );
+    }
+
+    #[test]
+    fn writes_csv() {
+        assert_eq!(
+            write(Format::Csv),
+            "name,lat,lon,ids\n\
+             \"Fish, \"\"Chips\"\"\",51.5,-0.12,\"[1,2]\"\n\
+             \"Tab\there\nand\r\nthere\",,,[]\n"
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let json = write(Format::Json);
+        assert!(json.starts_with("[\n{") && json.ends_with("}\n]\n"));
+        let records = serde_json::from_str::<Vec<Value>>(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["ids"], json!([1, 2]));
+        assert_eq!(records[1]["lat"], Value::Null);
+
+        let out = Output::new(Format::Json, Vec::new());
+        assert_eq!(out.finish().unwrap(), b"[]\n");
+    }
+
+    #[test]
+    fn writes_jsonl() {
+        let jsonl = write(Format::Jsonl);
+        let lines = jsonl.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let first = serde_json::from_str::<Value>(lines[0]).unwrap();
+        assert_eq!(first["name"], "Fish, \"Chips\"");
+        let second = serde_json::from_str::<Value>(lines[1]).unwrap();
+        assert_eq!(second["name"], "Tab\there\nand\r\nthere");
+    }
+
+    #[test]
+    fn writes_geojson() {
+        let geojson = serde_json::from_str::<Value>(&write(Format::GeoJson)).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(
+            features[0],
+            json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [-0.12, 51.5]},
+                "properties": {"name": "Fish, \"Chips\"", "ids": [1, 2]},
+            })
+        );
+        assert_eq!(features[1]["geometry"], Value::Null);
+        assert_eq!(features[1]["properties"]["ids"], json!([]));
+
+        let out = Output::new(Format::GeoJson, Vec::new());
+        let empty = serde_json::from_slice::<Value>(&out.finish().unwrap()).unwrap();
+        assert_eq!(empty["features"], json!([]));
+    }
+
+    #[test]
+    fn reads_osm_refs() {
+        for id in [NodeId(1).into(), WayId(-2).into(), RelationId(3).into()] {
+            assert_eq!(parse_osm_ref(&osm_ref(id)).unwrap(), id);
+        }
+        assert_eq!(parse_osm_ref("N12").unwrap(), NodeId(12).into());
+        for bad in ["", "n", "x12", "12", "n1.5"] {
+            assert!(parse_osm_ref(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::{collections::HashMap, io::Write, mem};
+
+use anyhow::{Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use osmpbfreader::{
+    fileformat::{Blob, BlobHeader},
+    osmformat::{
+        self, DenseNodes, HeaderBlock, PrimitiveBlock, PrimitiveGroup, Relation_MemberType,
+    },
+    Node, OsmId, OsmObj, Relation, Tags, Way,
+};
+use protobuf::Message;
+
+// Other tools (eg: osmium) use the same limit, and readers are expected to
+// cope with blocks at least this large.
+const BLOCK_SIZE: usize = 8000;
+
+/// Writes objects out as an `.osm.pbf` file. Objects should be written in
+/// the usual order (nodes, then ways, then relations, each by id), which is
+/// what you get from iterating over a `BTreeMap<OsmId, OsmObj>`.
+///
+/// osmpbfreader doesn't give us any element metadata (versions, changesets,
+/// etc.) so we don't write any either.
+pub struct PbfWriter<W: Write> {
+    w: W,
+    block: Vec<OsmObj>,
+}
+
+// Maps strings to their index in a block's string table. Index zero is
+// reserved, as it's used to delimit the tags in dense nodes.
+struct Strings {
+    table: Vec<Vec<u8>>,
+    index: HashMap<String, u32>,
+}
+
+impl<W: Write> PbfWriter<W> {
+    pub fn new(mut w: W) -> Result<Self> {
+        let mut header = HeaderBlock::new();
+        header
+            .mut_required_features()
+            .push("OsmSchema-V0.6".to_string());
+        header
+            .mut_required_features()
+            .push("DenseNodes".to_string());
+        header.set_writingprogram("osmrail".to_string());
+        write_blob(&mut w, "OSMHeader", &header.write_to_bytes()?).context("write header")?;
+
+        Ok(Self {
+            w,
+            block: Vec::with_capacity(BLOCK_SIZE),
+        })
+    }
+
+    pub fn write(&mut self, obj: OsmObj) -> Result<()> {
+        let same_kind = self
+            .block
+            .last()
+            .map(|last| mem::discriminant(&last.id()) == mem::discriminant(&obj.id()))
+            .unwrap_or(true);
+        if !same_kind || self.block.len() >= BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        self.block.push(obj);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_block()?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+
+    fn flush_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = encode_block(&self.block);
+        self.block.clear();
+        write_blob(&mut self.w, "OSMData", &block.write_to_bytes()?).context("write block")?;
+        Ok(())
+    }
+}
+
+fn write_blob<W: Write>(w: &mut W, kind: &str, data: &[u8]) -> Result<()> {
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(data)?;
+
+    let mut blob = Blob::new();
+    blob.set_raw_size(data.len() as i32);
+    blob.set_zlib_data(zlib.finish()?);
+    let blob = blob.write_to_bytes()?;
+
+    let mut header = BlobHeader::new();
+    header.set_field_type(kind.to_string());
+    header.set_datasize(blob.len() as i32);
+    let header = header.write_to_bytes()?;
+
+    w.write_all(&(header.len() as u32).to_be_bytes())?;
+    w.write_all(&header)?;
+    w.write_all(&blob)?;
+    Ok(())
+}
+
+// Everything in a block is expected to be the same kind of object, so each
+// block ends up with a single group.
+fn encode_block(objs: &[OsmObj]) -> PrimitiveBlock {
+    let mut strings = Strings::new();
+    let mut group = PrimitiveGroup::new();
+
+    let nodes = objs.iter().filter_map(OsmObj::node).collect::<Vec<_>>();
+    if !nodes.is_empty() {
+        group.set_dense(encode_nodes(&nodes, &mut strings));
+    }
+    for way in objs.iter().filter_map(OsmObj::way) {
+        group.mut_ways().push(encode_way(way, &mut strings));
+    }
+    for rel in objs.iter().filter_map(OsmObj::relation) {
+        group.mut_relations().push(encode_rel(rel, &mut strings));
+    }
+
+    let mut block = PrimitiveBlock::new();
+    block.set_stringtable(strings.into_table());
+    block.mut_primitivegroup().push(group);
+    block
+}
+
+// With the default granularity of 100 nanodegrees, stored coordinates are
+// the same decimicro-degree values that osmpbfreader hands us.
+fn encode_nodes(nodes: &[&Node], strings: &mut Strings) -> DenseNodes {
+    let mut dense = DenseNodes::new();
+    let (mut last_id, mut last_lat, mut last_lon) = (0i64, 0i64, 0i64);
+    for node in nodes {
+        let lat = i64::from(node.decimicro_lat);
+        let lon = i64::from(node.decimicro_lon);
+        dense.mut_id().push(node.id.0 - last_id);
+        dense.mut_lat().push(lat - last_lat);
+        dense.mut_lon().push(lon - last_lon);
+        last_id = node.id.0;
+        last_lat = lat;
+        last_lon = lon;
+
+        for (k, v) in node.tags.iter() {
+            dense.mut_keys_vals().push(strings.index(k) as i32);
+            dense.mut_keys_vals().push(strings.index(v) as i32);
+        }
+        dense.mut_keys_vals().push(0);
+    }
+    dense
+}
+
+fn encode_way(way: &Way, strings: &mut Strings) -> osmformat::Way {
+    let mut out = osmformat::Way::new();
+    out.set_id(way.id.0);
+    let (keys, vals) = encode_tags(&way.tags, strings);
+    out.set_keys(keys);
+    out.set_vals(vals);
+    let mut last = 0;
+    for node in way.nodes.iter() {
+        out.mut_refs().push(node.0 - last);
+        last = node.0;
+    }
+    out
+}
+
+fn encode_rel(rel: &Relation, strings: &mut Strings) -> osmformat::Relation {
+    let mut out = osmformat::Relation::new();
+    out.set_id(rel.id.0);
+    let (keys, vals) = encode_tags(&rel.tags, strings);
+    out.set_keys(keys);
+    out.set_vals(vals);
+    let mut last = 0;
+    for member in rel.refs.iter() {
+        let (id, kind) = match member.member {
+            OsmId::Node(id) => (id.0, Relation_MemberType::NODE),
+            OsmId::Way(id) => (id.0, Relation_MemberType::WAY),
+            OsmId::Relation(id) => (id.0, Relation_MemberType::RELATION),
+        };
+        out.mut_roles_sid().push(strings.index(&member.role) as i32);
+        out.mut_memids().push(id - last);
+        out.mut_types().push(kind);
+        last = id;
+    }
+    out
+}
+
+fn encode_tags(tags: &Tags, strings: &mut Strings) -> (Vec<u32>, Vec<u32>) {
+    tags.iter()
+        .map(|(k, v)| (strings.index(k), strings.index(v)))
+        .unzip()
+}
+
+impl Strings {
+    fn new() -> Self {
+        Self {
+            table: vec![Vec::new()],
+            index: HashMap::new(),
+        }
+    }
+
+    fn index(&mut self, s: &str) -> u32 {
+        let Self { table, index } = self;
+        *index.entry(s.to_string()).or_insert_with(|| {
+            table.push(s.as_bytes().to_vec());
+            (table.len() - 1) as u32
+        })
+    }
+
+    fn into_table(self) -> osmformat::StringTable {
+        let mut out = osmformat::StringTable::new();
+        out.set_s(self.table.into());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use osmpbfreader::NodeId;
+
+    use super::*;
+    use crate::{fixtures, input::Input};
+
+    fn round_trip(name: &str, objs: &[OsmObj]) -> Vec<OsmObj> {
+        let path = fixtures::temp_path(name);
+        let mut w = PbfWriter::new(File::create(&path).unwrap()).unwrap();
+        for obj in objs {
+            w.write(obj.clone()).unwrap();
+        }
+        w.finish().unwrap();
+
+        let input = Input::open(&path).unwrap();
+        let read = input.objs().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let objs = fixtures::objs();
+        assert_eq!(round_trip("pbf-round-trip.osm.pbf", &objs), objs);
+    }
+
+    #[test]
+    fn reads_back_more_than_one_block() {
+        let objs = (1..=BLOCK_SIZE as i64 * 2 + 1)
+            .map(|i| {
+                Node {
+                    id: NodeId(i * 3),
+                    tags: Tags::new(),
+                    decimicro_lat: (i * 7919 % 1_800_000_000 - 900_000_000) as i32,
+                    decimicro_lon: (i * 104_729 % 3_600_000_000 - 1_800_000_000) as i32,
+                }
+                .into()
+            })
+            .collect::<Vec<OsmObj>>();
+        assert_eq!(round_trip("pbf-blocks.osm.pbf", &objs), objs);
+    }
+}
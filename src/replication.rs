@@ -0,0 +1,187 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+
+use crate::osc::OscReader;
+
+/// An OSM replication feed, laid out the way planet.openstreetmap.org
+/// publishes them: a top level `state.txt`, and then each change file at
+/// `AAA/BBB/CCC.osc.gz` for sequence number `AAABBBCCC`.
+///
+/// Can be either a local directory, or served over HTTP(S).
+#[derive(Debug, Clone)]
+pub enum Replication {
+    Dir(PathBuf),
+    Http(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub sequence: u64,
+    pub timestamp: Option<String>,
+}
+
+impl Replication {
+    pub fn new(location: &str) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Replication::Http(location.trim_end_matches('/').to_string())
+        } else {
+            Replication::Dir(PathBuf::from(location))
+        }
+    }
+
+    /// The most recent state published by the feed.
+    pub fn state(&self) -> Result<State> {
+        let mut body = String::new();
+        self.open("state.txt")?
+            .read_to_string(&mut body)
+            .context("read state")?;
+        State::parse(&body)
+    }
+
+    pub fn changes(&self, sequence: u64) -> Result<OscReader> {
+        let r = self.open(&format!("{}.osc.gz", sequence_path(sequence)))?;
+        Ok(OscReader::new(Box::new(BufReader::new(
+            MultiGzDecoder::new(r),
+        ))))
+    }
+
+    fn open(&self, name: &str) -> Result<Box<dyn Read + Send>> {
+        match self {
+            Replication::Dir(dir) => {
+                let path = dir.join(name);
+                let f = File::open(&path).with_context(|| format!("open {:?}", path))?;
+                Ok(Box::new(f))
+            }
+            Replication::Http(base) => {
+                let url = format!("{}/{}", base, name);
+                let resp = ureq::get(&url)
+                    .call()
+                    .with_context(|| format!("fetch {}", url))?;
+                Ok(Box::new(resp.into_reader()))
+            }
+        }
+    }
+}
+
+impl State {
+    // These are Java properties files, so the colons in the timestamp come
+    // escaped with backslashes.
+    pub fn parse(body: &str) -> Result<Self> {
+        let mut sequence = None;
+        let mut timestamp = None;
+        for line in body.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim().replace('\\', "");
+                match key.trim() {
+                    "sequenceNumber" => {
+                        sequence = Some(value.parse().context("parse sequenceNumber")?)
+                    }
+                    "timestamp" => timestamp = Some(value),
+                    _ => (),
+                }
+            }
+        }
+        Ok(State {
+            sequence: sequence.context("state has no sequenceNumber")?,
+            timestamp,
+        })
+    }
+}
+
+fn sequence_path(sequence: u64) -> String {
+    format!(
+        "{:03}/{:03}/{:03}",
+        sequence / 1_000_000,
+        sequence / 1_000 % 1_000,
+        sequence % 1_000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn reads_state() {
+        let body = "#Sat Oct 17 20:21:02 UTC 2026\n\
+                    sequenceNumber=4567\n\
+                    timestamp=2026-10-17T20\\:20\\:58Z\n";
+        assert_eq!(
+            State::parse(body).unwrap(),
+            State {
+                sequence: 4567,
+                timestamp: Some("2026-10-17T20:20:58Z".into()),
+            }
+        );
+        assert_eq!(
+            State::parse(" sequenceNumber = 12 \r\n").unwrap(),
+            State {
+                sequence: 12,
+                timestamp: None,
+            }
+        );
+        assert!(State::parse("timestamp=2026-10-17T20\\:20\\:58Z\n").is_err());
+        assert!(State::parse("#sequenceNumber=12\n").is_err());
+        assert!(State::parse("sequenceNumber=twelve\n").is_err());
+    }
+
+    #[test]
+    fn lays_out_sequence_numbers() {
+        assert_eq!(sequence_path(1234567), "001/234/567");
+        assert_eq!(sequence_path(0), "000/000/000");
+        assert_eq!(sequence_path(999_999_999), "999/999/999");
+    }
+
+    #[test]
+    fn reads_a_local_feed() {
+        let dir = fixtures::temp_path("replication");
+        fs::create_dir_all(dir.join("001/234")).unwrap();
+        fs::write(dir.join("state.txt"), "sequenceNumber=1234567\n").unwrap();
+        let mut gz = GzEncoder::new(
+            File::create(dir.join("001/234/567.osc.gz")).unwrap(),
+            Compression::default(),
+        );
+        gz.write_all(br#"<osmChange><delete><node id="1"/></delete></osmChange>"#)
+            .unwrap();
+        gz.finish().unwrap();
+
+        let feed = Replication::new(dir.to_str().unwrap());
+        let state = feed.state().unwrap();
+        assert_eq!(state.sequence, 1234567);
+        let changes = feed
+            .changes(state.sequence)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, crate::osc::Action::Delete);
+        assert!(feed.changes(state.sequence + 1).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn knows_urls_from_directories() {
+        for url in &["http://example.com/feed/", "https://example.com/feed"] {
+            match Replication::new(url) {
+                Replication::Http(base) => assert!(base.ends_with("example.com/feed")),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(matches!(Replication::new("feed"), Replication::Dir(_)));
+    }
+}
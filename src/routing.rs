@@ -0,0 +1,771 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+};
+
+use geo::{HaversineDistance, Point};
+use osmpbfreader::OsmId;
+
+use crate::compact::{CompactMap, Vertex};
+
+/// How far apart two vertices are, in metres, or infinity if we don't know
+/// where one of them is.
+pub fn length(map: &CompactMap, a: Vertex, b: Vertex) -> f64 {
+    match (map.coord(a), map.coord(b)) {
+        (Some(a), Some(b)) => {
+            Point::new(a.lon(), a.lat()).haversine_distance(&Point::new(b.lon(), b.lat()))
+        }
+        _ => f64::INFINITY,
+    }
+}
+
+/// Dijkstra's algorithm over a `CompactMap`, with each edge weighted by
+/// its length (or, with `by_time`, how long it takes). Vertices come out in
+/// order of their distance (in metres, or seconds) from the nearest source,
+/// so callers can stop as soon as they've seen what they need.
+///
+/// The members of a stop area are joined up in the map, but there's no
+/// track between them, so we only go between them at either end: from a
+/// source onto the track, and off the track onto a station. Anywhere else,
+/// we stick to the ways.
+pub struct ShortestPaths<'a> {
+    map: &'a CompactMap,
+    avoid: Vec<&'a Avoid>,
+    speeds: Option<&'a Speeds>,
+    track_only: bool,
+    sources: HashSet<Vertex>,
+    // The shortest way to each vertex that we can carry on from.
+    dist: HashMap<Vertex, f64>,
+    prev: HashMap<Vertex, (Vertex, OsmId)>,
+    // The shortest way onto each station through a stop area, as the last
+    // step, which we can't carry on from.
+    last: HashMap<Vertex, (f64, Vertex, OsmId)>,
+    expanded: HashSet<Vertex>,
+    settled: HashSet<Vertex>,
+    queue: BinaryHeap<(Queued, bool)>,
+}
+
+// Ordered so that the `BinaryHeap` gives us the nearest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Queued(pub(crate) f64, pub(crate) Vertex);
+
+impl<'a> ShortestPaths<'a> {
+    /// Starts from each of `sources`, as if we were already the given
+    /// distance along (eg: from snapping a point to the network).
+    pub fn new(map: &'a CompactMap, sources: impl IntoIterator<Item = (Vertex, f64)>) -> Self {
+        let mut paths = ShortestPaths {
+            map,
+            avoid: Vec::new(),
+            speeds: None,
+            track_only: false,
+            sources: HashSet::new(),
+            dist: HashMap::new(),
+            prev: HashMap::new(),
+            last: HashMap::new(),
+            expanded: HashSet::new(),
+            settled: HashSet::new(),
+            queue: BinaryHeap::new(),
+        };
+        for (v, d) in sources {
+            paths.sources.insert(v);
+            if d < paths.distance(v).unwrap_or(f64::INFINITY) {
+                paths.dist.insert(v, d);
+                paths.queue.push((Queued(d, v), false));
+            }
+        }
+        paths
+    }
+
+    /// Keeps out of whatever `avoid` says (apart from the sources). Can be
+    /// given more than once, to keep out of all of them.
+    pub fn avoiding(mut self, avoid: &'a Avoid) -> Self {
+        self.avoid.push(avoid);
+        self
+    }
+
+    /// Weighs each edge by how long it takes (in seconds) at the line
+    /// speed, rather than how long it is.
+    pub fn by_time(mut self, speeds: &'a Speeds) -> Self {
+        self.speeds = Some(speeds);
+        self
+    }
+
+    /// Only goes along ways, never between the members of a stop area.
+    pub fn track_only(mut self) -> Self {
+        self.track_only = true;
+        self
+    }
+
+    /// The sources are partway along a route, already on the track, so we
+    /// can't step off them into a stop area (though we can still finish at
+    /// a station through one).
+    pub fn from_track(mut self) -> Self {
+        self.sources.clear();
+        self
+    }
+
+    /// The shortest distance to `v` found so far.
+    pub fn distance(&self, v: Vertex) -> Option<f64> {
+        let along = self.dist.get(&v).cloned();
+        let last = self.last.get(&v).map(|l| l.0);
+        match (along, last) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The vertices from a source to `v` (inclusive), and the way (or stop
+    /// area) we took to get to each one after the first.
+    pub fn path_to(&self, v: Vertex) -> Option<Vec<(Vertex, Option<OsmId>)>> {
+        let d = self.distance(v)?;
+        let mut path = Vec::new();
+        let mut here = v;
+        if let Some(&(last, prev, via)) = self.last.get(&v) {
+            if last <= d && self.dist.get(&v).map(|&a| last < a).unwrap_or(true) {
+                path.push((here, Some(via)));
+                here = prev;
+            }
+        }
+        while let Some(&(prev, via)) = self.prev.get(&here) {
+            path.push((here, Some(via)));
+            here = prev;
+        }
+        path.push((here, None));
+        path.reverse();
+        Some(path)
+    }
+
+    // Which way we can go from `v` along `via` to `next`: along the track
+    // (`Some(false)`), onto a station as the last step (`Some(true)`), or
+    // not at all.
+    fn step(&self, v: Vertex, next: Vertex, via: OsmId) -> Option<bool> {
+        if !self.avoid.iter().all(|avoid| avoid.allows(v, next, via)) {
+            return None;
+        }
+        if via.is_way() {
+            Some(false)
+        } else if self.track_only {
+            None
+        } else if self.sources.contains(&v) {
+            Some(false)
+        } else if self.map.is_station(next) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl Iterator for ShortestPaths<'_> {
+    type Item = (Vertex, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((Queued(d, v), last)) = self.queue.pop() {
+            if !last && self.expanded.insert(v) {
+                for (next, via) in self.map.edges(v) {
+                    let last = match self.step(v, next, via) {
+                        Some(last) => last,
+                        None => continue,
+                    };
+                    let nd = d + match self.speeds {
+                        Some(speeds) => speeds.seconds(self.map, v, next, via),
+                        None => length(self.map, v, next),
+                    };
+                    if last {
+                        if nd < self.last.get(&next).map(|l| l.0).unwrap_or(f64::INFINITY) {
+                            self.last.insert(next, (nd, v, via));
+                            self.queue.push((Queued(nd, next), true));
+                        }
+                    } else if nd < self.dist.get(&next).cloned().unwrap_or(f64::INFINITY) {
+                        self.dist.insert(next, nd);
+                        self.prev.insert(next, (v, via));
+                        self.queue.push((Queued(nd, next), false));
+                    }
+                }
+            }
+            if self.settled.insert(v) {
+                return Some((v, d));
+            }
+        }
+        None
+    }
+}
+
+/// Parts of the network for routes to keep out of: vertices not to go
+/// through, ways or stop areas not to go along, and single edges not to
+/// take.
+#[derive(Debug, Clone, Default)]
+pub struct Avoid {
+    vertices: HashSet<Vertex>,
+    vias: HashSet<OsmId>,
+    edges: HashSet<(Vertex, Vertex, OsmId)>,
+}
+
+impl Avoid {
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.vias.is_empty() && self.edges.is_empty()
+    }
+
+    pub fn vertex(&mut self, v: Vertex) {
+        self.vertices.insert(v);
+    }
+
+    /// Avoids going along a way, or between the members of a stop area.
+    pub fn via(&mut self, id: OsmId) {
+        self.vias.insert(id);
+    }
+
+    /// Avoids going from `a` to `b` along `via` (but not from `b` to `a`).
+    pub fn edge(&mut self, a: Vertex, b: Vertex, via: OsmId) {
+        self.edges.insert((a, b, via));
+    }
+
+    /// Avoids a station, and everything in a stop area with it, as trains
+    /// going through usually pass the platforms rather than the station
+    /// node itself.
+    pub fn station(&mut self, map: &CompactMap, v: Vertex) {
+        self.vertex(v);
+        for (w, via) in map.edges(v) {
+            if let OsmId::Relation(_) = via {
+                self.vertex(w);
+            }
+        }
+    }
+
+    /// Avoids every vertex for which `inside` is true. Returns how many
+    /// there were.
+    pub fn area<F>(&mut self, map: &CompactMap, inside: F) -> usize
+    where
+        F: Fn(Point<f64>) -> bool,
+    {
+        let before = self.vertices.len();
+        for v in 0..map.vertex_count() as Vertex {
+            if let Some(c) = map.coord(v) {
+                if inside(Point::new(c.lon(), c.lat())) {
+                    self.vertex(v);
+                }
+            }
+        }
+        self.vertices.len() - before
+    }
+
+    pub fn avoids(&self, v: Vertex) -> bool {
+        self.vertices.contains(&v)
+    }
+
+    /// Whether we can go from `from` to `to` along `via`.
+    pub fn allows(&self, from: Vertex, to: Vertex, via: OsmId) -> bool {
+        !self.vertices.contains(&to)
+            && !self.vias.contains(&via)
+            && !self.edges.contains(&(from, to, via))
+    }
+}
+
+/// A route through the network: each vertex along it, with the way or stop
+/// area we took to get there (`None` for the first), and how long it is in
+/// metres.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub steps: Vec<(Vertex, Option<OsmId>)>,
+    pub distance: f64,
+}
+
+/// The shortest route from `from` to `to`, keeping out of `avoid`, if
+/// there is one.
+pub fn shortest_path(map: &CompactMap, from: Vertex, to: Vertex, avoid: &Avoid) -> Option<Path> {
+    path_to(
+        ShortestPaths::new(map, Some((from, 0.0))).avoiding(avoid),
+        to,
+    )
+}
+
+fn path_to(mut paths: ShortestPaths, to: Vertex) -> Option<Path> {
+    let distance = paths.find(|&(v, _)| v == to)?.1;
+    Some(Path {
+        steps: paths.path_to(to)?,
+        distance,
+    })
+}
+
+impl Path {
+    /// The ways (and stop areas) along the path, in order, each once.
+    pub fn vias(&self) -> Vec<OsmId> {
+        let mut vias = Vec::<OsmId>::new();
+        for via in self.steps.iter().filter_map(|&(_, via)| via) {
+            if vias.last() != Some(&via) {
+                vias.push(via);
+            }
+        }
+        vias
+    }
+
+    /// How much (from 0 to 1) of this path's length is also on `other`.
+    pub fn shared(&self, map: &CompactMap, other: &Path) -> f64 {
+        if self.distance <= 0.0 {
+            return 1.0;
+        }
+        let theirs = other.edges().collect::<HashSet<_>>();
+        let shared = self
+            .edges()
+            .filter(|e| theirs.contains(e))
+            .fold(0.0, |total, (a, b)| total + length(map, a, b));
+        shared / self.distance
+    }
+
+    // Each edge along the path, either way round.
+    fn edges(&self) -> impl Iterator<Item = (Vertex, Vertex)> + '_ {
+        self.steps
+            .windows(2)
+            .map(|w| (w[0].0.min(w[1].0), w[0].0.max(w[1].0)))
+    }
+}
+
+/// Up to `k` of the shortest routes from `from` to `to`, shortest first,
+/// after Yen, "Finding the K Shortest Loopless Paths in a Network" (1971).
+///
+/// Routes which share more than `1 - min_dissimilarity` of their length
+/// with a shorter one we've already picked are skipped (but still used to
+/// find more routes from), so that we don't just get the best route with a
+/// different crossover at each end. As there can be a great many of those,
+/// we give up after looking at `max_candidates` routes.
+pub fn k_shortest_paths(
+    map: &CompactMap,
+    from: Vertex,
+    to: Vertex,
+    avoid: &Avoid,
+    k: usize,
+    min_dissimilarity: f64,
+    max_candidates: usize,
+) -> Vec<Path> {
+    let mut picked = Vec::<Path>::new();
+    let mut seen = match shortest_path(map, from, to, avoid) {
+        Some(best) => vec![best],
+        None => return picked,
+    };
+    let mut candidates = Vec::<Path>::new();
+    loop {
+        let last = seen.last().expect("seen at least the shortest path");
+        let dissimilar = picked
+            .iter()
+            .all(|p| 1.0 - last.shared(map, p) >= min_dissimilarity);
+        if dissimilar {
+            picked.push(last.clone());
+        }
+        if picked.len() >= k || seen.len() >= max_candidates {
+            break;
+        }
+
+        for path in spurs(map, to, avoid, last, &seen) {
+            if !candidates.contains(&path) && !seen.contains(&path) {
+                candidates.push(path);
+            }
+        }
+        let next = candidates
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.distance.total_cmp(&b.1.distance))
+            .map(|(i, _)| i);
+        match next {
+            Some(i) => seen.push(candidates.swap_remove(i)),
+            None => break,
+        }
+    }
+    picked
+}
+
+// Yen's deviations from `last`: for each vertex along it, the shortest way
+// on to `to` that leaves by a different edge from any route we've seen
+// which got there the same way, without going back through the vertices
+// before it.
+fn spurs(map: &CompactMap, to: Vertex, avoid: &Avoid, last: &Path, seen: &[Path]) -> Vec<Path> {
+    let mut spurs = Vec::new();
+    let mut root_distance = 0.0;
+    let mut behind = Avoid::default();
+    for i in 0..last.steps.len() - 1 {
+        if i > 0 {
+            root_distance += length(map, last.steps[i - 1].0, last.steps[i].0);
+        }
+        let root = &last.steps[..=i];
+        let spur = root[i].0;
+
+        // On top of `avoid`, rather than a copy of it, as that could be
+        // big, and we'd be copying it for every vertex along the path.
+        if i > 0 {
+            behind.vertex(root[i - 1].0);
+        }
+        let mut taken = Avoid::default();
+        for path in seen {
+            if path.steps.len() > i + 1 && same_root(&path.steps[..=i], root) {
+                if let (next, Some(via)) = path.steps[i + 1] {
+                    taken.edge(spur, next, via);
+                }
+            }
+        }
+
+        // Only the first vertex can step off into a stop area: the rest are
+        // already on the track.
+        let mut paths = ShortestPaths::new(map, Some((spur, 0.0)))
+            .avoiding(avoid)
+            .avoiding(&behind)
+            .avoiding(&taken);
+        if i > 0 {
+            paths = paths.from_track();
+        }
+        if let Some(rest) = path_to(paths, to) {
+            let mut steps = root.to_vec();
+            steps.extend(rest.steps.into_iter().skip(1));
+            spurs.push(Path {
+                steps,
+                distance: root_distance + rest.distance,
+            });
+        }
+    }
+    spurs
+}
+
+// Whether two paths start the same way. (The first step of a spur path has
+// no `via`, so we don't compare those.)
+fn same_root(a: &[(Vertex, Option<OsmId>)], b: &[(Vertex, Option<OsmId>)]) -> bool {
+    a.len() == b.len() && a[0].0 == b[0].0 && a[1..] == b[1..]
+}
+
+/// The distance along the track (in metres) from `from` to each of `to`,
+/// or `None` where there's no way there. We stop looking once we've found
+/// them all, so this is much quicker than a search for each one.
+pub fn one_to_many(map: &CompactMap, from: Vertex, to: &[Vertex]) -> Vec<Option<f64>> {
+    let mut left = to.iter().cloned().collect::<HashSet<_>>();
+    let mut paths = ShortestPaths::new(map, Some((from, 0.0)));
+    for (v, _) in &mut paths {
+        left.remove(&v);
+        if left.is_empty() {
+            break;
+        }
+    }
+    // Either we've settled all of them, or run out of places to go, so
+    // these are all final.
+    to.iter().map(|&v| paths.distance(v)).collect()
+}
+
+/// Line speeds, from the `maxspeed` of each way, for working out how long
+/// it takes to get anywhere. Track without a `maxspeed` (and the walk
+/// between the parts of a stop area) is taken to be at `default` km/h.
+#[derive(Debug, Clone)]
+pub struct Speeds {
+    default: f64,
+    kmh: HashMap<OsmId, f64>,
+}
+
+impl Speeds {
+    pub fn new(map: &CompactMap, default: f64) -> Self {
+        let mut kmh = HashMap::new();
+        for v in 0..map.vertex_count() as Vertex {
+            for (_, via) in map.edges(v) {
+                kmh.entry(via).or_insert_with(|| {
+                    map.obj(via)
+                        .and_then(|obj| obj.tags().get("maxspeed").and_then(|s| parse_speed(s)))
+                        .filter(|&kmh| kmh > 0.0)
+                        .unwrap_or(default)
+                });
+            }
+        }
+        Speeds { default, kmh }
+    }
+
+    /// The speed along `via`, in km/h.
+    pub fn kmh(&self, via: OsmId) -> f64 {
+        self.kmh.get(&via).cloned().unwrap_or(self.default)
+    }
+
+    /// How long it takes to get from `a` to `b` along `via`, in seconds.
+    pub fn seconds(&self, map: &CompactMap, a: Vertex, b: Vertex, via: OsmId) -> f64 {
+        length(map, a, b) / (self.kmh(via) / 3.6)
+    }
+
+    /// How long it takes to go along `path`, in seconds.
+    pub fn path_seconds(&self, map: &CompactMap, path: &Path) -> f64 {
+        path.steps
+            .windows(2)
+            .map(|w| match w[1].1 {
+                Some(via) => self.seconds(map, w[0].0, w[1].0, via),
+                None => length(map, w[0].0, w[1].0) / (self.default / 3.6),
+            })
+            .fold(0.0, |total, s| total + s)
+    }
+}
+
+/// Reads a `maxspeed`, eg: `100`, `60 mph`, in km/h.
+pub fn parse_speed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (number, factor) = match value.strip_suffix("mph") {
+        Some(number) => (number.trim(), 1.609_344),
+        None => (value.strip_suffix("km/h").unwrap_or(value).trim(), 1.0),
+    };
+    number.parse::<f64>().ok().map(|n| n * factor)
+}
+
+impl Eq for Queued {}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .0
+            .total_cmp(&self.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The bits of track within `limit` of where a search started, by way, as
+/// lines of `(lon, lat)`. `reached` is every vertex the search got to within
+/// the limit, and how far it is (in metres, or seconds with `speeds`). Edges
+/// that go beyond the limit are cut short, from whichever ends we can get
+/// to.
+pub fn covered(
+    map: &CompactMap,
+    speeds: Option<&Speeds>,
+    reached: &[(Vertex, f64)],
+    limit: f64,
+) -> BTreeMap<OsmId, Vec<Vec<[f64; 2]>>> {
+    let cost = reached.iter().cloned().collect::<HashMap<_, _>>();
+    let mut track = BTreeMap::<OsmId, Vec<Vec<[f64; 2]>>>::new();
+    for &(a, cost_a) in reached {
+        for (b, via) in map.edges(a) {
+            // There's no track between the members of a stop area.
+            if !via.is_way() {
+                continue;
+            }
+            let cost_b = cost.get(&b).cloned();
+            // Each edge once: from the lower vertex if we got to both ends.
+            if cost_b.is_some() && b < a {
+                continue;
+            }
+            let (ca, cb) = match (map.coord(a), map.coord(b)) {
+                (Some(ca), Some(cb)) => ([ca.lon(), ca.lat()], [cb.lon(), cb.lat()]),
+                _ => continue,
+            };
+            let edge = match speeds {
+                Some(speeds) => speeds.seconds(map, a, b, via),
+                None => length(map, a, b),
+            };
+            let reach = |c: f64| {
+                if edge > 0.0 {
+                    ((limit - c) / edge).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                }
+            };
+            let from_a = reach(cost_a);
+            let from_b = cost_b.map(reach).unwrap_or(0.0);
+            let lines = track.entry(via).or_default();
+            if from_a + from_b >= 1.0 {
+                lines.push(vec![ca, cb]);
+            } else {
+                lines.push(vec![ca, lerp(ca, cb, from_a)]);
+                if from_b > 0.0 {
+                    lines.push(vec![lerp(cb, ca, from_b), cb]);
+                }
+            }
+        }
+    }
+    track
+}
+
+fn lerp(a: [f64; 2], b: [f64; 2], t: f64) -> [f64; 2] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+}
+
+#[cfg(test)]
+mod tests {
+    use osmpbfreader::{NodeId, RelationId, WayId};
+
+    use super::*;
+    use crate::fixtures;
+
+    fn vertex(map: &CompactMap, i: usize) -> Vertex {
+        map.vertex(NodeId(i as i64 + 1)).unwrap()
+    }
+
+    #[test]
+    fn only_uses_stop_areas_at_either_end() {
+        // Two lines, 0-1-2 and 3-4, with a stop area around the platforms
+        // at 2 and 3, and the station 5 off to the side. We can get on or
+        // off the track through the stop area, but not change lines.
+        let map = fixtures::network(
+            &[
+                (0.0, 0.0),
+                (0.0, 0.01),
+                (0.0, 0.02),
+                (0.0, 0.0201),
+                (0.0, 0.03),
+                (0.0005, 0.02),
+            ],
+            &[&[0, 1, 2], &[3, 4]],
+            &[&[2, 3, 5]],
+            &[0, 4, 5],
+        );
+        let v = |i| vertex(&map, i);
+        let route = |from, to| shortest_path(&map, v(from), v(to), &Avoid::default());
+
+        assert!(route(0, 4).is_none());
+        assert!(route(4, 0).is_none());
+
+        let off = route(0, 5).unwrap();
+        assert_eq!(
+            off.steps.iter().map(|s| s.0).collect::<Vec<_>>(),
+            vec![v(0), v(1), v(2), v(5)]
+        );
+        assert_eq!(off.steps[3].1, Some(RelationId(1).into()));
+
+        let on = route(5, 4).unwrap();
+        assert_eq!(
+            on.steps.iter().map(|s| s.0).collect::<Vec<_>>(),
+            vec![v(5), v(3), v(4)]
+        );
+
+        // Nor do we go through the station to change lines.
+        let mut paths = ShortestPaths::new(&map, Some((v(0), 0.0)));
+        let reached = paths.by_ref().map(|(v, _)| v).collect::<HashSet<_>>();
+        assert_eq!(reached, [0, 1, 2, 5].iter().map(|&i| v(i)).collect());
+        assert!(paths.distance(v(3)).is_none());
+    }
+
+    #[test]
+    fn finds_the_next_shortest_paths() {
+        // From 0 to 1: straight along the line through 2 and 5, or with a
+        // little detour through 6 from 2 to 5, or out through 3, or
+        // further out through 4.
+        let map = fixtures::network(
+            &[
+                (0.0, 0.0),
+                (0.0, 0.03),
+                (0.0, 0.015),
+                (0.002, 0.015),
+                (0.01, 0.015),
+                (0.0, 0.025),
+                (0.0003, 0.02),
+            ],
+            &[
+                &[0, 2],
+                &[2, 5],
+                &[5, 1],
+                &[2, 6],
+                &[6, 5],
+                &[0, 3],
+                &[3, 1],
+                &[0, 4],
+                &[4, 1],
+            ],
+            &[],
+            &[0, 1],
+        );
+        let v = |i| vertex(&map, i);
+        let routes = |k, min_dissimilarity, max_candidates| {
+            k_shortest_paths(
+                &map,
+                v(0),
+                v(1),
+                &Avoid::default(),
+                k,
+                min_dissimilarity,
+                max_candidates,
+            )
+            .iter()
+            .map(|p| p.steps.iter().map(|s| s.0).collect::<Vec<_>>())
+            .collect::<Vec<_>>()
+        };
+        let straight = vec![v(0), v(2), v(5), v(1)];
+        let detour = vec![v(0), v(2), v(6), v(5), v(1)];
+        let out = vec![v(0), v(3), v(1)];
+        let further = vec![v(0), v(4), v(1)];
+
+        assert_eq!(
+            routes(3, 0.0, 100),
+            vec![straight.clone(), detour, out.clone()]
+        );
+        // The detour shares most of its length with the straight route.
+        assert_eq!(routes(3, 0.5, 100), vec![straight.clone(), out, further]);
+        assert_eq!(routes(3, 0.5, 2), vec![straight]);
+    }
+
+    // The track within `limit` of `sources` (by index), rounded to the
+    // nearest 0.0001 degrees (about 11m).
+    fn covered_from(
+        map: &CompactMap,
+        sources: &[usize],
+        speeds: Option<&Speeds>,
+        limit: f64,
+    ) -> Vec<Vec<[f64; 2]>> {
+        let mut paths = ShortestPaths::new(map, sources.iter().map(|&i| (vertex(map, i), 0.0)));
+        if let Some(speeds) = speeds {
+            paths = paths.by_time(speeds);
+        }
+        let reached = paths.take_while(|&(_, d)| d <= limit).collect::<Vec<_>>();
+        let round = |x: f64| (x * 1e4).round() / 1e4;
+        covered(map, speeds, &reached, limit)
+            .into_iter()
+            .flat_map(|(via, lines)| {
+                assert_eq!(via, OsmId::Way(WayId(1)));
+                lines
+            })
+            .map(|line| line.iter().map(|c| [round(c[0]), round(c[1])]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn cuts_track_short_at_the_limit() {
+        // A line along the equator, with vertices about 1112m apart.
+        let map = fixtures::network(
+            &[(0.0, 0.0), (0.0, 0.01), (0.0, 0.02)],
+            &[&[0, 1, 2]],
+            &[],
+            &[],
+        );
+        let edge = length(&map, vertex(&map, 0), vertex(&map, 1));
+
+        // From one end: all of the first edge, and some of the second.
+        let track = covered_from(&map, &[0], None, edge * 1.5);
+        assert_eq!(
+            track,
+            vec![
+                vec![[0.0, 0.0], [0.01, 0.0]],
+                vec![[0.01, 0.0], [0.015, 0.0]]
+            ]
+        );
+
+        // At 36km/h, it's 10m a second.
+        let speeds = Speeds::new(&map, 36.0);
+        let track = covered_from(&map, &[1], Some(&speeds), edge / 20.0);
+        assert_eq!(
+            track,
+            vec![
+                vec![[0.01, 0.0], [0.005, 0.0]],
+                vec![[0.01, 0.0], [0.015, 0.0]]
+            ]
+        );
+    }
+
+    #[test]
+    fn covers_track_from_both_ends() {
+        let map = fixtures::network(&[(0.0, 0.0), (0.0, 0.01)], &[&[0, 1]], &[], &[]);
+        let edge = length(&map, vertex(&map, 0), vertex(&map, 1));
+
+        // Not quite meeting in the middle.
+        let track = covered_from(&map, &[0, 1], None, edge * 0.4);
+        assert_eq!(
+            track,
+            vec![
+                vec![[0.0, 0.0], [0.004, 0.0]],
+                vec![[0.006, 0.0], [0.01, 0.0]]
+            ]
+        );
+
+        // ...and then meeting, so it's all in one.
+        let track = covered_from(&map, &[0, 1], None, edge * 0.6);
+        assert_eq!(track, vec![vec![[0.0, 0.0], [0.01, 0.0]]]);
+    }
+}
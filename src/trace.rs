@@ -0,0 +1,137 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::{Context, Result};
+use quick_xml::{events::Event, Reader};
+use serde::Deserialize;
+
+use crate::xml::attr;
+
+/// One GPS position from a trace. `time` is passed through as it is, as
+/// we only care about the order of the points.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fix {
+    #[serde(alias = "latitude")]
+    pub lat: f64,
+    #[serde(alias = "longitude", alias = "lng")]
+    pub lon: f64,
+    #[serde(default)]
+    pub time: Option<String>,
+}
+
+/// Reads the points from a GPX file (every `<trkpt>`, in order), or from a
+/// CSV file with `lat`, `lon` and optionally `time` columns.
+pub fn read(path: &Path) -> Result<Vec<Fix>> {
+    let is_gpx = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("gpx"))
+        .unwrap_or(false);
+    if is_gpx {
+        read_gpx(path).with_context(|| format!("read GPX from {:?}", path))
+    } else {
+        let mut rdr = csv::Reader::from_path(path).with_context(|| format!("open {:?}", path))?;
+        rdr.deserialize()
+            .collect::<Result<Vec<Fix>, _>>()
+            .with_context(|| format!("read {:?}", path))
+    }
+}
+
+fn read_gpx(path: &Path) -> Result<Vec<Fix>> {
+    let f = File::open(path).with_context(|| format!("open {:?}", path))?;
+    let mut reader = Reader::from_reader(BufReader::new(f));
+    reader.config_mut().trim_text(true);
+
+    let mut fixes = Vec::new();
+    // Only the `<time>` inside a `<trkpt>` is the time of that point.
+    let mut in_trkpt = false;
+    let mut in_time = false;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let pos = reader.buffer_position();
+        let ev = reader
+            .read_event_into(&mut buf)
+            .with_context(|| format!("parse XML at byte {}", pos))?;
+        let empty = matches!(ev, Event::Empty(_));
+        match ev {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"trkpt" => {
+                fixes.push(Fix {
+                    lat: attr(&e, "lat")?,
+                    lon: attr(&e, "lon")?,
+                    time: None,
+                });
+                in_trkpt = !empty;
+            }
+            Event::End(e) if e.name().as_ref() == b"trkpt" => in_trkpt = false,
+            Event::Start(e) if e.name().as_ref() == b"time" => in_time = in_trkpt,
+            Event::End(e) if e.name().as_ref() == b"time" => in_time = false,
+            Event::Text(t) if in_time => {
+                if let Some(fix) = fixes.last_mut() {
+                    fix.time = Some(t.unescape()?.into_owned());
+                }
+            }
+            Event::Eof => return Ok(fixes),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::fixtures;
+
+    fn read_file(name: &str, data: &str) -> Vec<(f64, f64, Option<String>)> {
+        let path = fixtures::temp_path(name);
+        fs::write(&path, data).unwrap();
+        let fixes = read(&path);
+        fs::remove_file(&path).unwrap();
+        fixes
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.lat, f.lon, f.time))
+            .collect()
+    }
+
+    #[test]
+    fn reads_gpx() {
+        let fixes = read_file(
+            "trace.gpx",
+            r#"<?xml version="1.0"?>
+            <gpx version="1.1">
+              <metadata><time>2024-01-01T00:00:00Z</time></metadata>
+              <trk><trkseg>
+                <trkpt lat="51.5" lon="-0.1"><ele>20</ele><time>10:00:00</time></trkpt>
+                <trkpt lat="51.6" lon="-0.2"/>
+                <trkpt lat="51.7" lon="-0.3"><time>10:00:02</time></trkpt>
+              </trkseg></trk>
+            </gpx>"#,
+        );
+        assert_eq!(
+            fixes,
+            vec![
+                (51.5, -0.1, Some("10:00:00".to_string())),
+                (51.6, -0.2, None),
+                (51.7, -0.3, Some("10:00:02".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_csv() {
+        let fixes = read_file(
+            "trace.csv",
+            "time,latitude,lng,speed\n10:00:00,51.5,-0.1,20\n10:00:01,51.6,-0.2,21\n",
+        );
+        assert_eq!(
+            fixes,
+            vec![
+                (51.5, -0.1, Some("10:00:00".to_string())),
+                (51.6, -0.2, Some("10:00:01".to_string())),
+            ]
+        );
+        let fixes = read_file("trace-no-time.csv", "lat,lon\n51.5,-0.1\n");
+        assert_eq!(fixes, vec![(51.5, -0.1, None)]);
+    }
+}
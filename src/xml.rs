@@ -0,0 +1,347 @@
+use std::{
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::osc::Action;
+
+/// Writes objects out as an OSM XML (`.osm`) file.
+///
+/// We don't have the real element versions, but JOSM refuses to load
+/// elements with positive ids and no version. So we claim everything is at
+/// version 1, and mark the file as `upload="never"` so the resulting extract
+/// can't be accidentally uploaded over the real data.
+pub struct XmlWriter<W: Write> {
+    w: W,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(mut w: W) -> Result<Self> {
+        writeln!(w, "<?xml version='1.0' encoding='UTF-8'?>")?;
+        writeln!(
+            w,
+            "<osm version=\"0.6\" generator=\"osmrail\" upload=\"never\">"
+        )?;
+        Ok(Self { w })
+    }
+
+    pub fn write(&mut self, obj: &OsmObj) -> Result<()> {
+        let w = &mut self.w;
+        match obj {
+            OsmObj::Node(node) => {
+                write!(
+                    w,
+                    "  <node id=\"{}\" version=\"1\" lat=\"{:.7}\" lon=\"{:.7}\"",
+                    node.id.0,
+                    node.lat(),
+                    node.lon()
+                )?;
+                if node.tags.is_empty() {
+                    writeln!(w, "/>")?;
+                } else {
+                    writeln!(w, ">")?;
+                    write_tags(w, &node.tags)?;
+                    writeln!(w, "  </node>")?;
+                }
+            }
+            OsmObj::Way(way) => {
+                writeln!(w, "  <way id=\"{}\" version=\"1\">", way.id.0)?;
+                for node in way.nodes.iter() {
+                    writeln!(w, "    <nd ref=\"{}\"/>", node.0)?;
+                }
+                write_tags(w, &way.tags)?;
+                writeln!(w, "  </way>")?;
+            }
+            OsmObj::Relation(rel) => {
+                writeln!(w, "  <relation id=\"{}\" version=\"1\">", rel.id.0)?;
+                for member in rel.refs.iter() {
+                    let (kind, id) = match member.member {
+                        OsmId::Node(id) => ("node", id.0),
+                        OsmId::Way(id) => ("way", id.0),
+                        OsmId::Relation(id) => ("relation", id.0),
+                    };
+                    writeln!(
+                        w,
+                        "    <member type=\"{}\" ref=\"{}\" role=\"{}\"/>",
+                        kind,
+                        id,
+                        escape(&member.role)
+                    )?;
+                }
+                write_tags(w, &rel.tags)?;
+                writeln!(w, "  </relation>")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        writeln!(self.w, "</osm>")?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+fn write_tags<W: Write>(w: &mut W, tags: &Tags) -> Result<()> {
+    for (k, v) in tags.iter() {
+        writeln!(w, "    <tag k=\"{}\" v=\"{}\"/>", escape(k), escape(v))?;
+    }
+    Ok(())
+}
+
+// Everything we write ends up in a double-quoted attribute.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            '\t' => out.push_str("&#9;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reads objects from an OSM XML (`.osm`) document. Also used for OsmChange
+/// (`.osc`) files, where we keep track of which section we're in.
+pub struct XmlReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    current: Option<OsmObj>,
+    action: Option<Action>,
+}
+
+impl<R: BufRead> XmlReader<R> {
+    pub fn new(r: R) -> Self {
+        let mut reader = Reader::from_reader(r);
+        reader.config_mut().trim_text(true);
+        Self {
+            reader,
+            buf: Vec::new(),
+            current: None,
+            action: None,
+        }
+    }
+
+    /// The OsmChange section (`<create>`, `<modify>` or `<delete>`) that
+    /// the most recently returned object came from, if any.
+    pub fn action(&self) -> Option<Action> {
+        self.action
+    }
+
+    fn next_obj(&mut self) -> Result<Option<OsmObj>> {
+        loop {
+            self.buf.clear();
+            let pos = self.reader.buffer_position();
+            let ev = self
+                .reader
+                .read_event_into(&mut self.buf)
+                .with_context(|| format!("parse XML at byte {}", pos))?;
+            let (e, empty) = match ev {
+                Event::Start(e) => (e, false),
+                Event::Empty(e) => (e, true),
+                Event::End(e) => {
+                    match e.name().as_ref() {
+                        b"node" | b"way" | b"relation" => {
+                            if let Some(obj) = self.current.take() {
+                                return Ok(Some(obj));
+                            }
+                        }
+                        b"create" | b"modify" | b"delete" => self.action = None,
+                        _ => (),
+                    }
+                    continue;
+                }
+                Event::Eof => return Ok(None),
+                _ => continue,
+            };
+            let e = e.into_owned();
+
+            match (e.name().as_ref(), &mut self.current) {
+                (b"create", _) => self.action = Some(Action::Create),
+                (b"modify", _) => self.action = Some(Action::Modify),
+                (b"delete", _) => self.action = Some(Action::Delete),
+                (b"node", _) => {
+                    let node = Node {
+                        id: NodeId(attr(&e, "id")?),
+                        tags: Tags::new(),
+                        decimicro_lat: decimicro(attr_or(&e, "lat", 0.0)?),
+                        decimicro_lon: decimicro(attr_or(&e, "lon", 0.0)?),
+                    };
+                    self.current = Some(node.into());
+                }
+                (b"way", _) => {
+                    let way = Way {
+                        id: WayId(attr(&e, "id")?),
+                        tags: Tags::new(),
+                        nodes: Vec::new(),
+                    };
+                    self.current = Some(way.into());
+                }
+                (b"relation", _) => {
+                    let rel = Relation {
+                        id: RelationId(attr(&e, "id")?),
+                        tags: Tags::new(),
+                        refs: Vec::new(),
+                    };
+                    self.current = Some(rel.into());
+                }
+                (b"tag", Some(obj)) => {
+                    let k = attr::<String>(&e, "k")?;
+                    let v = attr::<String>(&e, "v")?;
+                    tags_mut(obj).insert(k.into(), v.into());
+                }
+                (b"nd", Some(OsmObj::Way(way))) => {
+                    way.nodes.push(NodeId(attr(&e, "ref")?));
+                }
+                (b"member", Some(OsmObj::Relation(rel))) => {
+                    let id = attr(&e, "ref")?;
+                    let member = match &*attr::<String>(&e, "type")? {
+                        "node" => NodeId(id).into(),
+                        "way" => WayId(id).into(),
+                        "relation" => RelationId(id).into(),
+                        other => bail!("Unknown member type {:?} at byte {}", other, pos),
+                    };
+                    let role = attr_or::<String>(&e, "role", Default::default())?;
+                    rel.refs.push(Ref {
+                        member,
+                        role: role.into(),
+                    });
+                }
+                _ => (),
+            }
+
+            if empty {
+                if let b"node" | b"way" | b"relation" = e.name().as_ref() {
+                    return Ok(self.current.take());
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for XmlReader<R> {
+    type Item = Result<OsmObj>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_obj().transpose()
+    }
+}
+
+fn tags_mut(obj: &mut OsmObj) -> &mut Tags {
+    match obj {
+        OsmObj::Node(n) => &mut n.tags,
+        OsmObj::Way(w) => &mut w.tags,
+        OsmObj::Relation(r) => &mut r.tags,
+    }
+}
+
+fn decimicro(deg: f64) -> i32 {
+    (deg * 1e7).round() as i32
+}
+
+pub(crate) fn attr<T>(e: &BytesStart, name: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match e.try_get_attribute(name)? {
+        Some(a) => Ok(a.unescape_value()?.parse()?),
+        None => bail!(
+            "Missing attribute {:?} on <{}>",
+            name,
+            String::from_utf8_lossy(e.name().as_ref())
+        ),
+    }
+}
+
+fn attr_or<T>(e: &BytesStart, name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match e.try_get_attribute(name)? {
+        Some(a) => Ok(a.unescape_value()?.parse()?),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::*;
+    use crate::{fixtures, input::Input};
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let objs = fixtures::objs();
+        let path = fixtures::temp_path("xml-round-trip.osm");
+        let mut w = XmlWriter::new(File::create(&path).unwrap()).unwrap();
+        for obj in &objs {
+            w.write(obj).unwrap();
+        }
+        w.finish().unwrap();
+
+        let input = Input::open(&path).unwrap();
+        let read = input.objs().unwrap().collect::<Result<Vec<_>>>().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, objs);
+    }
+
+    #[test]
+    fn reads_nodes_ways_and_relations() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="test">
+  <bounds minlat="51.5" minlon="-0.13" maxlat="51.51" maxlon="0.13"/>
+  <node id="1" version="3" lat="51.5007292" lon="-0.1246254">
+    <tag k="railway" v="station"/>
+    <tag k="name" v="Fish &amp; &lt;&quot;Chips&quot;&gt; &apos;Ok&apos;"/>
+    <tag k="ref:crs" v="FAC"/>
+  </node>
+  <node id="2" lat="51.501" lon="-0.12"/>
+  <node id="3" lat="51.502" lon="0.0000001"/>
+  <node id="4" lat="51.503" lon="0.12"><tag k="railway" v="buffer_stop"/></node>
+  <node id="5" lat="51.5007" lon="-0.1246">
+    <tag k="public_transport" v="platform"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/>
+    <tag k="railway" v="rail"/>
+    <tag k="maxspeed" v="60 mph"/>
+  </way>
+  <relation id="20">
+    <member type="node" ref="1" role="station"/>
+    <member type="node" ref="5" role="platform"/>
+    <member type="way" ref="10"/>
+    <member type="relation" ref="21" role="sub area"/>
+    <tag k="public_transport" v="stop_area"/>
+    <tag k="name" v="Fish &amp; Chips"/>
+  </relation>
+</osm>
+"#;
+        let read = XmlReader::new(text.as_bytes())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, fixtures::objs());
+    }
+
+    #[test]
+    fn reports_bad_members() {
+        let text = r#"<osm><relation id="1"><member type="area" ref="2"/></relation></osm>"#;
+        assert!(XmlReader::new(text.as_bytes()).next().unwrap().is_err());
+    }
+}